@@ -0,0 +1,299 @@
+use crate::{
+    services::agent::{DirectoryScan, DirectorySelection},
+    uploads::format_size,
+};
+use chrono::Utc;
+use gpui::{
+    Context, Image, ImageFormat, IntoElement, ObjectFit, ParentElement as _, Render, Styled as _,
+    StyledImage as _, Window, div, img, px,
+};
+use gpui_component::{ActiveTheme as _, label::Label, v_flex};
+use std::{
+    io::{BufRead as _, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// Number of lines shown when previewing a text attachment
+const PREVIEW_LINES: usize = 8;
+
+/// Largest file accepted by the Files API
+pub const MAX_ATTACHMENT_BYTES: u64 = 500 * 1024 * 1024;
+
+/// Broad file category, used to pick an icon and preview style
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentKind {
+    Image,
+    Text,
+    Pdf,
+    Directory,
+    Other,
+}
+
+impl AttachmentKind {
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("png" | "jpg" | "jpeg" | "gif" | "webp") => AttachmentKind::Image,
+            Some("txt" | "md" | "json" | "csv") => AttachmentKind::Text,
+            Some("pdf") => AttachmentKind::Pdf,
+            _ => AttachmentKind::Other,
+        }
+    }
+
+    pub fn icon_path(&self) -> &'static str {
+        match self {
+            AttachmentKind::Image => "icons/image.svg",
+            AttachmentKind::Text | AttachmentKind::Pdf => "icons/file-text.svg",
+            AttachmentKind::Directory => "icons/folder.svg",
+            AttachmentKind::Other => "icons/file.svg",
+        }
+    }
+}
+
+/// What will be packed from an attached directory
+#[derive(Debug, Clone)]
+pub struct DirectorySummary {
+    pub selection: DirectorySelection,
+    pub files: Vec<String>,
+    pub estimated_tokens: usize,
+}
+
+/// A file or directory attached to the draft message
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub kind: AttachmentKind,
+    pub directory: Option<DirectorySummary>,
+    /// Why the last attempt to send this attachment failed
+    pub error: Option<String>,
+    /// The selected model can't read this kind of file
+    pub unsupported: bool,
+}
+
+impl Attachment {
+    pub fn from_path(path: PathBuf) -> Self {
+        let size_bytes = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let kind = AttachmentKind::from_path(&path);
+        Self {
+            path,
+            size_bytes,
+            kind,
+            directory: None,
+            error: None,
+            unsupported: false,
+        }
+    }
+
+    /// Attach a scanned directory, packed into the message as text
+    pub fn directory(selection: DirectorySelection, scan: &DirectoryScan) -> Self {
+        Self {
+            path: selection.root.clone(),
+            size_bytes: scan.total_bytes(),
+            kind: AttachmentKind::Directory,
+            directory: Some(DirectorySummary {
+                files: scan.files.iter().map(|f| f.path.clone()).collect(),
+                estimated_tokens: scan.estimated_tokens(),
+                selection,
+            }),
+            error: None,
+            unsupported: false,
+        }
+    }
+
+    /// Check that a path can be sent as an attachment
+    pub fn validate(path: PathBuf) -> Result<Self, String> {
+        let metadata = std::fs::metadata(&path)
+            .map_err(|e| format!("Can't attach {}: {}", path.display(), e))?;
+
+        if !metadata.is_file() {
+            return Err(format!("Can't attach {}: not a file", path.display()));
+        }
+        if metadata.len() > MAX_ATTACHMENT_BYTES {
+            return Err(format!(
+                "Can't attach {}: larger than {}",
+                path.display(),
+                format_size(MAX_ATTACHMENT_BYTES)
+            ));
+        }
+
+        let attachment = Self::from_path(path);
+        if attachment.kind == AttachmentKind::Other {
+            return Err(format!(
+                "Can't attach {}: unsupported file type",
+                attachment.path.display()
+            ));
+        }
+
+        Ok(attachment)
+    }
+
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+
+    /// Short size description shown on the attachment chip
+    pub fn details(&self) -> String {
+        match &self.directory {
+            Some(summary) => format!(
+                "{} files · ~{} tokens",
+                summary.files.len(),
+                summary.estimated_tokens
+            ),
+            None => format_size(self.size_bytes),
+        }
+    }
+}
+
+/// Write a pasted clipboard image to a temp file so it can be attached like any other file
+pub fn save_pasted_image(image: &Image) -> Result<PathBuf, String> {
+    let extension = match image.format {
+        ImageFormat::Png => "png",
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Webp => "webp",
+        ImageFormat::Gif => "gif",
+        _ => return Err("Can't attach pasted image: unsupported image format".to_string()),
+    };
+
+    let dir = pasted_images_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Can't save pasted image: {}", e))?;
+
+    let path = dir.join(format!(
+        "pasted-{}.{}",
+        Utc::now().format("%Y%m%d-%H%M%S%3f"),
+        extension
+    ));
+    std::fs::write(&path, &image.bytes).map_err(|e| format!("Can't save pasted image: {}", e))?;
+
+    Ok(path)
+}
+
+/// Where pasted images are saved until they're sent
+fn pasted_images_dir() -> PathBuf {
+    std::env::temp_dir().join("chatai")
+}
+
+/// Delete a pasted image that's no longer attached. Other files are left alone.
+pub fn remove_pasted_image(path: &Path) {
+    if path.parent() == Some(pasted_images_dir().as_path()) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Delete images pasted in earlier runs
+pub fn clean_pasted_images() {
+    let Ok(entries) = std::fs::read_dir(pasted_images_dir()) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        remove_pasted_image(&entry.path());
+    }
+}
+
+/// Read the first few lines of a text file
+fn preview_text(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .take(PREVIEW_LINES)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    Some(lines.join("\n"))
+}
+
+/// Hover preview for an attachment chip
+pub struct AttachmentPreview {
+    attachment: Attachment,
+}
+
+impl AttachmentPreview {
+    pub fn new(attachment: Attachment) -> Self {
+        Self { attachment }
+    }
+}
+
+impl Render for AttachmentPreview {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let container = div()
+            .p_2()
+            .max_w(px(280.))
+            .border_1()
+            .border_color(cx.theme().border)
+            .rounded_lg()
+            .bg(cx.theme().popover)
+            .shadow_md();
+
+        let container = match &self.attachment.error {
+            Some(error) => container.child(
+                Label::new(error.clone())
+                    .text_xs()
+                    .text_color(cx.theme().danger),
+            ),
+            None => container,
+        };
+
+        match self.attachment.kind {
+            AttachmentKind::Image => container.child(
+                img(self.attachment.path.clone())
+                    .max_w(px(260.))
+                    .max_h(px(180.))
+                    .object_fit(ObjectFit::Contain),
+            ),
+            AttachmentKind::Text => container.child(
+                div()
+                    .text_xs()
+                    .font_family("monospace")
+                    .child(preview_text(&self.attachment.path).unwrap_or_default()),
+            ),
+            AttachmentKind::Directory => container.child(
+                v_flex().text_xs().font_family("monospace").children(
+                    self.attachment
+                        .directory
+                        .iter()
+                        .flat_map(|summary| summary.files.iter().take(PREVIEW_LINES))
+                        .map(|file| div().child(file.clone())),
+                ),
+            ),
+            AttachmentKind::Pdf | AttachmentKind::Other => {
+                container.child(Label::new(self.attachment.path.display().to_string()).text_xs())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_from_extension() {
+        assert_eq!(
+            AttachmentKind::from_path(&PathBuf::from("photo.JPG")),
+            AttachmentKind::Image
+        );
+        assert_eq!(
+            AttachmentKind::from_path(&PathBuf::from("notes.md")),
+            AttachmentKind::Text
+        );
+        assert_eq!(
+            AttachmentKind::from_path(&PathBuf::from("spec.pdf")),
+            AttachmentKind::Pdf
+        );
+        assert_eq!(
+            AttachmentKind::from_path(&PathBuf::from("archive.zip")),
+            AttachmentKind::Other
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_directories() {
+        assert!(Attachment::validate(std::env::temp_dir()).is_err());
+    }
+}
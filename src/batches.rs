@@ -0,0 +1,439 @@
+use crate::services::agent::{
+    AgentRequest, BatchInput, BatchJob, BatchResult, BatchResultStatus, BatchRun, INPUT_PLACEHOLDER,
+};
+use async_channel::Sender;
+use gpui::{
+    AppContext as _, Context, Div, Entity, IntoElement, ParentElement as _, PathPromptOptions,
+    Render, Styled as _, Task, Window, div, prelude::FluentBuilder as _, px,
+};
+use gpui_component::{
+    ActiveTheme as _, Disableable as _, Icon, Sizable as _, StyledExt as _,
+    button::*,
+    h_flex,
+    input::{Input, InputEvent, InputState},
+    label::Label,
+    v_flex,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// How often unfinished batches are checked
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Characters of each output shown in the results table
+const OUTPUT_PREVIEW_LEN: usize = 200;
+
+/// File formats results can be exported to
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// Panel for running a prompt template over many inputs with the Message Batches API
+pub struct BatchPanel {
+    request_tx: Sender<AgentRequest>,
+    template_input: Entity<InputState>,
+    text_inputs: Entity<InputState>,
+    files: Vec<PathBuf>,
+    /// Submitted batches, newest first
+    runs: Vec<BatchRun>,
+    /// Why the last check of a batch failed, by batch ID
+    poll_errors: HashMap<String, String>,
+    is_submitting: bool,
+    /// Outcome of the last export
+    export_status: Option<Result<String, String>>,
+    _poll_task: Task<()>,
+}
+
+impl BatchPanel {
+    pub fn new(
+        request_tx: Sender<AgentRequest>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let template_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(3, 8)
+                .soft_wrap(true)
+                .placeholder(format!(
+                    "Prompt template. {} is replaced by each input.",
+                    INPUT_PLACEHOLDER
+                ))
+        });
+        let text_inputs = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(3, 8)
+                .placeholder("Text inputs, one per line")
+        });
+
+        // Re-render so the submit button reflects the inputs
+        for input in [&template_input, &text_inputs] {
+            cx.subscribe(input, |_: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Change = event {
+                    cx.notify();
+                }
+            })
+            .detach();
+        }
+
+        Self {
+            request_tx,
+            template_input,
+            text_inputs,
+            files: vec![],
+            runs: vec![],
+            poll_errors: HashMap::new(),
+            is_submitting: false,
+            export_status: None,
+            _poll_task: Task::ready(()),
+        }
+    }
+
+    /// Attached files followed by the non-blank text lines
+    fn inputs(&self, cx: &Context<Self>) -> Vec<BatchInput> {
+        let text = self.text_inputs.read(cx).value();
+        self.files
+            .iter()
+            .cloned()
+            .map(BatchInput::File)
+            .chain(
+                text.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(|line| BatchInput::Text(line.to_string())),
+            )
+            .collect()
+    }
+
+    fn submit(&mut self, cx: &mut Context<Self>) {
+        let template = self.template_input.read(cx).value().to_string();
+        let inputs = self.inputs(cx);
+        if template.trim().is_empty() || inputs.is_empty() {
+            return;
+        }
+
+        match self
+            .request_tx
+            .try_send(AgentRequest::SubmitBatch { template, inputs })
+        {
+            Ok(_) => self.set_submitting(true, cx),
+            Err(e) => tracing::error!("Failed to submit batch: {}", e),
+        }
+    }
+
+    pub fn set_submitting(&mut self, submitting: bool, cx: &mut Context<Self>) {
+        self.is_submitting = submitting;
+        cx.notify();
+    }
+
+    fn add_files(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let paths_receiver = cx.prompt_for_paths(PathPromptOptions {
+            files: true,
+            directories: false,
+            multiple: true,
+            prompt: Some("Select files for the batch".into()),
+        });
+
+        cx.spawn_in(window, async move |this, cx| {
+            let Ok(Ok(Some(paths))) = paths_receiver.await else {
+                return;
+            };
+            let _ = this.update(cx, |this, cx| {
+                for path in paths {
+                    if !this.files.contains(&path) {
+                        this.files.push(path);
+                    }
+                }
+                cx.notify();
+            });
+        })
+        .detach();
+    }
+
+    /// Show a newly created batch and clear the inputs for the next one
+    pub fn add_run(&mut self, run: BatchRun, window: &mut Window, cx: &mut Context<Self>) {
+        self.runs.insert(0, run);
+        self.files.clear();
+        self.text_inputs
+            .update(cx, |input, cx| input.set_value("", window, cx));
+        self.set_submitting(false, cx);
+        self.schedule_poll(cx);
+    }
+
+    pub fn update_run(&mut self, job: BatchJob, results: Vec<BatchResult>, cx: &mut Context<Self>) {
+        self.poll_errors.remove(&job.id);
+        if let Some(run) = self.runs.iter_mut().find(|run| run.job.id == job.id) {
+            run.job = job;
+            if !results.is_empty() {
+                run.results = results;
+            }
+        }
+        cx.notify();
+        self.schedule_poll(cx);
+    }
+
+    /// Show why a batch couldn't be checked and try again later
+    pub fn poll_failed(&mut self, batch_id: String, error: String, cx: &mut Context<Self>) {
+        self.poll_errors.insert(batch_id, error);
+        cx.notify();
+        self.schedule_poll(cx);
+    }
+
+    /// Check on unfinished batches after a while.
+    /// Replacing the task restarts the wait.
+    fn schedule_poll(&mut self, cx: &mut Context<Self>) {
+        let pending: Vec<String> = self
+            .runs
+            .iter()
+            .filter(|run| !run.job.is_ended())
+            .map(|run| run.job.id.clone())
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+
+        let request_tx = self.request_tx.clone();
+        self._poll_task = cx.spawn(async move |_, cx| {
+            cx.background_executor().timer(POLL_INTERVAL).await;
+            for batch_id in pending {
+                let _ = request_tx.try_send(AgentRequest::PollBatch(batch_id));
+            }
+        });
+    }
+
+    fn export(
+        &mut self,
+        ix: usize,
+        format: ExportFormat,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some(run) = self.runs.get(ix) else {
+            return;
+        };
+        let contents = match format {
+            ExportFormat::Csv => run.to_csv(),
+            ExportFormat::Jsonl => run.to_jsonl(),
+        };
+        let directory = dirs::download_dir()
+            .or_else(dirs::home_dir)
+            .unwrap_or_else(std::env::temp_dir);
+        let file_name = format!("{}.{}", run.job.id, format.extension());
+        let path_receiver = cx.prompt_for_new_path(&directory, Some(&file_name));
+
+        cx.spawn_in(window, async move |this, cx| {
+            let Ok(Ok(Some(path))) = path_receiver.await else {
+                return;
+            };
+            let result = smol::unblock(move || {
+                std::fs::write(&path, contents)
+                    .map(|_| format!("Saved {}", path.display()))
+                    .map_err(|e| format!("Failed to export results: {}", e))
+            })
+            .await;
+            let _ = this.update(cx, |this, cx| {
+                this.export_status = Some(result);
+                cx.notify();
+            });
+        })
+        .detach();
+    }
+
+    fn render_file(&self, ix: usize, path: &Path, cx: &mut Context<Self>) -> Div {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        h_flex()
+            .gap_1()
+            .pl_2()
+            .rounded_full()
+            .border_1()
+            .border_color(cx.theme().border)
+            .child(Label::new(name).text_sm())
+            .child(
+                Button::new(("remove-batch-file", ix))
+                    .icon(Icon::empty().path("icons/x.svg"))
+                    .xsmall()
+                    .ghost()
+                    .on_click(cx.listener(move |this, _, _, cx| {
+                        if ix < this.files.len() {
+                            this.files.remove(ix);
+                            cx.notify();
+                        }
+                    })),
+            )
+    }
+
+    fn render_run(&self, ix: usize, run: &BatchRun, cx: &mut Context<Self>) -> Div {
+        let counts = run.job.request_counts;
+        let summary = format!(
+            "{} · {} inputs · {} succeeded, {} errored, {} processing",
+            run.job.processing_status.replace('_', " "),
+            run.inputs.len(),
+            counts.succeeded,
+            counts.errored,
+            counts.processing
+        );
+        let muted = cx.theme().muted_foreground;
+
+        let rows: Vec<Div> = run
+            .rows()
+            .into_iter()
+            .map(|row| {
+                let output: String = row.output.chars().take(OUTPUT_PREVIEW_LEN).collect();
+                h_flex()
+                    .items_start()
+                    .gap_2()
+                    .py_1()
+                    .border_t_1()
+                    .border_color(cx.theme().border)
+                    .text_sm()
+                    .child(div().w(px(140.)).child(Label::new(row.input)))
+                    .child(
+                        div().w(px(80.)).child(
+                            Label::new(row.status.as_str())
+                                .when(row.status != BatchResultStatus::Succeeded, |label| {
+                                    label.text_color(cx.theme().danger)
+                                }),
+                        ),
+                    )
+                    .child(div().flex_1().child(Label::new(output)))
+            })
+            .collect();
+
+        div()
+            .p_2()
+            .border_1()
+            .border_color(cx.theme().border)
+            .rounded_lg()
+            .child(
+                h_flex()
+                    .justify_between()
+                    .gap_2()
+                    .child(Label::new(run.job.id.clone()).font_semibold().text_sm())
+                    .when(!run.results.is_empty(), |d| {
+                        d.child(
+                            h_flex()
+                                .gap_1()
+                                .child(
+                                    Button::new(("export-csv", ix))
+                                        .label("CSV")
+                                        .tooltip("Export results as CSV")
+                                        .xsmall()
+                                        .ghost()
+                                        .on_click(cx.listener(move |this, _, window, cx| {
+                                            this.export(ix, ExportFormat::Csv, window, cx);
+                                        })),
+                                )
+                                .child(
+                                    Button::new(("export-jsonl", ix))
+                                        .label("JSONL")
+                                        .tooltip("Export results as JSON Lines")
+                                        .xsmall()
+                                        .ghost()
+                                        .on_click(cx.listener(move |this, _, window, cx| {
+                                            this.export(ix, ExportFormat::Jsonl, window, cx);
+                                        })),
+                                ),
+                        )
+                    }),
+            )
+            .child(Label::new(summary).text_sm().text_color(muted))
+            .when_some(self.poll_errors.get(&run.job.id), |d, error| {
+                d.child(
+                    Label::new(error.clone())
+                        .text_sm()
+                        .text_color(cx.theme().danger),
+                )
+            })
+            .when(!rows.is_empty(), |d| {
+                d.child(
+                    h_flex()
+                        .gap_2()
+                        .pt_2()
+                        .text_sm()
+                        .font_semibold()
+                        .child(div().w(px(140.)).child("Input"))
+                        .child(div().w(px(80.)).child("Status"))
+                        .child(div().flex_1().child("Output")),
+                )
+                .children(rows)
+            })
+    }
+}
+
+impl Render for BatchPanel {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let input_count = self.inputs(cx).len();
+        let can_submit = input_count > 0
+            && !self.template_input.read(cx).value().trim().is_empty()
+            && !self.is_submitting;
+
+        let files: Vec<Div> = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(ix, path)| self.render_file(ix, path, cx))
+            .collect();
+        let runs: Vec<Div> = self
+            .runs
+            .iter()
+            .enumerate()
+            .map(|(ix, run)| self.render_run(ix, run, cx))
+            .collect();
+
+        v_flex()
+            .gap_2()
+            .child(Input::new(&self.template_input))
+            .child(Input::new(&self.text_inputs))
+            .when(!files.is_empty(), |d| {
+                d.child(h_flex().flex_wrap().gap_1().children(files))
+            })
+            .child(
+                h_flex()
+                    .justify_between()
+                    .gap_2()
+                    .child(
+                        Button::new("add-batch-files")
+                            .label("Add files…")
+                            .small()
+                            .ghost()
+                            .on_click(
+                                cx.listener(|this, _, window, cx| this.add_files(window, cx)),
+                            ),
+                    )
+                    .child(
+                        Button::new("submit-batch")
+                            .label(format!("Run on {} inputs", input_count))
+                            .small()
+                            .primary()
+                            .loading(self.is_submitting)
+                            .disabled(!can_submit)
+                            .on_click(cx.listener(|this, _, _, cx| this.submit(cx))),
+                    ),
+            )
+            .when_some(self.export_status.clone(), |d, status| {
+                d.child(match status {
+                    Ok(message) => Label::new(message)
+                        .text_sm()
+                        .text_color(cx.theme().muted_foreground),
+                    Err(error) => Label::new(error).text_sm().text_color(cx.theme().danger),
+                })
+            })
+            .children(runs)
+    }
+}
@@ -1,34 +1,72 @@
 use crate::{
+    attachments::{Attachment, AttachmentPreview, remove_pasted_image, save_pasted_image},
+    batches::BatchPanel,
+    conversation_settings::{ConversationSettings, SettingsEvent},
+    directory_options::DirectoryOptions,
     handler::{handle_incoming, handle_outgoing},
-    services::agent::{AgentRequest, AgentResponse, MessageRole, UiMessage},
+    json_tree::json_tree,
+    models::ModelOption,
+    services::agent::{
+        AgentRequest, AgentResponse, AttachmentFailure, BatchJob, BatchResult, BatchRun,
+        ContextUsage, DEFAULT_MODEL, DirectorySelection, LocalModel, McpServerStatus, MessageRole,
+        ModelCapabilities, ModelInfo, Profile, ProviderKind, ThoughtData, TokenCount, ToolApproval,
+        ToolDecision, ToolInfo, ToolResultData, UiMessage, UploadInfo, capabilities, load_models,
+        load_profiles,
+    },
     theme::change_color_mode,
+    uploads::UploadsPanel,
 };
 use async_channel::{Sender, unbounded};
 use gpui::{
-    AnyElement, App, AppContext as _, ClickEvent, Context, Div, Entity, IntoElement, ListAlignment,
-    ListState, ParentElement as _, PathPromptOptions, Render, SharedString, Styled as _, Window,
-    div, list, prelude::FluentBuilder as _, px,
+    AnyElement, App, AppContext as _, ClickEvent, ClipboardEntry, ClipboardItem, Context, Corner,
+    Div, Entity, ExternalPaths, InteractiveElement as _, IntoElement, ListAlignment, ListState,
+    ParentElement as _, PathPromptOptions, Render, SharedString, Stateful,
+    StatefulInteractiveElement as _, Styled as _, Task, Window, div, list,
+    prelude::FluentBuilder as _, px,
 };
 use gpui_component::{
-    ActiveTheme as _, Icon, IndexPath, Sizable as _, StyledExt as _, ThemeMode, TitleBar,
+    ActiveTheme as _, Icon, IndexPath, Root, Selectable as _, Sizable as _, StyledExt as _,
+    ThemeMode, TitleBar, WindowExt as _,
     alert::Alert,
     button::*,
+    dialog::DialogButtonProps,
     divider::Divider,
     h_flex,
-    input::{Input, InputState},
+    input::{Input, InputEvent, InputState, Paste},
     label::Label,
+    popover::Popover,
     select::{Select, SelectEvent, SelectState},
     text::TextView,
+    v_flex,
 };
-use std::{env, path::PathBuf};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Pause in typing before the draft's tokens are counted
+const TOKEN_COUNT_DELAY: Duration = Duration::from_millis(500);
 
-/// Available LLM models
-pub const AVAILABLE_MODELS: &[(&str, &str)] = &[
-    ("claude-haiku-4-5-20251001", "Claude Haiku 4.5"),
-    ("claude-sonnet-4-5-20250929", "Claude Sonnet 4.5"),
-    ("claude-opus-4-5-20251101", "Claude Opus 4.5"),
-    ("claude-opus-4-1-20250805", "Claude Opus 4.1"),
-];
+/// Lines of tool input or output shown in the chat
+const TOOL_PREVIEW_LINES: usize = 12;
+
+/// A tool call waiting for the user to approve, edit or deny it
+struct PendingApproval {
+    /// The call's input as JSON, editable before approving
+    input: Entity<InputState>,
+    reason: Entity<InputState>,
+    editing: bool,
+    error: Option<String>,
+}
+
+/// The last submitted draft, kept so it can be restored if the turn is aborted
+struct PendingTurn {
+    text: String,
+    attachments: Vec<Attachment>,
+}
 
 pub struct MessageState {
     messages: Vec<UiMessage>,
@@ -38,11 +76,42 @@ pub struct ChatAI {
     text_input: Entity<InputState>,
     message_state: Entity<MessageState>,
     list_state: ListState,
+    /// The assistant message showing reply text as it streams in
+    streamed_message: Option<usize>,
     request_tx: Sender<AgentRequest>,
-    model_select: Entity<SelectState<Vec<SharedString>>>,
-    attached_files: Vec<PathBuf>,
+    approval_tx: Sender<ToolApproval>,
+    /// Tool calls awaiting approval, by tool use ID
+    pending_approvals: HashMap<String, PendingApproval>,
+    model_select: Entity<SelectState<Vec<ModelOption>>>,
+    profile_select: Entity<SelectState<Vec<SharedString>>>,
+    profiles: Vec<Profile>,
+    /// The profile applied to this conversation, re-applied on a new chat
+    active_profile: Option<Profile>,
+    hosted_models: Vec<ModelOption>,
+    local_models: Vec<ModelOption>,
+    /// The model conversations are sent to
+    model: ModelOption,
+    uploads_panel: Entity<UploadsPanel>,
+    batch_panel: Entity<BatchPanel>,
+    settings: Entity<ConversationSettings>,
+    /// Messages whose reasoning is expanded
+    expanded_thoughts: HashSet<usize>,
+    /// Collapsed nodes of structured replies, by message
+    collapsed_json: HashMap<usize, HashSet<String>>,
+    attached_files: Vec<Attachment>,
+    pending_turn: Option<PendingTurn>,
+    /// How full the context window was after the last turn
+    context_usage: Option<ContextUsage>,
+    /// Input tokens the current draft would send
+    token_count: Option<TokenCount>,
+    _count_task: Task<()>,
+    /// New chats the agent hasn't started yet; until it does, replies to the
+    /// cleared chat are dropped
+    clears_pending: usize,
     is_loading: bool,
     has_api_key: bool,
+    /// Whether any model can be used, hosted or local
+    has_provider: bool,
 }
 
 impl ChatAI {
@@ -60,15 +129,17 @@ impl ChatAI {
          */
         let (response_tx, response_rx) = unbounded::<AgentResponse>();
         let (request_tx, request_rx) = unbounded::<AgentRequest>();
+        // Approvals get their own channel, since the agent waits for them mid-turn
+        let (approval_tx, approval_rx) = unbounded::<ToolApproval>();
 
         // Spawn the agent message handler in backgrond
         cx.background_executor()
-            .spawn(handle_outgoing(request_rx, response_tx))
+            .spawn(handle_outgoing(request_rx, approval_rx, response_tx))
             .detach();
 
         // Spawn foreground task to handle incoming responses from agent
         // detaching let's it run to execution
-        cx.spawn(async move |this, cx| {
+        cx.spawn_in(window, async move |this, cx| {
             handle_incoming(this, response_rx, cx).await;
         })
         .detach();
@@ -78,14 +149,38 @@ impl ChatAI {
         // Initialize state with empty messages
         let message_state = cx.new(|_cx| MessageState { messages: vec![] });
 
-        let model_names: Vec<SharedString> = AVAILABLE_MODELS
+        // Start from the cached (or static) model list, refreshed in the background.
+        // Local models are added once discovery finishes.
+        let hosted_models: Vec<ModelOption> =
+            load_models().into_iter().map(ModelOption::hosted).collect();
+        let default_ix = hosted_models
             .iter()
-            .map(|(_, display_name)| SharedString::from(*display_name))
-            .collect();
+            .position(|m| m.id == DEFAULT_MODEL && provider_configured(m.provider))
+            .or_else(|| {
+                hosted_models
+                    .iter()
+                    .position(|m| provider_configured(m.provider))
+            })
+            .unwrap_or(0);
+        let default_model = hosted_models[default_ix].clone();
+        let has_provider = provider_configured(default_model.provider);
+        if has_provider {
+            let _ = request_tx.try_send(AgentRequest::SetModel {
+                model: default_model.id.clone(),
+                provider: default_model.provider,
+            });
+        }
+        let _ = request_tx.try_send(AgentRequest::RefreshModels);
+        let _ = request_tx.try_send(AgentRequest::DiscoverLocalModels);
 
-        // Default to first model
-        let model_select =
-            cx.new(|cx| SelectState::new(model_names, Some(IndexPath::new(0)), window, cx));
+        let model_select = cx.new(|cx| {
+            SelectState::new(
+                hosted_models.clone(),
+                Some(IndexPath::new(default_ix)),
+                window,
+                cx,
+            )
+        });
 
         // When messages are updated, update our list
         cx.observe(&message_state, |this: &mut ChatAI, _event, cx| {
@@ -96,55 +191,630 @@ impl ChatAI {
         .detach();
 
         // Subscribe to model selection changes
-        let request_tx_for_select = request_tx.clone();
         cx.subscribe_in(
             &model_select,
             window,
-            move |_this, _entity, event: &SelectEvent<Vec<SharedString>>, _window, _cx| {
-                if let SelectEvent::Confirm(Some(selected_display_name)) = event {
-                    // Find the model ID from the display name
-                    if let Some((model_id, _)) = AVAILABLE_MODELS
-                        .iter()
-                        .find(|(_, display)| *display == selected_display_name.as_ref())
-                    {
-                        let _ = request_tx_for_select
-                            .try_send(AgentRequest::SetModel(model_id.to_string()));
-                    }
+            |this, _entity, event: &SelectEvent<Vec<ModelOption>>, _window, cx| {
+                if let SelectEvent::Confirm(Some(model)) = event {
+                    this.set_model(model.clone(), cx);
                 }
             },
         )
         .detach();
 
+        let profiles = load_profiles();
+        let profile_select = cx.new(|cx| {
+            SelectState::new(
+                profiles
+                    .iter()
+                    .map(|p| SharedString::from(p.name.clone()))
+                    .collect::<Vec<_>>(),
+                None,
+                window,
+                cx,
+            )
+        });
+        cx.subscribe_in(
+            &profile_select,
+            window,
+            |this, _entity, event: &SelectEvent<Vec<SharedString>>, window, cx| {
+                let SelectEvent::Confirm(name) = event;
+                let profile = name
+                    .as_ref()
+                    .and_then(|name| this.profiles.iter().find(|p| p.name == name.as_ref()))
+                    .cloned();
+                match profile {
+                    Some(profile) => this.apply_profile(profile, window, cx),
+                    None => this.clear_profile(window, cx),
+                }
+            },
+        )
+        .detach();
+
+        let uploads_panel = cx.new(|_cx| UploadsPanel::new(request_tx.clone()));
+        let batch_panel = cx.new(|cx| BatchPanel::new(request_tx.clone(), window, cx));
+        let settings = cx.new(|cx| {
+            ConversationSettings::new(
+                request_tx.clone(),
+                default_model.name.clone(),
+                capabilities(&default_model.id),
+                default_model.provider,
+                window,
+                cx,
+            )
+        });
+        // Pick up the conversation settings from the last launch
+        settings.update(cx, |settings, cx| settings.restore(window, cx));
+        cx.subscribe_in(
+            &settings,
+            window,
+            |this, _entity, event: &SettingsEvent, window, cx| match event {
+                SettingsEvent::SaveProfile(name) => this.save_profile(name.clone(), window, cx),
+                SettingsEvent::ImportProfile => this.import_profile(window, cx),
+            },
+        )
+        .detach();
+
         let text_input = cx.new(|cx| {
             InputState::new(window, cx)
                 .auto_grow(1, 3)
                 .soft_wrap(true)
                 .placeholder("Ask me anything")
         });
+        cx.subscribe(&text_input, |this: &mut Self, _, event: &InputEvent, cx| {
+            if let InputEvent::Change = event {
+                this.schedule_token_count(cx);
+            }
+        })
+        .detach();
 
         Self {
             text_input,
             message_state,
             list_state,
+            streamed_message: None,
             request_tx,
+            approval_tx,
+            pending_approvals: HashMap::new(),
             model_select,
+            profile_select,
+            profiles,
+            active_profile: None,
+            hosted_models,
+            local_models: vec![],
+            model: default_model,
+            uploads_panel,
+            batch_panel,
+            settings,
+            expanded_thoughts: HashSet::new(),
+            collapsed_json: HashMap::new(),
             is_loading: false,
             has_api_key,
+            has_provider,
             attached_files: vec![],
+            pending_turn: None,
+            context_usage: None,
+            token_count: None,
+            _count_task: Task::ready(()),
+            clears_pending: 0,
         }
     }
 
+    fn set_model(&mut self, model: ModelOption, cx: &mut Context<Self>) {
+        let _ = self.request_tx.try_send(AgentRequest::SetModel {
+            model: model.id.clone(),
+            provider: model.provider,
+        });
+        self.model = model;
+        // Switching models starts a new conversation
+        self.context_usage = None;
+        self.schedule_token_count(cx);
+
+        // Flag attachments the new model can't read
+        let capabilities = self.capabilities();
+        let name = self.model.name.clone();
+        let provider = self.model.provider;
+        self.settings.update(cx, |settings, cx| {
+            settings.set_model(name, capabilities, provider, cx)
+        });
+        for attachment in &mut self.attached_files {
+            let reason = capabilities.unsupported_reason(&attachment.path);
+            // Keep errors from a failed send; only replace the previous model's verdict
+            if let Some(reason) = reason {
+                attachment.error = Some(format!("{} {}", self.model.name, reason));
+            } else if attachment.unsupported {
+                attachment.error = None;
+            }
+            attachment.unsupported = reason.is_some();
+        }
+        cx.notify();
+    }
+
+    /// What the selected model supports
+    fn capabilities(&self) -> ModelCapabilities {
+        capabilities(&self.model.id)
+    }
+
+    /// Tooltip for the attach button, noting file types the model can't read
+    fn attach_tooltip(&self) -> String {
+        let capabilities = self.capabilities();
+        let unsupported = match (capabilities.vision, capabilities.pdf) {
+            (true, true) => return "Attach files".to_string(),
+            (false, false) => "images or PDFs",
+            (false, true) => "images",
+            (true, false) => "PDFs",
+        };
+        format!(
+            "Attach files ({} doesn't accept {})",
+            self.model.name, unsupported
+        )
+    }
+
+    /// Refill the model select, keeping the current model selected
+    fn refresh_model_select(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let items: Vec<ModelOption> = self
+            .hosted_models
+            .iter()
+            .chain(&self.local_models)
+            .cloned()
+            .collect();
+        let selected = items
+            .iter()
+            .position(|m| m.id == self.model.id && m.provider == self.model.provider)
+            .map(IndexPath::new);
+        self.model_select.update(cx, |state, cx| {
+            state.set_items(items, window, cx);
+            state.set_selected_index(selected, window, cx);
+        });
+        cx.notify();
+    }
+
+    /// Replace the hosted models with a freshly fetched list
+    pub fn set_hosted_models(
+        &mut self,
+        models: Vec<ModelInfo>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.hosted_models = models.into_iter().map(ModelOption::hosted).collect();
+        self.refresh_model_select(window, cx);
+    }
+
+    /// Add models found on local servers to the model select
+    pub fn add_local_models(
+        &mut self,
+        models: Vec<LocalModel>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        if models.is_empty() {
+            return;
+        }
+        self.local_models = models.into_iter().map(ModelOption::local).collect();
+
+        // Nothing hosted is configured, so switch to a local model
+        if !self.has_provider {
+            self.has_provider = true;
+            self.set_model(self.local_models[0].clone(), cx);
+        }
+        self.refresh_model_select(window, cx);
+    }
+
+    /// Switch to a profile's model and settings and attach its default files
+    fn apply_profile(&mut self, profile: Profile, window: &mut Window, cx: &mut Context<Self>) {
+        if let Some(id) = &profile.model {
+            // Local models are only listed once their server has answered
+            let model = self
+                .hosted_models
+                .iter()
+                .filter(|m| provider_configured(m.provider))
+                .chain(&self.local_models)
+                .find(|m| &m.id == id)
+                .cloned();
+            match model {
+                Some(model) => {
+                    self.set_model(model, cx);
+                    self.refresh_model_select(window, cx);
+                }
+                None => self.add_message(
+                    UiMessage::error(format!(
+                        "Profile {} uses {}, which isn't available",
+                        profile.name, id
+                    )),
+                    cx,
+                ),
+            }
+        }
+
+        self.settings.update(cx, |settings, cx| {
+            settings.apply_profile(&profile, window, cx)
+        });
+        let _ = self
+            .request_tx
+            .try_send(AgentRequest::SetTools(profile.tools.clone()));
+        self.add_attachments(profile.attachments.clone(), window, cx);
+        self.active_profile = Some(profile);
+    }
+
+    /// Go back to the default settings, keeping the current model
+    fn clear_profile(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        self.active_profile = None;
+        self.settings
+            .update(cx, |settings, cx| settings.reset(window, cx));
+        let _ = self.request_tx.try_send(AgentRequest::SetTools(None));
+    }
+
+    /// Reload profiles from disk, selecting the named one
+    fn reload_profiles(&mut self, selected: &str, window: &mut Window, cx: &mut Context<Self>) {
+        self.profiles = load_profiles();
+        let items: Vec<SharedString> = self
+            .profiles
+            .iter()
+            .map(|p| SharedString::from(p.name.clone()))
+            .collect();
+        let selected = self
+            .profiles
+            .iter()
+            .position(|p| p.name == selected)
+            .map(IndexPath::new);
+        self.profile_select.update(cx, |state, cx| {
+            state.set_items(items, window, cx);
+            state.set_selected_index(selected, window, cx);
+        });
+    }
+
+    /// Save the current model, settings and attachments as a profile
+    fn save_profile(&mut self, name: String, window: &mut Window, cx: &mut Context<Self>) {
+        let profile = self
+            .settings
+            .read(cx)
+            .to_profile(name, cx)
+            .map(|profile| Profile {
+                model: Some(self.model.id.clone()),
+                tools: self.active_profile.as_ref().and_then(|p| p.tools.clone()),
+                attachments: self.attached_files.iter().map(|a| a.path.clone()).collect(),
+                ..profile
+            });
+
+        match profile
+            .map_err(anyhow::Error::msg)
+            .and_then(|p| p.save().map(|_| p))
+        {
+            Ok(profile) => {
+                self.reload_profiles(&profile.name, window, cx);
+                self.active_profile = Some(profile);
+            }
+            Err(e) => {
+                tracing::error!("Failed to save profile: {}", e);
+                self.add_message(
+                    UiMessage::error(format!("Failed to save profile: {}", e)),
+                    cx,
+                );
+            }
+        }
+    }
+
+    /// Import a shared profile file and apply it
+    fn import_profile(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let paths_receiver = cx.prompt_for_paths(PathPromptOptions {
+            files: true,
+            directories: false,
+            multiple: false,
+            prompt: Some("Select a profile to import".into()),
+        });
+
+        cx.spawn_in(window, async move |this, cx| {
+            let Ok(Ok(Some(paths))) = paths_receiver.await else {
+                return;
+            };
+            let Some(path) = paths.into_iter().next() else {
+                return;
+            };
+            let result = smol::unblock(move || Profile::import(&path)).await;
+            let _ = this.update_in(cx, |chat, window, cx| match result {
+                Ok((profile, dropped)) => {
+                    if !dropped.is_empty() {
+                        let paths: Vec<String> = dropped
+                            .iter()
+                            .map(|path| format!("- `{}`", path.display()))
+                            .collect();
+                        chat.add_message(
+                            UiMessage::system(format!(
+                                "_Profile {} listed files to attach, which were left out. \
+                                Attach any you want and save the profile to keep them:_\n{}",
+                                profile.name,
+                                paths.join("\n")
+                            )),
+                            cx,
+                        );
+                    }
+                    chat.reload_profiles(&profile.name, window, cx);
+                    chat.apply_profile(profile, window, cx);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to import profile: {}", e);
+                    chat.add_message(
+                        UiMessage::error(format!("Failed to import profile: {}", e)),
+                        cx,
+                    );
+                }
+            });
+        })
+        .detach();
+    }
+
     pub fn add_message(&mut self, message: UiMessage, cx: &mut Context<Self>) {
+        let streamed = self.streamed_message.take();
         cx.update_entity(&self.message_state, |state, cx| {
-            state.messages.push(message);
+            match streamed {
+                // The whole reply replaces the text streamed so far
+                Some(ix) if message.role == MessageRole::Assistant && ix < state.messages.len() => {
+                    state.messages[ix] = message;
+                }
+                _ => state.messages.push(message),
+            }
+            cx.notify();
+        });
+    }
+
+    /// Show reply text as it arrives, in a message of its own until the whole reply comes
+    pub fn append_streamed_text(&mut self, text: &str, cx: &mut Context<Self>) {
+        let streamed = self.streamed_message;
+        let ix = cx.update_entity(&self.message_state, |state, cx| {
+            let ix = match streamed {
+                Some(ix) if ix < state.messages.len() => {
+                    state.messages[ix].content.push_str(text);
+                    ix
+                }
+                _ => {
+                    state.messages.push(UiMessage::assistant(text));
+                    state.messages.len() - 1
+                }
+            };
+            cx.notify();
+            ix
+        });
+        self.streamed_message = Some(ix);
+    }
+
+    /// Index of the message showing a tool call
+    fn tool_call_index(&self, tool_use_id: &str, cx: &App) -> Option<usize> {
+        self.message_state.read(cx).messages.iter().rposition(|m| {
+            m.role == MessageRole::ToolCall
+                && m.metadata.as_ref().and_then(|m| m.tool_use_id.as_deref()) == Some(tool_use_id)
+        })
+    }
+
+    /// Show approval controls on a tool call's card
+    pub fn request_approval(
+        &mut self,
+        tool_use_id: String,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let Some(ix) = self.tool_call_index(&tool_use_id, cx) else {
+            // Nothing to approve it from, so don't leave the agent waiting
+            let _ = self.approval_tx.try_send(ToolApproval {
+                tool_use_id,
+                decision: ToolDecision::Deny {
+                    reason: String::new(),
+                },
+            });
+            return;
+        };
+        let json = self.message_state.read(cx).messages[ix]
+            .metadata
+            .as_ref()
+            .and_then(|m| m.tool_input.as_ref())
+            .map(|input| serde_json::to_string_pretty(input).unwrap_or_default())
+            .unwrap_or_default();
+
+        let input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(3, 16)
+                .default_value(json)
+        });
+        let reason =
+            cx.new(|cx| InputState::new(window, cx).placeholder("Reason for denying (optional)"));
+        self.pending_approvals.insert(
+            tool_use_id,
+            PendingApproval {
+                input,
+                reason,
+                editing: false,
+                error: None,
+            },
+        );
+        self.list_state.splice(ix..ix + 1, 1);
+        cx.notify();
+    }
+
+    fn toggle_tool_input_editing(&mut self, tool_use_id: &str, cx: &mut Context<Self>) {
+        if let Some(approval) = self.pending_approvals.get_mut(tool_use_id) {
+            approval.editing = !approval.editing;
+            approval.error = None;
+        }
+        if let Some(ix) = self.tool_call_index(tool_use_id, cx) {
+            self.list_state.splice(ix..ix + 1, 1);
+        }
+        cx.notify();
+    }
+
+    /// Send the user's decision about a call. Edited input must be valid JSON.
+    fn decide_tool_call(&mut self, tool_use_id: &str, approve: bool, cx: &mut Context<Self>) {
+        let Some(approval) = self.pending_approvals.get_mut(tool_use_id) else {
+            return;
+        };
+        let decision = if !approve {
+            ToolDecision::Deny {
+                reason: approval.reason.read(cx).value().to_string(),
+            }
+        } else if approval.editing {
+            match serde_json::from_str(&approval.input.read(cx).value()) {
+                Ok(input) => ToolDecision::Approve { input: Some(input) },
+                Err(e) => {
+                    approval.error = Some(format!("Input isn't valid JSON: {}", e));
+                    cx.notify();
+                    return;
+                }
+            }
+        } else {
+            ToolDecision::Approve { input: None }
+        };
+
+        self.pending_approvals.remove(tool_use_id);
+        let _ = self.approval_tx.try_send(ToolApproval {
+            tool_use_id: tool_use_id.to_string(),
+            decision,
+        });
+        if let Some(ix) = self.tool_call_index(tool_use_id, cx) {
+            self.list_state.splice(ix..ix + 1, 1);
+        }
+        cx.notify();
+    }
+
+    /// Deny every call awaiting approval, so the agent can finish the turn
+    fn deny_pending_approvals(&mut self, reason: &str, cx: &mut Context<Self>) {
+        let ids: Vec<String> = self.pending_approvals.drain().map(|(id, _)| id).collect();
+        for tool_use_id in ids {
+            if let Some(ix) = self.tool_call_index(&tool_use_id, cx) {
+                self.list_state.splice(ix..ix + 1, 1);
+            }
+            let _ = self.approval_tx.try_send(ToolApproval {
+                tool_use_id,
+                decision: ToolDecision::Deny {
+                    reason: reason.to_string(),
+                },
+            });
+        }
+        cx.notify();
+    }
+
+    /// Show which tools are registered and how they're allowed to run
+    pub fn set_tools(&mut self, tools: Vec<ToolInfo>, cx: &mut Context<Self>) {
+        self.settings
+            .update(cx, |settings, cx| settings.set_tools(tools, cx));
+    }
+
+    /// Show the workspace folder the agent opened
+    pub fn set_workspace(
+        &mut self,
+        root: Option<PathBuf>,
+        error: Option<String>,
+        cx: &mut Context<Self>,
+    ) {
+        self.settings
+            .update(cx, |settings, cx| settings.set_workspace(root, error, cx));
+    }
+
+    pub fn set_mcp_servers(&mut self, servers: Vec<McpServerStatus>, cx: &mut Context<Self>) {
+        self.settings
+            .update(cx, |settings, cx| settings.set_mcp_servers(servers, cx));
+    }
+
+    /// Show tool results, labelled with the tools that produced them
+    pub fn add_tool_results(&mut self, results: Vec<ToolResultData>, cx: &mut Context<Self>) {
+        for result in results {
+            // A call the agent stopped waiting for can't be approved anymore
+            if self.pending_approvals.remove(&result.tool_use_id).is_some()
+                && let Some(ix) = self.tool_call_index(&result.tool_use_id, cx)
+            {
+                self.list_state.splice(ix..ix + 1, 1);
+            }
+            let tool_name = self
+                .message_state
+                .read(cx)
+                .messages
+                .iter()
+                .rev()
+                .filter_map(|m| m.metadata.as_ref())
+                .find(|m| m.tool_use_id.as_ref() == Some(&result.tool_use_id))
+                .and_then(|m| m.tool_name.clone());
+            self.add_message(UiMessage::tool_result(tool_name, result), cx);
+        }
+    }
+
+    pub fn set_context_usage(&mut self, usage: Option<ContextUsage>, cx: &mut Context<Self>) {
+        self.context_usage = usage;
+        cx.notify();
+    }
+
+    /// Count the draft's tokens once typing pauses
+    fn schedule_token_count(&mut self, cx: &mut Context<Self>) {
+        let content = self.text_input.read(cx).value().to_string();
+        if content.trim().is_empty() && self.attached_files.is_empty() {
+            self.token_count = None;
+            self._count_task = Task::ready(());
             cx.notify();
+            return;
+        }
+
+        let (files, directories) = split_attachments(&self.attached_files);
+        let request_tx = self.request_tx.clone();
+        // Replacing the task drops (and cancels) a count that hasn't started yet
+        self._count_task = cx.spawn(async move |_, cx| {
+            cx.background_executor().timer(TOKEN_COUNT_DELAY).await;
+            let _ = request_tx.try_send(AgentRequest::CountTokens {
+                content,
+                files,
+                directories,
+            });
         });
     }
 
+    pub fn set_token_count(&mut self, count: TokenCount, cx: &mut Context<Self>) {
+        // A count can arrive after the draft it measured was sent
+        if self.text_input.read(cx).value().trim().is_empty() && self.attached_files.is_empty() {
+            return;
+        }
+        self.token_count = Some(count);
+        cx.notify();
+    }
+
     pub fn set_loading(&mut self, loading: bool, cx: &mut Context<Self>) {
         self.is_loading = loading;
+        if !loading {
+            // A reply that failed midway keeps the text it streamed
+            self.streamed_message = None;
+        }
         cx.notify();
     }
+
+    pub fn set_uploads(&mut self, uploads: Vec<UploadInfo>, cx: &mut Context<Self>) {
+        self.uploads_panel
+            .update(cx, |panel, cx| panel.set_uploads(uploads, cx));
+    }
+
+    pub fn add_batch(&mut self, run: BatchRun, window: &mut Window, cx: &mut Context<Self>) {
+        self.batch_panel
+            .update(cx, |panel, cx| panel.add_run(run, window, cx));
+    }
+
+    pub fn update_batch(
+        &mut self,
+        job: BatchJob,
+        results: Vec<BatchResult>,
+        cx: &mut Context<Self>,
+    ) {
+        self.batch_panel
+            .update(cx, |panel, cx| panel.update_run(job, results, cx));
+    }
+
+    pub fn batch_poll_failed(&mut self, batch_id: String, error: String, cx: &mut Context<Self>) {
+        self.batch_panel
+            .update(cx, |panel, cx| panel.poll_failed(batch_id, error, cx));
+    }
+
+    pub fn set_batch_submitting(&mut self, submitting: bool, cx: &mut Context<Self>) {
+        self.batch_panel
+            .update(cx, |panel, cx| panel.set_submitting(submitting, cx));
+    }
+
+    pub fn set_uploads_loading(&mut self, loading: bool, cx: &mut Context<Self>) {
+        self.uploads_panel
+            .update(cx, |panel, cx| panel.set_loading(loading, cx));
+    }
+
     fn render_assistant(
         &mut self,
         ix: usize,
@@ -155,7 +825,116 @@ impl ChatAI {
         let id: SharedString = format!("chat-{}", ix).into();
         div()
             .p_2()
-            .child(TextView::markdown(id, item.content, window, cx).selectable(true))
+            .when_some(item.thought.clone(), |d, thought| {
+                d.child(self.render_thought(ix, thought, window, cx))
+            })
+            .map(|d| match item.json {
+                Some(json) => d.child(self.render_json(ix, item.content, &json, cx)),
+                None => d.child(TextView::markdown(id, item.content, window, cx).selectable(true)),
+            })
+    }
+
+    /// A structured reply as a collapsible tree, with a button copying the JSON
+    fn render_json(&self, ix: usize, text: String, json: &Value, cx: &mut Context<Self>) -> Div {
+        let chat = cx.entity().downgrade();
+        let collapsed = self.collapsed_json.get(&ix).cloned().unwrap_or_default();
+
+        v_flex()
+            .gap_1()
+            .p_2()
+            .rounded_lg()
+            .border_1()
+            .border_color(cx.theme().border)
+            .child(
+                h_flex()
+                    .justify_between()
+                    .child(
+                        Label::new("JSON")
+                            .text_xs()
+                            .text_color(cx.theme().muted_foreground),
+                    )
+                    .child(
+                        Button::new(("copy-json", ix))
+                            .icon(Icon::empty().path("icons/copy.svg"))
+                            .tooltip("Copy JSON")
+                            .xsmall()
+                            .ghost()
+                            .on_click(move |_, _, cx| {
+                                cx.write_to_clipboard(ClipboardItem::new_string(text.clone()))
+                            }),
+                    ),
+            )
+            .child(json_tree(
+                format!("json-{}", ix),
+                json,
+                &collapsed,
+                move |path, _, cx| {
+                    let _ = chat.update(cx, |this, cx| this.toggle_json_node(ix, path, cx));
+                },
+                cx,
+            ))
+    }
+
+    fn toggle_json_node(&mut self, ix: usize, path: String, cx: &mut Context<Self>) {
+        let collapsed = self.collapsed_json.entry(ix).or_default();
+        if !collapsed.remove(&path) {
+            collapsed.insert(path);
+        }
+        self.list_state.splice(ix..ix + 1, 1);
+        cx.notify();
+    }
+
+    /// Collapsible reasoning shown above a reply
+    fn render_thought(
+        &mut self,
+        ix: usize,
+        thought: ThoughtData,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Div {
+        let expanded = self.expanded_thoughts.contains(&ix);
+        let muted = cx.theme().muted_foreground;
+        let chevron = if expanded {
+            "icons/chevron-down.svg"
+        } else {
+            "icons/chevron-right.svg"
+        };
+
+        let header = h_flex()
+            .id(SharedString::from(format!("thought-toggle-{}", ix)))
+            .gap_1()
+            .text_sm()
+            .text_color(muted)
+            .cursor_pointer()
+            .child(Icon::empty().path(chevron).xsmall())
+            .child(format!("Thought for {}s", thought.duration_secs))
+            .on_click(cx.listener(move |this, _, _, cx| this.toggle_thought(ix, cx)));
+
+        div().mb_2().child(header).when(expanded, |d| {
+            let id: SharedString = format!("thought-{}", ix).into();
+            d.child(
+                div()
+                    .mt_1()
+                    .pl_3()
+                    .border_l_2()
+                    .border_color(cx.theme().border)
+                    .text_sm()
+                    .text_color(muted)
+                    .child(TextView::markdown(id, thought.text, window, cx).selectable(true))
+                    .when(thought.redacted, |d| {
+                        d.child(Label::new("Some reasoning was redacted for safety").text_xs())
+                    }),
+            )
+        })
+    }
+
+    fn toggle_thought(&mut self, ix: usize, cx: &mut Context<Self>) {
+        if !self.expanded_thoughts.remove(&ix) {
+            self.expanded_thoughts.insert(ix);
+        }
+        // The entry changes height, so it has to be measured again
+        self.list_state.splice(ix..ix + 1, 1);
+        cx.notify();
     }
 
     fn render_user(
@@ -175,6 +954,135 @@ impl ChatAI {
             .child(TextView::markdown(id, item.content, window, cx).selectable(true))
     }
 
+    /// A tool call with the input the model gave it
+    fn render_tool_call(&self, ix: usize, item: UiMessage, cx: &mut Context<Self>) -> Div {
+        let (id, input) = item
+            .metadata
+            .map(|m| (m.tool_use_id.unwrap_or_default(), m.tool_input))
+            .unwrap_or_default();
+        let input = input
+            .map(|input| serde_json::to_string_pretty(&input).unwrap_or_default())
+            .unwrap_or_default();
+        let approval = self.pending_approvals.get(&id);
+
+        h_flex()
+            .items_start()
+            .gap_2()
+            .px_2()
+            .py_1()
+            .rounded_lg()
+            .border_1()
+            .border_color(cx.theme().border)
+            .child(
+                Icon::empty()
+                    .path("icons/wrench.svg")
+                    .xsmall()
+                    .text_color(cx.theme().muted_foreground)
+                    .mt_1(),
+            )
+            .child(
+                v_flex()
+                    .flex_1()
+                    .min_w_0()
+                    .gap_1()
+                    .child(Label::new(item.content).text_sm().font_semibold())
+                    .map(|d| match approval.filter(|a| a.editing) {
+                        Some(approval) => d.child(Input::new(&approval.input).small()),
+                        None => d.child(
+                            div()
+                                .text_xs()
+                                .font_family("monospace")
+                                .text_color(cx.theme().muted_foreground)
+                                .child(preview_lines(&input)),
+                        ),
+                    })
+                    .when_some(approval, |d, approval| {
+                        d.child(self.render_approval(ix, id.clone(), approval, cx))
+                    }),
+            )
+    }
+
+    /// Approve, edit and deny controls for a call awaiting approval
+    fn render_approval(
+        &self,
+        ix: usize,
+        id: String,
+        approval: &PendingApproval,
+        cx: &mut Context<Self>,
+    ) -> Div {
+        v_flex()
+            .gap_1()
+            .child(Input::new(&approval.reason).small())
+            .child(
+                h_flex()
+                    .gap_1()
+                    .child(
+                        Button::new(("approve-tool", ix))
+                            .label("Approve")
+                            .primary()
+                            .xsmall()
+                            .on_click(cx.listener({
+                                let id = id.clone();
+                                move |this, _, _, cx| this.decide_tool_call(&id, true, cx)
+                            })),
+                    )
+                    .child(
+                        Button::new(("deny-tool", ix))
+                            .label("Deny")
+                            .danger()
+                            .xsmall()
+                            .on_click(cx.listener({
+                                let id = id.clone();
+                                move |this, _, _, cx| this.decide_tool_call(&id, false, cx)
+                            })),
+                    )
+                    .child(
+                        Button::new(("edit-tool", ix))
+                            .label("Edit")
+                            .ghost()
+                            .xsmall()
+                            .selected(approval.editing)
+                            .on_click(cx.listener(move |this, _, _, cx| {
+                                this.toggle_tool_input_editing(&id, cx)
+                            })),
+                    ),
+            )
+            .when_some(approval.error.clone(), |d, error| {
+                d.child(Label::new(error).text_xs().text_color(cx.theme().danger))
+            })
+    }
+
+    /// What a tool call returned, shown under the call
+    fn render_tool_result(&self, item: UiMessage, cx: &mut Context<Self>) -> Div {
+        let (tool_name, is_error) = item
+            .metadata
+            .map(|m| (m.tool_name, m.is_error))
+            .unwrap_or_default();
+        let color = if is_error {
+            cx.theme().danger
+        } else {
+            cx.theme().muted_foreground
+        };
+
+        v_flex()
+            .ml_6()
+            .pl_2()
+            .border_l_2()
+            .border_color(cx.theme().border)
+            .text_xs()
+            .text_color(color)
+            .child(format!(
+                "{} {}",
+                tool_name.unwrap_or_else(|| "Tool".to_string()),
+                if is_error { "failed" } else { "returned" }
+            ))
+            .child(
+                div()
+                    .font_family("monospace")
+                    .child(preview_lines(&item.content)),
+            )
+    }
+
     fn render_entry(
         &mut self,
         ix: usize,
@@ -182,13 +1090,13 @@ impl ChatAI {
         cx: &mut Context<Self>,
     ) -> AnyElement {
         let items = self.message_state.read(cx).messages.clone();
-        if items.len() == 0 {
+        if items.is_empty() {
             return div().into_any_element();
         }
         let item = items.get(ix).unwrap().clone();
         let elem = match item.role {
-            MessageRole::ToolCall => div(),
-            MessageRole::ToolResult => div(),
+            MessageRole::ToolCall => self.render_tool_call(ix, item, cx),
+            MessageRole::ToolResult => self.render_tool_result(item, cx),
             MessageRole::Assistant => self.render_assistant(ix, item, window, cx),
             MessageRole::System => self.render_assistant(ix, item, window, cx),
             MessageRole::User => self.render_user(ix, item, window, cx),
@@ -203,13 +1111,50 @@ impl ChatAI {
             return;
         }
 
+        // The agent would refuse these anyway, so keep the draft as is
+        if self.attached_files.iter().any(|a| a.unsupported) {
+            self.add_message(
+                UiMessage::error(format!(
+                    "{} can't read some attachments. Remove them or pick another model.",
+                    self.model.name
+                )),
+                cx,
+            );
+            return;
+        }
+
+        if let Some(count) = self.token_count.filter(TokenCount::blocks_send) {
+            self.add_message(
+                UiMessage::error(format!(
+                    "This message needs {} tokens but {} fits {}. Remove attachments or start \
+                    a new chat.",
+                    count.tokens, self.model.name, count.limit
+                )),
+                cx,
+            );
+            return;
+        }
+
         // Take attached files (clears them from state)
-        let files = std::mem::take(&mut self.attached_files);
+        let attachments = std::mem::take(&mut self.attached_files);
+        // The previous turn went through, so its pasted images aren't needed
+        if let Some(previous) = self.pending_turn.take() {
+            for attachment in &previous.attachments {
+                remove_pasted_image(&attachment.path);
+            }
+        }
+        self.pending_turn = Some(PendingTurn {
+            text: text.clone(),
+            attachments: attachments.clone(),
+        });
+        let (files, directories) = split_attachments(&attachments);
 
         // Send chat request to agent with files
         let result = self.request_tx.try_send(AgentRequest::Chat {
             content: text.clone(),
             files,
+            directories,
+            tool_choice: None,
         });
 
         match result {
@@ -233,6 +1178,67 @@ impl ChatAI {
         cx.notify();
     }
 
+    /// Put an unsent turn back into the input, flagging the attachments that failed
+    pub fn restore_aborted_turn(
+        &mut self,
+        failures: Vec<AttachmentFailure>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        if let Some(pending) = self.pending_turn.take() {
+            // Drop the optimistic user message, it was never sent
+            cx.update_entity(&self.message_state, |state, cx| {
+                if state
+                    .messages
+                    .last()
+                    .is_some_and(|m| m.role == MessageRole::User && m.content == pending.text)
+                {
+                    state.messages.pop();
+                    cx.notify();
+                }
+            });
+
+            if self.text_input.read(cx).value().is_empty() {
+                self.text_input.update(cx, |input, cx| {
+                    input.set_value(pending.text, window, cx);
+                });
+            }
+
+            let mut restored: Vec<Attachment> = pending
+                .attachments
+                .into_iter()
+                .map(|mut attachment| {
+                    attachment.error = failures
+                        .iter()
+                        .find(|f| f.path == attachment.path)
+                        .map(|f| f.error.clone());
+                    attachment
+                })
+                .collect();
+            restored.append(&mut self.attached_files);
+            self.attached_files = restored;
+            self.schedule_token_count(cx);
+        }
+
+        let names: Vec<String> = failures
+            .iter()
+            .map(|f| {
+                f.path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| f.path.display().to_string())
+            })
+            .collect();
+        self.add_message(
+            UiMessage::error(format!(
+                "Message not sent, couldn't attach {}. Retry, or remove the file and send again.",
+                names.join(", ")
+            )),
+            cx,
+        );
+        self.set_loading(false, cx);
+    }
+
     pub fn change_mode(&mut self, _: &ClickEvent, window: &mut Window, cx: &mut Context<Self>) {
         tracing::debug!("Current mode: {:?}", cx.theme().mode);
         let new_mode = if cx.theme().mode.is_dark() {
@@ -243,17 +1249,28 @@ impl ChatAI {
         change_color_mode(new_mode, window, cx);
     }
 
-    pub fn clear_chat(&mut self, _: &ClickEvent, _window: &mut Window, cx: &mut Context<Self>) {
+    pub fn clear_chat(&mut self, _: &ClickEvent, window: &mut Window, cx: &mut Context<Self>) {
+        // The agent finishes the turn before it reads the next request
+        self.deny_pending_approvals("The user started a new chat", cx);
         self.set_loading(true, cx);
         let result = self.request_tx.try_send(AgentRequest::ClearHistory);
 
         match result {
             Ok(_) => {
                 tracing::debug!("Chat cleared successfully");
+                self.clears_pending += 1;
                 cx.update_entity(&self.message_state, |state, cx| {
                     state.messages.clear();
                     cx.notify();
                 });
+                self.expanded_thoughts.clear();
+                self.collapsed_json.clear();
+                self.context_usage = None;
+                self.settings
+                    .update(cx, |settings, cx| settings.reset(window, cx));
+                if let Some(profile) = self.active_profile.clone() {
+                    self.apply_profile(profile, window, cx);
+                }
             }
             Err(e) => {
                 tracing::error!("Failed to clear chat: {}", e);
@@ -265,19 +1282,194 @@ impl ChatAI {
         cx.notify();
     }
 
-    fn attachment_label(&mut self) -> String {
-        match self.attached_files.clone().len() {
-            0 => "Attach file".to_string(),
-            1 => "1 file".to_string(),
-            n => format!("{} files", n),
+    /// Whether a new chat was started that the agent hasn't picked up yet
+    pub fn is_clearing(&self) -> bool {
+        self.clears_pending > 0
+    }
+
+    /// The agent started the new chat, so replies are shown again
+    pub fn history_cleared(&mut self) {
+        self.clears_pending = self.clears_pending.saturating_sub(1);
+    }
+
+    fn open_uploads(&mut self, _: &ClickEvent, window: &mut Window, cx: &mut Context<Self>) {
+        self.uploads_panel.update(cx, |panel, cx| panel.refresh(cx));
+
+        let panel = self.uploads_panel.clone();
+        window.open_sheet(cx, move |sheet, _window, _cx| {
+            sheet.title("Manage uploads").child(panel.clone())
+        });
+    }
+
+    fn open_batches(&mut self, _: &ClickEvent, window: &mut Window, cx: &mut Context<Self>) {
+        let panel = self.batch_panel.clone();
+        window.open_sheet(cx, move |sheet, _window, _cx| {
+            sheet.title("Batch prompts").child(panel.clone())
+        });
+    }
+
+    /// Validate and attach files, reporting any that can't be sent
+    fn add_attachments(
+        &mut self,
+        paths: impl IntoIterator<Item = PathBuf>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        for path in paths {
+            if self.attached_files.iter().any(|a| a.path == path) {
+                continue;
+            }
+            if path.is_dir() {
+                self.open_directory_options(path, window, cx);
+                continue;
+            }
+            match Attachment::validate(path) {
+                Ok(attachment) => {
+                    if let Some(reason) = self.capabilities().unsupported_reason(&attachment.path) {
+                        self.add_message(
+                            UiMessage::error(format!(
+                                "Can't attach {}: {} {}",
+                                attachment.file_name(),
+                                self.model.name,
+                                reason
+                            )),
+                            cx,
+                        );
+                        continue;
+                    }
+                    tracing::debug!("Attached file: {:?}", attachment.path);
+                    self.attached_files.push(attachment);
+                }
+                Err(e) => {
+                    tracing::warn!("{}", e);
+                    self.add_message(UiMessage::error(e), cx);
+                }
+            }
+        }
+        self.schedule_token_count(cx);
+        cx.notify();
+    }
+
+    /// Ask for include/exclude globs before attaching a directory
+    fn open_directory_options(
+        &mut self,
+        root: PathBuf,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let options = cx.new(|cx| DirectoryOptions::new(root, window, cx));
+        let chat = cx.entity().downgrade();
+
+        window.open_dialog(cx, move |dialog, _window, _cx| {
+            let options_for_ok = options.clone();
+            let chat = chat.clone();
+            dialog
+                .title("Attach folder")
+                .child(options.clone())
+                .confirm()
+                .button_props(DialogButtonProps::default().ok_text("Attach"))
+                .on_ok(move |_, _window, cx| {
+                    let Some(attachment) = options_for_ok.read(cx).attachment(cx) else {
+                        return false;
+                    };
+                    let _ = chat.update(cx, |chat, cx| {
+                        tracing::debug!("Attached directory: {:?}", attachment.path);
+                        chat.attached_files.push(attachment);
+                        chat.schedule_token_count(cx);
+                        cx.notify();
+                    });
+                    true
+                })
+        });
+    }
+
+    fn on_drop_paths(
+        &mut self,
+        paths: &ExternalPaths,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.add_attachments(paths.paths().to_vec(), window, cx);
+    }
+
+    /// Attach pasted clipboard images; text pastes fall through to the input
+    fn on_paste(&mut self, _: &Paste, window: &mut Window, cx: &mut Context<Self>) {
+        let Some(item) = cx.read_from_clipboard() else {
+            return;
+        };
+        let Some(image) = item.entries().iter().find_map(|entry| match entry {
+            ClipboardEntry::Image(image) => Some(image),
+            _ => None,
+        }) else {
+            return;
+        };
+
+        match save_pasted_image(image) {
+            Ok(path) => self.add_attachments([path], window, cx),
+            Err(e) => {
+                tracing::error!("{}", e);
+                self.add_message(UiMessage::error(e), cx);
+            }
+        }
+        cx.stop_propagation();
+    }
+
+    fn remove_attachment(&mut self, ix: usize, cx: &mut Context<Self>) {
+        if ix < self.attached_files.len() {
+            let removed = self.attached_files.remove(ix);
+            tracing::debug!("Removed attachment: {:?}", removed.path);
+            remove_pasted_image(&removed.path);
+            self.schedule_token_count(cx);
+            cx.notify();
         }
     }
 
-    fn on_attach_file(&mut self, _: &ClickEvent, _window: &mut Window, cx: &mut Context<Self>) {
-        // Create the path prompt options - allow files, multiple selection
+    fn render_attachment(
+        &mut self,
+        ix: usize,
+        attachment: Attachment,
+        cx: &mut Context<Self>,
+    ) -> Stateful<Div> {
+        let preview = attachment.clone();
+        h_flex()
+            .id(("attachment", ix))
+            .gap_1()
+            .pl_2()
+            .rounded_full()
+            .border_1()
+            .border_color(if attachment.error.is_some() {
+                cx.theme().danger
+            } else {
+                cx.theme().border
+            })
+            .bg(cx.theme().list_even)
+            .child(Icon::empty().path(attachment.kind.icon_path()).xsmall())
+            .child(Label::new(attachment.file_name()).text_xs())
+            .child(
+                Label::new(attachment.details())
+                    .text_xs()
+                    .text_color(cx.theme().muted_foreground),
+            )
+            .child(
+                Button::new(("remove-attachment", ix))
+                    .icon(Icon::empty().path("icons/x.svg"))
+                    .tooltip("Remove")
+                    .xsmall()
+                    .ghost()
+                    .rounded_full()
+                    .on_click(cx.listener(move |this, _, _, cx| this.remove_attachment(ix, cx))),
+            )
+            .tooltip(move |_window, cx| {
+                let preview = preview.clone();
+                cx.new(|_cx| AttachmentPreview::new(preview)).into()
+            })
+    }
+
+    fn on_attach_file(&mut self, _: &ClickEvent, window: &mut Window, cx: &mut Context<Self>) {
+        // Create the path prompt options - allow files and folders, multiple selection
         let options = PathPromptOptions {
             files: true,
-            directories: false,
+            directories: true,
             multiple: true,
             prompt: Some("Select files to attach".into()),
         };
@@ -286,22 +1478,15 @@ impl ChatAI {
         let paths_receiver = cx.prompt_for_paths(options);
 
         // Spawn an async task to handle the response
-        cx.spawn(async move |this, cx| {
+        cx.spawn_in(window, async move |this, cx| {
             // Wait for the user to select paths or cancel
             if let Ok(result) = paths_receiver.await {
                 match result {
                     Ok(Some(paths)) => {
                         // User selected one or more paths
-                        cx.update(|cx| {
-                            let _ = this.update(cx, |chat, cx| {
-                                for path in &paths {
-                                    tracing::debug!("Attached file: {:?}", path);
-                                }
-                                chat.attached_files.extend(paths);
-                                cx.notify();
-                            });
-                        })
-                        .ok();
+                        let _ = this.update_in(cx, |chat, window, cx| {
+                            chat.add_attachments(paths, window, cx);
+                        });
                     }
                     Ok(None) => {
                         // User cancelled the dialog
@@ -318,7 +1503,7 @@ impl ChatAI {
 }
 
 impl Render for ChatAI {
-    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+    fn render(&mut self, window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
         let items_len = self.message_state.read(cx).messages.clone().len();
 
         let theme_toggle = Button::new("theme-mode")
@@ -341,6 +1526,20 @@ impl Render for ChatAI {
             .ghost()
             .on_click(cx.listener(Self::clear_chat));
 
+        let manage_uploads = Button::new("manage-uploads")
+            .icon(Icon::empty().path("icons/folder.svg"))
+            .tooltip("Manage uploads")
+            .small()
+            .ghost()
+            .on_click(cx.listener(Self::open_uploads));
+
+        let batch_prompts = Button::new("batch-prompts")
+            .icon(Icon::empty().path("icons/layers.svg"))
+            .tooltip("Batch prompts")
+            .small()
+            .ghost()
+            .on_click(cx.listener(Self::open_batches));
+
         let header = TitleBar::new().child(
             h_flex()
                 .w_full()
@@ -354,6 +1553,9 @@ impl Render for ChatAI {
                         .flex()
                         .items_center()
                         .when(items_len > 0, |d| d.child(clear_chat))
+                        .when(self.has_api_key, |d| {
+                            d.child(batch_prompts).child(manage_uploads)
+                        })
                         .child(theme_toggle),
                 ),
         );
@@ -365,8 +1567,11 @@ impl Render for ChatAI {
             .justify_end()
             .gap_4()
             .p_4()
-            .when(!self.has_api_key.clone(), |d| {
-                d.child(Alert::error("no-api-key", "No Anthropic API Key Found"))
+            .when(!self.has_provider, |d| {
+                d.child(Alert::error(
+                    "no-api-key",
+                    "No API key or local model server found",
+                ))
             })
             .child(
                 div()
@@ -391,6 +1596,7 @@ impl Render for ChatAI {
 
         let form_header = div()
             .flex()
+            .flex_wrap()
             .gap_1()
             .p_2()
             .justify_start()
@@ -398,12 +1604,22 @@ impl Render for ChatAI {
             .child(
                 Button::new("add-file")
                     .icon(Icon::empty().path("icons/paperclip.svg"))
+                    .tooltip(self.attach_tooltip())
                     .ghost()
                     .mr_1()
                     .on_click(cx.listener(Self::on_attach_file)),
             )
             .child(Divider::vertical())
-            .child(Label::new(self.attachment_label()).pl_2());
+            .when(self.attached_files.is_empty(), |d| {
+                d.child(Label::new("Attach file").pl_2())
+            })
+            .children(
+                self.attached_files
+                    .clone()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(ix, attachment)| self.render_attachment(ix, attachment, cx)),
+            );
 
         let form_footer = div()
             .flex()
@@ -418,16 +1634,77 @@ impl Render for ChatAI {
                     .gap_1()
                     .pl_2()
                     .items_center()
-                    .child(Icon::empty().path("icons/anthropic.svg"))
-                    .child(Select::new(&self.model_select).appearance(false)),
+                    .child(Icon::empty().path(self.model.provider.icon_path()))
+                    .child(Select::new(&self.model_select).appearance(false))
+                    .when(!self.profiles.is_empty(), |d| {
+                        d.child(
+                            Select::new(&self.profile_select)
+                                .appearance(false)
+                                .cleanable(true)
+                                .placeholder("No profile"),
+                        )
+                    })
+                    .child(
+                        Popover::new("conversation-settings")
+                            .anchor(Corner::BottomLeft)
+                            .trigger(
+                                Button::new("settings")
+                                    .icon(Icon::empty().path("icons/sliders-horizontal.svg"))
+                                    .tooltip("Conversation settings")
+                                    .small()
+                                    .ghost(),
+                            )
+                            .content({
+                                let settings = self.settings.clone();
+                                move |_, _, _| settings.clone()
+                            }),
+                    ),
             )
             .child(
-                Button::new("send")
-                    .rounded_full()
-                    .bg(cx.theme().accent)
-                    .loading(self.is_loading.clone())
-                    .icon(Icon::empty().path("icons/move-up.svg"))
-                    .on_click(cx.listener(Self::on_submit)),
+                h_flex()
+                    .gap_2()
+                    .when_some(self.context_usage.and_then(cache_label), |d, label| {
+                        d.child(
+                            Label::new(label)
+                                .text_xs()
+                                .text_color(cx.theme().muted_foreground),
+                        )
+                    })
+                    .when_some(self.token_count, |d, count| {
+                        d.child(Label::new(token_count_label(&count)).text_xs().text_color(
+                            if count.blocks_send() {
+                                cx.theme().danger
+                            } else if count.exceeds_limit() {
+                                // The limit is a guess for this model, so only warn
+                                cx.theme().warning
+                            } else {
+                                cx.theme().muted_foreground
+                            },
+                        ))
+                    })
+                    .when(!self.pending_approvals.is_empty(), |d| {
+                        d.child(
+                            Button::new("deny-all-tools")
+                                .label("Deny all")
+                                .tooltip("Deny every tool call waiting for approval")
+                                .danger()
+                                .small()
+                                .on_click(cx.listener(|this, _, _, cx| {
+                                    this.deny_pending_approvals(
+                                        "The user stopped all pending calls",
+                                        cx,
+                                    )
+                                })),
+                        )
+                    })
+                    .child(
+                        Button::new("send")
+                            .rounded_full()
+                            .bg(cx.theme().accent)
+                            .loading(self.is_loading)
+                            .icon(Icon::empty().path("icons/move-up.svg"))
+                            .on_click(cx.listener(Self::on_submit)),
+                    ),
             );
 
         let form = div()
@@ -438,38 +1715,118 @@ impl Render for ChatAI {
             .border_1()
             .border_color(cx.theme().border.opacity(0.8))
             .bg(cx.theme().popover)
-            .h(px(180.))
+            .min_h(px(180.))
             .shadow_lg()
             .w_full()
             .child(
                 div().flex().flex_col().child(form_header).child(
                     Input::new(&self.text_input.clone())
                         .appearance(false)
-                        .disabled(!self.has_api_key.clone()),
+                        .disabled(!self.has_provider),
                 ),
             )
             .child(form_footer);
 
-        div().v_flex().size_full().child(header).child(
-            div()
-                .p_2()
-                .v_flex()
-                .size_full()
-                .when(items_len == 0, |d| d.child(empty_content))
-                .when(items_len > 0, |d| {
-                    d.child(
-                        div().p_2().size_full().flex().child(
-                            list(
-                                self.list_state.clone(),
-                                cx.processor(|this, ix, window, cx| {
-                                    this.render_entry(ix, window, cx)
-                                }),
+        div()
+            .v_flex()
+            .size_full()
+            .capture_action(cx.listener(Self::on_paste))
+            .on_drop(cx.listener(Self::on_drop_paths))
+            .drag_over::<ExternalPaths>(|style, _, _, cx| style.bg(cx.theme().drop_target))
+            .child(header)
+            .child(
+                div()
+                    .p_2()
+                    .v_flex()
+                    .size_full()
+                    .when(items_len == 0, |d| d.child(empty_content))
+                    .when(items_len > 0, |d| {
+                        d.child(
+                            div().p_2().size_full().flex().child(
+                                list(
+                                    self.list_state.clone(),
+                                    cx.processor(|this, ix, window, cx| {
+                                        this.render_entry(ix, window, cx)
+                                    }),
+                                )
+                                .size_full(),
+                            ),
+                        )
+                    })
+                    .when_some(
+                        self.context_usage.filter(ContextUsage::is_near_limit),
+                        |d, usage| {
+                            d.child(
+                                Label::new(format!(
+                                    "This conversation uses {:.0}% of the context window. \
+                                    Older messages will be summarized.",
+                                    usage.ratio() * 100.0
+                                ))
+                                .text_xs()
+                                .px_2()
+                                .pb_1()
+                                .text_color(cx.theme().warning),
                             )
-                            .size_full(),
-                        ),
+                        },
                     )
-                })
-                .child(form),
+                    .child(form),
+            )
+            .children(Root::render_sheet_layer(window, cx))
+            .children(Root::render_dialog_layer(window, cx))
+    }
+}
+
+/// Split attachments into file paths and directory selections for a request
+fn split_attachments(attachments: &[Attachment]) -> (Vec<PathBuf>, Vec<DirectorySelection>) {
+    let (directories, files): (Vec<_>, Vec<_>) =
+        attachments.iter().partition(|a| a.directory.is_some());
+    (
+        files.into_iter().map(|a| a.path.clone()).collect(),
+        directories
+            .into_iter()
+            .filter_map(|a| a.directory.as_ref())
+            .map(|summary| summary.selection.clone())
+            .collect(),
+    )
+}
+
+/// The first lines of a tool's input or output, noting how many were left out
+fn preview_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= TOOL_PREVIEW_LINES {
+        return text.to_string();
+    }
+    format!(
+        "{}\n… {} more lines",
+        lines[..TOOL_PREVIEW_LINES].join("\n"),
+        lines.len() - TOOL_PREVIEW_LINES
+    )
+}
+
+/// Token count and cost shown next to the send button
+fn token_count_label(count: &TokenCount) -> String {
+    let approx = if count.exact { "" } else { "~" };
+    match count.cost {
+        Some(cost) => format!("{}{} tokens · ${:.4}", approx, count.tokens, cost),
+        None => format!("{}{} tokens", approx, count.tokens),
+    }
+}
+
+/// Prompt cache use of the last request, if it used the cache
+fn cache_label(usage: ContextUsage) -> Option<String> {
+    (usage.cache_read > 0 || usage.cache_write > 0).then(|| {
+        format!(
+            "Cache: {} read · {} written",
+            usage.cache_read, usage.cache_write
         )
+    })
+}
+
+/// Whether credentials for a hosted provider are set
+fn provider_configured(provider: ProviderKind) -> bool {
+    match provider {
+        ProviderKind::Anthropic => env::var("ANTHROPIC_API_KEY").is_ok(),
+        ProviderKind::OpenAi => env::var("OPENAI_API_KEY").is_ok(),
+        ProviderKind::Ollama | ProviderKind::LlamaCpp => false,
     }
 }
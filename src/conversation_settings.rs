@@ -0,0 +1,1008 @@
+use crate::{
+    handler::DEFAULT_SYSTEM_PROMPT,
+    services::agent::{
+        AgentRequest, CommandRules, MIN_THINKING_BUDGET, MIN_THINKING_TOP_P, McpConfig,
+        McpServerState, McpServerStatus, ModelCapabilities, Profile, ProviderKind,
+        RUN_COMMAND_TOOL_NAME, SamplingParams, SavedSchema, ToolChoice, ToolInfo, ToolPermission,
+        load_schemas, parse_schema,
+    },
+};
+use async_channel::Sender;
+use gpui::{
+    App, AppContext as _, Context, Div, Entity, EventEmitter, IntoElement, ParentElement as _,
+    PathPromptOptions, Render, SharedString, Styled as _, Window, prelude::FluentBuilder as _,
+};
+use gpui_component::{
+    ActiveTheme as _, Disableable as _, IndexPath, Selectable as _, Sizable as _, StyledExt as _,
+    button::{Button, ButtonVariants as _},
+    h_flex,
+    input::{Input, InputEvent, InputState},
+    label::Label,
+    select::{Select, SelectEvent, SelectState},
+    slider::{Slider, SliderEvent, SliderState},
+    switch::Switch,
+    v_flex,
+};
+use std::path::PathBuf;
+
+/// Largest budget offered by the slider
+const MAX_THINKING_BUDGET: u32 = 32_000;
+
+/// Budget selected when thinking is first enabled
+const DEFAULT_THINKING_BUDGET: u32 = 4_096;
+
+/// Tool use options, in the order of `tool_choice`
+const TOOL_CHOICES: [&str; 3] = ["Model decides", "Must call a tool", "No tools"];
+
+/// Parse an optional number, treating blank input as unset
+fn parse_field<T: std::str::FromStr>(
+    input: &Entity<InputState>,
+    name: &str,
+    cx: &App,
+) -> Result<Option<T>, String> {
+    let value = input.read(cx).value();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("{} must be a number", name))
+}
+
+/// Profile actions that need the chat's model and attachments
+pub enum SettingsEvent {
+    SaveProfile(String),
+    ImportProfile,
+}
+
+/// Per-conversation model settings, shown in a popover next to the model select
+pub struct ConversationSettings {
+    request_tx: Sender<AgentRequest>,
+    model_name: SharedString,
+    capabilities: ModelCapabilities,
+    /// Which API the model is served by, for its sampling limits
+    provider: ProviderKind,
+    thinking: bool,
+    budget: u32,
+    budget_slider: Entity<SliderState>,
+    system_prompt_input: Entity<InputState>,
+    temperature_input: Entity<InputState>,
+    top_p_input: Entity<InputState>,
+    top_k_input: Entity<InputState>,
+    /// One stop sequence per line
+    stop_input: Entity<InputState>,
+    /// Why the sampling fields weren't applied
+    sampling_error: Option<String>,
+    profile_name_input: Entity<InputState>,
+    /// Reply with JSON matching the schema input
+    structured: bool,
+    schema_input: Entity<InputState>,
+    /// Why the schema wasn't applied
+    schema_error: Option<String>,
+    schemas: Vec<SavedSchema>,
+    schema_select: Entity<SelectState<Vec<SharedString>>>,
+    schema_name_input: Entity<InputState>,
+    /// Index into `TOOL_CHOICES`
+    tool_choice: usize,
+    tool_choice_select: Entity<SelectState<Vec<SharedString>>>,
+    /// Allow several tool calls in one reply
+    parallel_tools: bool,
+    /// Registered tools with their permissions
+    tools: Vec<ToolInfo>,
+    /// Folder the file tools work in
+    workspace: Option<PathBuf>,
+    /// Why the last chosen folder couldn't be used
+    workspace_error: Option<String>,
+    /// Offer the tools that edit files in the workspace
+    allow_writes: bool,
+    /// Offer `run_command` in the workspace
+    allow_commands: bool,
+    /// Command prefixes `run_command` may run, one per line
+    allowed_commands_input: Entity<InputState>,
+    /// Command prefixes `run_command` never runs, one per line
+    denied_commands_input: Entity<InputState>,
+    /// Configured MCP servers and whether they started
+    mcp_servers: Vec<McpServerStatus>,
+}
+
+impl EventEmitter<SettingsEvent> for ConversationSettings {}
+
+impl ConversationSettings {
+    pub fn new(
+        request_tx: Sender<AgentRequest>,
+        model_name: SharedString,
+        capabilities: ModelCapabilities,
+        provider: ProviderKind,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let budget_slider = cx.new(|_| {
+            SliderState::new()
+                .min(MIN_THINKING_BUDGET as f32)
+                .max(MAX_THINKING_BUDGET as f32)
+                .step(MIN_THINKING_BUDGET as f32)
+                .default_value(DEFAULT_THINKING_BUDGET as f32)
+        });
+
+        cx.subscribe(
+            &budget_slider,
+            |this: &mut Self, _, event: &SliderEvent, cx| {
+                let SliderEvent::Change(value) = event;
+                this.budget = value.start() as u32;
+                if this.thinking {
+                    this.send_thinking();
+                    this.save_current(cx);
+                }
+                cx.notify();
+            },
+        )
+        .detach();
+
+        let system_prompt_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(3, 8)
+                .soft_wrap(true)
+                .default_value(DEFAULT_SYSTEM_PROMPT)
+        });
+        cx.subscribe(
+            &system_prompt_input,
+            |this: &mut Self, input, event: &InputEvent, cx| {
+                if let InputEvent::Change = event {
+                    let prompt = input.read(cx).value().to_string();
+                    let _ = this
+                        .request_tx
+                        .try_send(AgentRequest::SetSystemPrompt(prompt));
+                    this.save_current(cx);
+                }
+            },
+        )
+        .detach();
+
+        let temperature_input = cx.new(|cx| InputState::new(window, cx).placeholder("Default"));
+        let top_p_input = cx.new(|cx| InputState::new(window, cx).placeholder("Default (0–1)"));
+        let top_k_input = cx.new(|cx| InputState::new(window, cx).placeholder("Default"));
+        let stop_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(1, 4)
+                .placeholder("One per line")
+        });
+
+        for input in [&temperature_input, &top_p_input, &top_k_input, &stop_input] {
+            cx.subscribe(input, |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Change = event {
+                    this.send_sampling(cx);
+                }
+            })
+            .detach();
+        }
+
+        let profile_name_input =
+            cx.new(|cx| InputState::new(window, cx).placeholder("Profile name"));
+
+        let schema_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(4, 12)
+                .placeholder(r#"{"type": "object", "properties": {…}}"#)
+        });
+        cx.subscribe(
+            &schema_input,
+            |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Change = event {
+                    this.send_schema(cx);
+                }
+            },
+        )
+        .detach();
+
+        let schemas = load_schemas();
+        let schema_select = cx.new(|cx| SelectState::new(schema_names(&schemas), None, window, cx));
+        cx.subscribe_in(
+            &schema_select,
+            window,
+            |this, _, event: &SelectEvent<Vec<SharedString>>, window, cx| {
+                let SelectEvent::Confirm(name) = event;
+                let Some(saved) = name
+                    .as_ref()
+                    .and_then(|name| this.schemas.iter().find(|s| s.name == name.as_ref()))
+                    .cloned()
+                else {
+                    return;
+                };
+                let json = serde_json::to_string_pretty(&saved.schema).unwrap_or_default();
+                // Setting the schema sends it through its change event
+                this.schema_input
+                    .update(cx, |input, cx| input.set_value(json, window, cx));
+                this.schema_name_input
+                    .update(cx, |input, cx| input.set_value(saved.name, window, cx));
+            },
+        )
+        .detach();
+        let schema_name_input = cx.new(|cx| InputState::new(window, cx).placeholder("Schema name"));
+
+        let tool_choice_select = cx.new(|cx| {
+            SelectState::new(
+                TOOL_CHOICES.map(SharedString::from).to_vec(),
+                Some(IndexPath::new(0)),
+                window,
+                cx,
+            )
+        });
+        cx.subscribe(
+            &tool_choice_select,
+            |this: &mut Self, _, event: &SelectEvent<Vec<SharedString>>, cx| {
+                let SelectEvent::Confirm(Some(choice)) = event else {
+                    return;
+                };
+                if let Some(ix) = TOOL_CHOICES.iter().position(|c| *c == choice.as_ref()) {
+                    this.tool_choice = ix;
+                    this.send_tool_choice(cx);
+                }
+            },
+        )
+        .detach();
+
+        let command_rules = CommandRules::load();
+        let allowed_commands_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(1, 8)
+                .placeholder("Any command that isn't blocked")
+                .default_value(command_rules.allowed.join("\n"))
+        });
+        let denied_commands_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .auto_grow(1, 8)
+                .placeholder("One per line")
+                .default_value(command_rules.denied.join("\n"))
+        });
+        // Rules are saved to disk, so only send them once editing is done
+        for input in [&allowed_commands_input, &denied_commands_input] {
+            cx.subscribe(input, |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Blur | InputEvent::PressEnter { .. } = event {
+                    this.send_command_rules(cx);
+                }
+            })
+            .detach();
+        }
+
+        Self {
+            request_tx,
+            model_name,
+            capabilities,
+            provider,
+            thinking: false,
+            budget: DEFAULT_THINKING_BUDGET,
+            budget_slider,
+            system_prompt_input,
+            temperature_input,
+            top_p_input,
+            top_k_input,
+            stop_input,
+            sampling_error: None,
+            profile_name_input,
+            structured: false,
+            schema_input,
+            schema_error: None,
+            schemas,
+            schema_select,
+            schema_name_input,
+            tool_choice: 0,
+            tool_choice_select,
+            parallel_tools: true,
+            tools: Vec::new(),
+            workspace: None,
+            workspace_error: None,
+            allow_writes: false,
+            allow_commands: false,
+            allowed_commands_input,
+            denied_commands_input,
+            mcp_servers: Vec::new(),
+        }
+    }
+
+    fn sampling(&self, cx: &App) -> Result<SamplingParams, String> {
+        let sampling = SamplingParams {
+            temperature: parse_field(&self.temperature_input, "Temperature", cx)?,
+            top_p: parse_field(&self.top_p_input, "Top P", cx)?,
+            top_k: parse_field(&self.top_k_input, "Top K", cx)?,
+            stop_sequences: self
+                .stop_input
+                .read(cx)
+                .value()
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        };
+        sampling
+            .validate(self.thinking_enabled(), Some(self.provider))
+            .map_err(|e| e.to_string())?;
+        Ok(sampling)
+    }
+
+    /// Apply the sampling fields if they're valid, otherwise show why not
+    fn send_sampling(&mut self, cx: &mut Context<Self>) {
+        match self.sampling(cx) {
+            Ok(sampling) => {
+                self.sampling_error = None;
+                let _ = self
+                    .request_tx
+                    .try_send(AgentRequest::SetSampling(sampling));
+                self.save_current(cx);
+            }
+            Err(e) => self.sampling_error = Some(e),
+        }
+        cx.notify();
+    }
+
+    /// Keep the prompt, thinking and sampling settings for the next launch.
+    /// Invalid sampling fields aren't saved, leaving the last valid settings.
+    fn save_current(&self, cx: &App) {
+        if let Ok(profile) = self.to_profile(String::new(), cx) {
+            let _ = profile
+                .save_current()
+                .inspect_err(|e| tracing::warn!("{}", e));
+        }
+    }
+
+    /// Restore the settings saved by the last launch
+    pub fn restore(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        if let Some(profile) = Profile::load_current() {
+            self.apply_profile(&profile, window, cx);
+        }
+    }
+
+    /// Require replies matching the schema if it's valid, otherwise show why not
+    fn send_schema(&mut self, cx: &mut Context<Self>) {
+        self.schema_error = None;
+        let schema = if self.structured {
+            parse_schema(&self.schema_input.read(cx).value())
+                .inspect_err(|e| self.schema_error = Some(e.to_string()))
+                .ok()
+        } else {
+            None
+        };
+        let _ = self
+            .request_tx
+            .try_send(AgentRequest::SetOutputSchema(schema));
+        cx.notify();
+    }
+
+    /// Apply the tool use options. The defaults send `None`, leaving the
+    /// choice to each provider.
+    fn send_tool_choice(&mut self, cx: &mut Context<Self>) {
+        let disable_parallel_tool_use = !self.parallel_tools;
+        let choice = match self.tool_choice {
+            0 if !disable_parallel_tool_use => None,
+            0 => Some(ToolChoice::Auto {
+                disable_parallel_tool_use,
+            }),
+            1 => Some(ToolChoice::Any {
+                disable_parallel_tool_use,
+            }),
+            _ => Some(ToolChoice::None),
+        };
+        let _ = self
+            .request_tx
+            .try_send(AgentRequest::SetToolChoice(choice));
+        cx.notify();
+    }
+
+    pub fn set_tools(&mut self, tools: Vec<ToolInfo>, cx: &mut Context<Self>) {
+        self.tools = tools;
+        cx.notify();
+    }
+
+    pub fn set_mcp_servers(&mut self, servers: Vec<McpServerStatus>, cx: &mut Context<Self>) {
+        self.mcp_servers = servers;
+        cx.notify();
+    }
+
+    /// A server's name with its tool count, or why it isn't running
+    fn render_mcp_server(&self, server: &McpServerStatus, cx: &mut Context<Self>) -> Div {
+        let (status, color) = match &server.state {
+            McpServerState::Running(tools) if !server.skipped_tools.is_empty() => (
+                format!(
+                    "{} tools; skipped clashing names: {}",
+                    tools,
+                    server.skipped_tools.join(", ")
+                ),
+                cx.theme().warning,
+            ),
+            McpServerState::Running(tools) => {
+                (format!("{} tools", tools), cx.theme().muted_foreground)
+            }
+            McpServerState::Failed(error) => (error.clone(), cx.theme().danger),
+            McpServerState::Exited => ("Stopped; reload to restart".to_string(), cx.theme().danger),
+        };
+        h_flex()
+            .justify_between()
+            .gap_2()
+            .child(Label::new(server.name.clone()).font_family("monospace"))
+            .child(Label::new(status).text_color(color))
+    }
+
+    fn set_tool_permission(&mut self, name: String, permission: ToolPermission) {
+        let _ = self
+            .request_tx
+            .try_send(AgentRequest::SetToolPermission { name, permission });
+    }
+
+    /// A tool with buttons choosing whether it runs without asking
+    fn render_tool_permission(&self, tool: &ToolInfo, cx: &mut Context<Self>) -> Div {
+        let row = h_flex()
+            .justify_between()
+            .gap_2()
+            .child(Label::new(tool.name.clone()).font_family("monospace"))
+            .child(h_flex().children(ToolPermission::ALL.map(|permission| {
+                let name = tool.name.clone();
+                Button::new(SharedString::from(format!(
+                    "permission-{}-{}",
+                    tool.name,
+                    permission.label()
+                )))
+                .label(permission.label())
+                .xsmall()
+                .ghost()
+                .selected(tool.permission == permission)
+                .on_click(cx.listener(move |this, _, _, _| {
+                    this.set_tool_permission(name.clone(), permission)
+                }))
+            })));
+
+        // The command lists can't stop an allowed program from starting others
+        let unattended_commands =
+            tool.name == RUN_COMMAND_TOOL_NAME && tool.permission == ToolPermission::Allow;
+        v_flex().child(row).when(unattended_commands, |d| {
+            d.child(
+                Label::new(
+                    "Commands will run without asking. The command lists don't sandbox them.",
+                )
+                .text_color(cx.theme().warning),
+            )
+        })
+    }
+
+    fn send_workspace(&self) {
+        self.send_workspace_root(self.workspace.clone());
+    }
+
+    /// Ask the agent to use a folder. It's only shown once the agent opens it.
+    fn send_workspace_root(&self, root: Option<PathBuf>) {
+        let _ = self.request_tx.try_send(AgentRequest::SetWorkspace {
+            root,
+            allow_writes: self.allow_writes,
+            allow_commands: self.allow_commands,
+        });
+    }
+
+    pub fn set_workspace(
+        &mut self,
+        root: Option<PathBuf>,
+        error: Option<String>,
+        cx: &mut Context<Self>,
+    ) {
+        self.workspace = root;
+        self.workspace_error = error;
+        cx.notify();
+    }
+
+    fn send_command_rules(&self, cx: &App) {
+        let lines = |input: &Entity<InputState>| -> Vec<String> {
+            input
+                .read(cx)
+                .value()
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let _ = self
+            .request_tx
+            .try_send(AgentRequest::SetCommandRules(CommandRules {
+                allowed: lines(&self.allowed_commands_input),
+                denied: lines(&self.denied_commands_input),
+            }));
+    }
+
+    fn toggle_allow_commands(&mut self, checked: &bool, _: &mut Window, cx: &mut Context<Self>) {
+        self.allow_commands = *checked;
+        self.send_workspace();
+        cx.notify();
+    }
+
+    /// Ask for a folder and offer the file tools in it
+    fn choose_workspace(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let paths_receiver = cx.prompt_for_paths(PathPromptOptions {
+            files: false,
+            directories: true,
+            multiple: false,
+            prompt: Some("Select a workspace folder".into()),
+        });
+
+        cx.spawn_in(window, async move |this, cx| {
+            let Ok(Ok(Some(paths))) = paths_receiver.await else {
+                return;
+            };
+            let _ = this.update(cx, |settings, _| {
+                settings.send_workspace_root(paths.into_iter().next());
+            });
+        })
+        .detach();
+    }
+
+    fn clear_workspace(&mut self, cx: &mut Context<Self>) {
+        self.workspace = None;
+        self.send_workspace();
+        cx.notify();
+    }
+
+    fn toggle_allow_writes(&mut self, checked: &bool, _: &mut Window, cx: &mut Context<Self>) {
+        self.allow_writes = *checked;
+        self.send_workspace();
+        cx.notify();
+    }
+
+    fn toggle_parallel_tools(&mut self, checked: &bool, _: &mut Window, cx: &mut Context<Self>) {
+        self.parallel_tools = *checked;
+        self.send_tool_choice(cx);
+    }
+
+    fn toggle_structured(&mut self, checked: &bool, _: &mut Window, cx: &mut Context<Self>) {
+        self.structured = *checked;
+        self.send_schema(cx);
+    }
+
+    /// Save the schema under the entered name and select it
+    fn save_schema(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let name = self.schema_name_input.read(cx).value().trim().to_string();
+        let result = parse_schema(&self.schema_input.read(cx).value()).and_then(|schema| {
+            SavedSchema {
+                name: name.clone(),
+                schema,
+            }
+            .save()
+        });
+        if let Err(e) = result {
+            self.schema_error = Some(e.to_string());
+            cx.notify();
+            return;
+        }
+
+        self.schemas = load_schemas();
+        let items = schema_names(&self.schemas);
+        let selected = self
+            .schemas
+            .iter()
+            .position(|s| s.name == name)
+            .map(IndexPath::new);
+        self.schema_select.update(cx, |state, cx| {
+            state.set_items(items, window, cx);
+            state.set_selected_index(selected, window, cx);
+        });
+        cx.notify();
+    }
+
+    /// Whether thinking is on and the current model supports it
+    fn thinking_enabled(&self) -> bool {
+        self.thinking && self.capabilities.thinking
+    }
+
+    fn send_thinking(&self) {
+        let budget = self.thinking_enabled().then_some(self.budget);
+        let _ = self.request_tx.try_send(AgentRequest::SetThinking(budget));
+    }
+
+    /// Update for a newly selected model
+    pub fn set_model(
+        &mut self,
+        model_name: SharedString,
+        capabilities: ModelCapabilities,
+        provider: ProviderKind,
+        cx: &mut Context<Self>,
+    ) {
+        self.model_name = model_name;
+        self.capabilities = capabilities;
+        self.provider = provider;
+        // Thinking may have become available or unavailable
+        self.send_sampling(cx);
+    }
+
+    /// Load a profile's prompt, thinking and sampling settings
+    pub fn apply_profile(
+        &mut self,
+        profile: &Profile,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.thinking = profile.thinking_budget.is_some();
+        self.budget = profile.thinking_budget.unwrap_or(DEFAULT_THINKING_BUDGET);
+        let budget = self.budget as f32;
+        self.budget_slider
+            .update(cx, |slider, cx| slider.set_value(budget, window, cx));
+        self.send_thinking();
+
+        // Setting the inputs sends the prompt and sampling through their change events
+        let sampling = &profile.sampling;
+        let format = |value: Option<String>| value.unwrap_or_default();
+        for (input, value) in [
+            (&self.system_prompt_input, profile.system_prompt.clone()),
+            (
+                &self.temperature_input,
+                format(sampling.temperature.map(|v| v.to_string())),
+            ),
+            (
+                &self.top_p_input,
+                format(sampling.top_p.map(|v| v.to_string())),
+            ),
+            (
+                &self.top_k_input,
+                format(sampling.top_k.map(|v| v.to_string())),
+            ),
+            (&self.stop_input, sampling.stop_sequences.join("\n")),
+            (&self.profile_name_input, profile.name.clone()),
+        ] {
+            input.update(cx, |input, cx| input.set_value(value, window, cx));
+        }
+        cx.notify();
+    }
+
+    /// The current prompt, thinking and sampling settings as a profile
+    pub fn to_profile(&self, name: String, cx: &App) -> Result<Profile, String> {
+        Ok(Profile {
+            name,
+            system_prompt: self.system_prompt_input.read(cx).value().to_string(),
+            model: None,
+            thinking_budget: self.thinking_enabled().then_some(self.budget),
+            sampling: self.sampling(cx)?,
+            tools: None,
+            attachments: vec![],
+        })
+    }
+
+    /// Back to defaults for a new conversation
+    pub fn reset(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        self.thinking = false;
+        self.budget = DEFAULT_THINKING_BUDGET;
+        self.budget_slider.update(cx, |slider, cx| {
+            slider.set_value(DEFAULT_THINKING_BUDGET as f32, window, cx)
+        });
+        self.system_prompt_input.update(cx, |input, cx| {
+            input.set_value(DEFAULT_SYSTEM_PROMPT, window, cx)
+        });
+        for input in [
+            &self.temperature_input,
+            &self.top_p_input,
+            &self.top_k_input,
+            &self.stop_input,
+            &self.profile_name_input,
+            &self.schema_input,
+            &self.schema_name_input,
+        ] {
+            input.update(cx, |input, cx| input.set_value("", window, cx));
+        }
+        self.sampling_error = None;
+        self.structured = false;
+        self.schema_error = None;
+        self.tool_choice = 0;
+        self.parallel_tools = true;
+        self.tool_choice_select.update(cx, |state, cx| {
+            state.set_selected_index(Some(IndexPath::new(0)), window, cx)
+        });
+        self.save_current(cx);
+        cx.notify();
+    }
+
+    fn toggle_thinking(&mut self, checked: &bool, window: &mut Window, cx: &mut Context<Self>) {
+        self.thinking = *checked;
+        if self.thinking_enabled() {
+            // The API rejects these with thinking, so they'd fail every request
+            let low_top_p = parse_field::<f32>(&self.top_p_input, "Top P", cx)
+                .is_ok_and(|top_p| top_p.is_some_and(|top_p| top_p < MIN_THINKING_TOP_P));
+            let mut conflicting = vec![&self.temperature_input, &self.top_k_input];
+            if low_top_p {
+                conflicting.push(&self.top_p_input);
+            }
+            for input in conflicting {
+                input.update(cx, |input, cx| input.set_value("", window, cx));
+            }
+        }
+        self.send_thinking();
+        // Thinking restricts which sampling overrides are allowed
+        self.send_sampling(cx);
+    }
+}
+
+impl Render for ConversationSettings {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let muted = cx.theme().muted_foreground;
+        let supported = self.capabilities.thinking;
+
+        v_flex()
+            .gap_3()
+            .w_80()
+            .child(
+                Label::new("Conversation settings")
+                    .text_sm()
+                    .font_semibold(),
+            )
+            .child(field("System prompt", &self.system_prompt_input).text_xs())
+            .child(
+                Switch::new("thinking")
+                    .label("Extended thinking")
+                    .checked(self.thinking_enabled())
+                    .disabled(!supported)
+                    .on_click(cx.listener(Self::toggle_thinking)),
+            )
+            .when(!supported, |d| {
+                d.child(
+                    Label::new(format!(
+                        "{} doesn't support extended thinking",
+                        self.model_name
+                    ))
+                    .text_xs()
+                    .text_color(muted),
+                )
+            })
+            .when(self.thinking_enabled(), |d| {
+                d.child(
+                    v_flex()
+                        .gap_1()
+                        .child(
+                            h_flex()
+                                .justify_between()
+                                .text_xs()
+                                .child(Label::new("Thinking budget"))
+                                .child(
+                                    Label::new(format!("{} tokens", self.budget)).text_color(muted),
+                                ),
+                        )
+                        .child(Slider::new(&self.budget_slider)),
+                )
+            })
+            .child(
+                v_flex()
+                    .gap_1()
+                    .text_xs()
+                    .child(Label::new("Sampling").text_sm().font_semibold())
+                    .child(field_when(
+                        "Temperature",
+                        &self.temperature_input,
+                        !self.thinking_enabled(),
+                    ))
+                    .child(field("Top P", &self.top_p_input))
+                    .child(field_when(
+                        "Top K",
+                        &self.top_k_input,
+                        !self.thinking_enabled(),
+                    ))
+                    .when(self.thinking_enabled(), |d| {
+                        d.child(
+                            Label::new("Temperature and Top K are off while thinking is on")
+                                .text_color(muted),
+                        )
+                    })
+                    .child(field("Stop sequences", &self.stop_input))
+                    .when_some(self.sampling_error.clone(), |d, error| {
+                        d.child(Label::new(error).text_color(cx.theme().danger))
+                    }),
+            )
+            .child(
+                v_flex()
+                    .gap_1()
+                    .text_xs()
+                    .child(Label::new("Workspace").text_sm().font_semibold())
+                    .child(
+                        h_flex()
+                            .gap_1()
+                            .child(
+                                Button::new("choose-workspace")
+                                    .label(if self.workspace.is_some() {
+                                        "Change folder…"
+                                    } else {
+                                        "Choose folder…"
+                                    })
+                                    .xsmall()
+                                    .on_click(cx.listener(|this, _, window, cx| {
+                                        this.choose_workspace(window, cx)
+                                    })),
+                            )
+                            .when(self.workspace.is_some(), |d| {
+                                d.child(
+                                    Button::new("clear-workspace")
+                                        .label("Remove")
+                                        .xsmall()
+                                        .ghost()
+                                        .on_click(
+                                            cx.listener(|this, _, _, cx| this.clear_workspace(cx)),
+                                        ),
+                                )
+                            }),
+                    )
+                    .child(
+                        Label::new(match &self.workspace {
+                            Some(path) => path.display().to_string(),
+                            None => "File tools are off until you choose a folder".to_string(),
+                        })
+                        .text_color(muted),
+                    )
+                    .when_some(self.workspace_error.clone(), |d, error| {
+                        d.child(Label::new(error).text_color(cx.theme().danger))
+                    })
+                    .when(self.workspace.is_some(), |d| {
+                        d.child(
+                            Switch::new("allow-writes")
+                                .label("Allow file edits")
+                                .checked(self.allow_writes)
+                                .on_click(cx.listener(Self::toggle_allow_writes)),
+                        )
+                        .child(
+                            Switch::new("allow-commands")
+                                .label("Allow commands")
+                                .checked(self.allow_commands)
+                                .on_click(cx.listener(Self::toggle_allow_commands)),
+                        )
+                    })
+                    .when(self.workspace.is_some() && self.allow_commands, |d| {
+                        d.child(field("Allowed commands", &self.allowed_commands_input))
+                            .child(field("Blocked commands", &self.denied_commands_input))
+                            .child(
+                                Label::new(
+                                    "These lists are advisory: an allowed program can still \
+                                     start others. Approving each call is what keeps \
+                                     commands in check.",
+                                )
+                                .text_color(muted),
+                            )
+                    }),
+            )
+            .child(
+                v_flex()
+                    .gap_1()
+                    .text_xs()
+                    .child(
+                        h_flex()
+                            .justify_between()
+                            .child(Label::new("MCP servers").text_sm().font_semibold())
+                            .child(
+                                Button::new("reload-mcp-servers")
+                                    .label("Reload")
+                                    .xsmall()
+                                    .ghost()
+                                    .on_click(cx.listener(|this, _, _, _| {
+                                        let _ = this
+                                            .request_tx
+                                            .try_send(AgentRequest::ReloadMcpServers);
+                                    })),
+                            ),
+                    )
+                    .when(self.mcp_servers.is_empty(), |d| {
+                        d.child(
+                            Label::new(format!("Add servers to {}", McpConfig::path().display()))
+                                .text_color(muted),
+                        )
+                    })
+                    .children(
+                        self.mcp_servers
+                            .iter()
+                            .map(|server| self.render_mcp_server(server, cx)),
+                    ),
+            )
+            .child(
+                v_flex()
+                    .gap_1()
+                    .text_xs()
+                    .child(Label::new("Tool use").text_sm().font_semibold())
+                    .child(Select::new(&self.tool_choice_select).small())
+                    .when(self.tool_choice != 2, |d| {
+                        d.child(
+                            Switch::new("parallel-tools")
+                                .label("Allow parallel tool calls")
+                                .checked(self.parallel_tools)
+                                .on_click(cx.listener(Self::toggle_parallel_tools)),
+                        )
+                    })
+                    .when(!self.tools.is_empty(), |d| {
+                        d.child(Label::new("Permissions").text_color(muted))
+                            .children(
+                                self.tools
+                                    .iter()
+                                    .map(|tool| self.render_tool_permission(tool, cx)),
+                            )
+                    }),
+            )
+            .child(
+                v_flex()
+                    .gap_1()
+                    .text_xs()
+                    .child(Label::new("Structured output").text_sm().font_semibold())
+                    .child(
+                        Switch::new("structured")
+                            .label("Reply with JSON")
+                            .checked(self.structured)
+                            .on_click(cx.listener(Self::toggle_structured)),
+                    )
+                    .when(self.structured, |d| {
+                        d.when(!self.schemas.is_empty(), |d| {
+                            d.child(
+                                Select::new(&self.schema_select)
+                                    .small()
+                                    .placeholder("Saved schemas"),
+                            )
+                        })
+                        .child(field("JSON Schema", &self.schema_input))
+                        .child(
+                            h_flex()
+                                .gap_1()
+                                .child(Input::new(&self.schema_name_input).small())
+                                .child(Button::new("save-schema").label("Save").small().on_click(
+                                    cx.listener(|this, _, window, cx| this.save_schema(window, cx)),
+                                )),
+                        )
+                    })
+                    .when(
+                        self.structured && self.provider == ProviderKind::Ollama,
+                        |d| {
+                            // Ollama has no `tool_choice`, so the reply tool is only offered
+                            d.child(
+                                Label::new(
+                                    "Ollama can't be made to use the reply tool, so the model \
+                                     may answer in plain text instead of JSON.",
+                                )
+                                .text_color(cx.theme().warning),
+                            )
+                        },
+                    )
+                    .when_some(self.schema_error.clone(), |d, error| {
+                        d.child(Label::new(error).text_color(cx.theme().danger))
+                    }),
+            )
+            .child(
+                v_flex()
+                    .gap_1()
+                    .child(Label::new("Profile").text_sm().font_semibold())
+                    .child(
+                        h_flex()
+                            .gap_1()
+                            .child(Input::new(&self.profile_name_input).small())
+                            .child(Button::new("save-profile").label("Save").small().on_click(
+                                cx.listener(|this, _, _, cx| {
+                                    let name = this.profile_name_input.read(cx).value();
+                                    cx.emit(SettingsEvent::SaveProfile(name.trim().to_string()));
+                                }),
+                            ))
+                            .child(
+                                Button::new("import-profile")
+                                    .label("Import…")
+                                    .small()
+                                    .ghost()
+                                    .on_click(cx.listener(|_, _, _, cx| {
+                                        cx.emit(SettingsEvent::ImportProfile)
+                                    })),
+                            ),
+                    ),
+            )
+    }
+}
+
+fn schema_names(schemas: &[SavedSchema]) -> Vec<SharedString> {
+    schemas
+        .iter()
+        .map(|s| SharedString::from(s.name.clone()))
+        .collect()
+}
+
+/// A labelled settings input
+fn field(label: &'static str, input: &Entity<InputState>) -> Div {
+    field_when(label, input, true)
+}
+
+/// A labeled input that can only be edited when `enabled`
+fn field_when(label: &'static str, input: &Entity<InputState>, enabled: bool) -> Div {
+    v_flex()
+        .gap_1()
+        .child(Label::new(label))
+        .child(Input::new(input).small().disabled(!enabled))
+}
@@ -0,0 +1,155 @@
+use crate::{
+    attachments::Attachment,
+    services::agent::{DirectoryScan, DirectorySelection, SkipReason, scan_directory},
+};
+use gpui::{
+    App, AppContext as _, Context, Entity, IntoElement, ParentElement as _, Render, Styled as _,
+    Task, Window, div, prelude::FluentBuilder as _,
+};
+use gpui_component::{
+    ActiveTheme as _, StyledExt as _,
+    input::{Input, InputEvent, InputState},
+    label::Label,
+    v_flex,
+};
+use std::path::PathBuf;
+
+/// Number of files listed in the summary before collapsing the rest
+const SUMMARY_FILES: usize = 10;
+
+/// Split a comma or whitespace separated list of globs
+fn parse_globs(text: &str) -> Vec<String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|glob| !glob.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Include/exclude filters and a live summary for a directory being attached
+pub struct DirectoryOptions {
+    root: PathBuf,
+    include_input: Entity<InputState>,
+    exclude_input: Entity<InputState>,
+    scan: Option<Result<DirectoryScan, String>>,
+    _scan_task: Task<()>,
+}
+
+impl DirectoryOptions {
+    pub fn new(root: PathBuf, window: &mut Window, cx: &mut Context<Self>) -> Self {
+        let include_input =
+            cx.new(|cx| InputState::new(window, cx).placeholder("Include globs, e.g. src/**/*.rs"));
+        let exclude_input =
+            cx.new(|cx| InputState::new(window, cx).placeholder("Exclude globs, e.g. *.lock"));
+
+        for input in [&include_input, &exclude_input] {
+            cx.subscribe(input, |this: &mut Self, _, event: &InputEvent, cx| {
+                if let InputEvent::Change = event {
+                    this.rescan(cx);
+                }
+            })
+            .detach();
+        }
+
+        let mut this = Self {
+            root,
+            include_input,
+            exclude_input,
+            scan: None,
+            _scan_task: Task::ready(()),
+        };
+        this.rescan(cx);
+        this
+    }
+
+    pub fn selection(&self, cx: &App) -> DirectorySelection {
+        DirectorySelection {
+            root: self.root.clone(),
+            include: parse_globs(&self.include_input.read(cx).value()),
+            exclude: parse_globs(&self.exclude_input.read(cx).value()),
+        }
+    }
+
+    /// The attachment to add, once the scan has finished successfully
+    pub fn attachment(&self, cx: &App) -> Option<Attachment> {
+        match &self.scan {
+            Some(Ok(scan)) if !scan.files.is_empty() => {
+                Some(Attachment::directory(self.selection(cx), scan))
+            }
+            _ => None,
+        }
+    }
+
+    fn rescan(&mut self, cx: &mut Context<Self>) {
+        let selection = self.selection(cx);
+        self.scan = None;
+        cx.notify();
+
+        // Replacing the task drops (and cancels) any scan still in flight
+        self._scan_task = cx.spawn(async move |this, cx| {
+            let result = smol::unblock(move || scan_directory(&selection))
+                .await
+                .map_err(|e| e.to_string());
+            let _ = this.update(cx, |this, cx| {
+                this.scan = Some(result);
+                cx.notify();
+            });
+        });
+    }
+
+    fn render_summary(&self, cx: &mut Context<Self>) -> impl IntoElement {
+        let muted = cx.theme().muted_foreground;
+        let summary = v_flex().gap_1().text_sm();
+
+        match &self.scan {
+            None => summary.child(Label::new("Scanning…").text_color(muted)),
+            Some(Err(e)) => summary.child(Label::new(e.clone()).text_color(cx.theme().danger)),
+            Some(Ok(scan)) => {
+                let count = |reason| scan.skipped.iter().filter(|(_, r)| *r == reason).count();
+                let skipped = format!(
+                    "Skipped {} binary, {} too large, {} unreadable",
+                    count(SkipReason::Binary),
+                    count(SkipReason::TooLarge),
+                    count(SkipReason::Unreadable)
+                );
+
+                summary
+                    .child(Label::new(format!(
+                        "{} files · ~{} tokens",
+                        scan.files.len(),
+                        scan.estimated_tokens()
+                    )))
+                    .child(Label::new(skipped).text_color(muted))
+                    .child(
+                        v_flex()
+                            .text_xs()
+                            .font_family("monospace")
+                            .text_color(muted)
+                            .children(
+                                scan.files
+                                    .iter()
+                                    .take(SUMMARY_FILES)
+                                    .map(|file| div().child(file.path.clone())),
+                            )
+                            .when(scan.files.len() > SUMMARY_FILES, |d| {
+                                d.child(format!("…and {} more", scan.files.len() - SUMMARY_FILES))
+                            }),
+                    )
+            }
+        }
+    }
+}
+
+impl Render for DirectoryOptions {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        v_flex()
+            .gap_2()
+            .child(
+                Label::new(self.root.display().to_string())
+                    .text_sm()
+                    .font_semibold(),
+            )
+            .child(Input::new(&self.include_input))
+            .child(Input::new(&self.exclude_input))
+            .child(self.render_summary(cx))
+    }
+}
@@ -1,77 +1,1016 @@
-use async_channel::{Receiver, Sender};
-use gpui::{AppContext, AsyncApp, WeakEntity};
+use anyhow::{Result, anyhow};
+use async_channel::{Receiver, Sender, unbounded};
+use chrono::Utc;
+use gpui::{AppContext, AsyncWindowContext, WeakEntity};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     ChatAI,
     services::agent::{
-        Agent, AgentRequest, AgentResponse, ContentBlock, FileSource, UiMessage, upload_file,
+        Agent, AgentRequest, AgentResponse, AttachmentFailure, BatchInput, BatchRun,
+        CHAT_ID_PREFIX, CommandRules, ContentBlock, ConversationRef, DirectorySelection, FileCache,
+        FileReferences, FileSource, McpConfig, McpServer, McpServerStatus, ModelCapabilities,
+        ProviderKind, RunCommand, SamplingParams, ToolApproval, ToolCallData, ToolDecision,
+        ToolInfo, ToolPermission, ToolPolicy, ToolRegistry, ToolResultData, UiMessage, UploadInfo,
+        Workspace, batch_results, create_batch, delete_file, discover_local_models, get_batch,
+        inline_file, is_image_file, list_files, ollama_context_length, refresh_models,
+        render_template, scan_directory, start_servers, upload_file_cached, workspace_tools,
     },
 };
 
+/// Maximum length of a conversation title derived from its first message
+const CONVERSATION_TITLE_LEN: usize = 40;
+
+/// System prompt for new conversations, editable in conversation settings
+pub const DEFAULT_SYSTEM_PROMPT: &str =
+    "You are a helpful, succinct assistant. Please respond only in markdown and no emojis.";
+
+/// Upload attempts made for each attachment before the turn is aborted
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Rounds of tool calls a single turn may make before it's stopped
+const MAX_TOOL_STEPS: usize = 25;
+
+/// How long tool calls wait for approval before they're denied, so an
+/// unattended turn doesn't hold up every other request
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Upload a file through the cache, retrying transient failures with backoff
+async fn upload_with_retry(
+    api_key: &str,
+    file_cache: &Arc<Mutex<FileCache>>,
+    path: &Path,
+) -> Result<String> {
+    let mut attempt = 1;
+    loop {
+        let result = smol::unblock({
+            let api_key = api_key.to_string();
+            let file_cache = file_cache.clone();
+            let path = path.to_path_buf();
+            move || upload_file_cached(&api_key, &file_cache, &path)
+        })
+        .await;
+
+        match result {
+            Ok(file_id) => return Ok(file_id),
+            Err(e) if attempt < UPLOAD_ATTEMPTS => {
+                tracing::warn!(
+                    "Upload attempt {} for {} failed: {}",
+                    attempt,
+                    path.display(),
+                    e
+                );
+                smol::Timer::after(Duration::from_millis(500 * 2u64.pow(attempt - 1))).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Upload file inputs, build one request per input with the agent's model and
+/// settings, and submit them as a batch
+async fn submit_batch(
+    agent: &Agent,
+    api_key: &str,
+    file_cache: &Arc<Mutex<FileCache>>,
+    file_refs: &mut FileReferences,
+    template: &str,
+    inputs: Vec<BatchInput>,
+) -> Result<BatchRun> {
+    if agent.provider().kind() != ProviderKind::Anthropic {
+        return Err(anyhow!("Batch mode needs a Claude model"));
+    }
+
+    let capabilities = agent.capabilities();
+    let mut requests = Vec::new();
+    let mut uploaded = Vec::new();
+    for input in &inputs {
+        let mut content = Vec::new();
+        if let BatchInput::File(path) = input {
+            if let Some(reason) = capabilities.unsupported_reason(path) {
+                return Err(anyhow!(
+                    "Can't send {}: {} {}",
+                    input.label(),
+                    agent.model(),
+                    reason
+                ));
+            }
+            // Going through the cache records each upload as it completes,
+            // so files sent before a later failure still show as this app's
+            let file_id = upload_with_retry(api_key, file_cache, path).await?;
+            uploaded.push(file_id.clone());
+            let source = FileSource::File { file_id };
+            content.push(if is_image_file(path) {
+                ContentBlock::Image {
+                    source,
+                    cache_control: None,
+                }
+            } else {
+                ContentBlock::Document {
+                    source,
+                    cache_control: None,
+                }
+            });
+        }
+        content.push(ContentBlock::text(render_template(
+            template,
+            &input.label(),
+        )));
+        requests.push(agent.standalone_request(content));
+    }
+
+    let job = smol::unblock({
+        let api_key = api_key.to_string();
+        move || create_batch(&api_key, &requests)
+    })
+    .await?;
+
+    // Keep the uploads from looking unused while the batch runs
+    let batch = ConversationRef {
+        id: job.id.clone(),
+        title: format!("Batch of {}", inputs.len()),
+    };
+    for file_id in &uploaded {
+        file_refs.record(file_id, batch.clone());
+    }
+    if let Err(e) = file_refs.save() {
+        tracing::warn!("{}", e);
+    }
+
+    Ok(BatchRun {
+        job,
+        inputs,
+        results: Vec::new(),
+    })
+}
+
+/// Content of a drafted turn for counting tokens. Files are read inline rather
+/// than uploaded, and attachments that can't be sent are left out.
+async fn draft_content(
+    content: String,
+    files: Vec<PathBuf>,
+    directories: Vec<DirectorySelection>,
+    capabilities: ModelCapabilities,
+    cache: &mut DraftBlocks,
+) -> Vec<ContentBlock> {
+    let mut blocks = Vec::new();
+    if !content.trim().is_empty() {
+        blocks.push(ContentBlock::text(content));
+    }
+
+    // Only the current attachments are kept, so removed ones are freed
+    let mut previous = std::mem::take(cache);
+    for path in files {
+        if capabilities.unsupported_reason(&path).is_some() {
+            continue;
+        }
+        // An edited file is read again
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let key = (path, modified);
+        let block = match previous.files.remove(&key) {
+            Some(block) => block,
+            None => {
+                let path = key.0.clone();
+                match smol::unblock(move || inline_file(&path)).await {
+                    Ok(block) => block,
+                    Err(e) => {
+                        tracing::debug!("Skipping attachment in token count: {}", e);
+                        continue;
+                    }
+                }
+            }
+        };
+        blocks.push(block.clone());
+        cache.files.insert(key, block);
+    }
+    for selection in directories {
+        let block = match previous.directories.remove(&selection) {
+            Some(block) => block,
+            None => {
+                let scan = smol::unblock({
+                    let selection = selection.clone();
+                    move || scan_directory(&selection)
+                })
+                .await;
+                match scan {
+                    Ok(scan) => ContentBlock::text(scan.to_text()),
+                    Err(_) => continue,
+                }
+            }
+        };
+        blocks.push(block.clone());
+        cache.directories.insert(selection, block);
+    }
+    blocks
+}
+
+/// Attachment blocks of the draft, kept between token counts so each pause in
+/// typing doesn't read and encode every file or rescan every directory again
+#[derive(Default)]
+struct DraftBlocks {
+    files: HashMap<(PathBuf, Option<SystemTime>), ContentBlock>,
+    directories: HashMap<DirectorySelection, ContentBlock>,
+}
+
+/// Runs the model's tool calls as the user's permissions allow
+struct ToolRunner {
+    registry: ToolRegistry,
+    policy: ToolPolicy,
+    /// Decisions about calls awaiting approval, sent by the chat view
+    approval_rx: Receiver<ToolApproval>,
+    /// Folder the file and command tools work in
+    workspace: Option<Workspace>,
+    allow_writes: bool,
+    allow_commands: bool,
+    /// Which commands `run_command` may run
+    command_rules: CommandRules,
+    /// Running MCP servers, whose tools are registered after the built-in ones
+    mcp_servers: Vec<McpServer>,
+    /// Counts server launches, so servers from a superseded launch are dropped
+    mcp_generation: u64,
+}
+
+impl ToolRunner {
+    /// Register the tools for the current workspace options and MCP servers
+    fn rebuild(&mut self) {
+        self.registry = ToolRegistry::default();
+        if let Some(workspace) = &self.workspace {
+            for tool in workspace_tools(workspace, self.allow_writes) {
+                self.registry.register(tool);
+            }
+            if self.allow_commands {
+                self.registry.register(Arc::new(RunCommand::new(
+                    workspace.clone(),
+                    self.command_rules.clone(),
+                )));
+            }
+        }
+        for server in &self.mcp_servers {
+            for tool in &server.tools {
+                self.registry.register(tool.clone());
+            }
+        }
+    }
+
+    /// Use the servers from the latest launch. Returns false for an older one.
+    fn servers_started(&mut self, started: McpStarted) -> bool {
+        if started.generation != self.mcp_generation {
+            return false;
+        }
+        self.mcp_servers = started.servers;
+        // A server may have stopped before its launch finished
+        for server in &mut self.mcp_servers {
+            if server.has_exited() {
+                server.mark_exited();
+            }
+        }
+        self.rebuild();
+        true
+    }
+
+    /// Remove the tools of a server that stopped. Returns false if it isn't in use.
+    fn server_exited(&mut self, name: &str) -> bool {
+        let Some(server) = self
+            .mcp_servers
+            .iter_mut()
+            .find(|server| server.status.name == name && server.has_exited())
+        else {
+            return false;
+        };
+        server.mark_exited();
+        self.rebuild();
+        true
+    }
+
+    fn mcp_statuses(&self) -> Vec<McpServerStatus> {
+        self.mcp_servers
+            .iter()
+            .map(|server| server.status.clone())
+            .collect()
+    }
+
+    /// Registered tools with their current permissions
+    fn infos(&self) -> Vec<ToolInfo> {
+        self.registry
+            .definitions()
+            .into_iter()
+            .map(|tool| ToolInfo {
+                permission: self
+                    .policy
+                    .permission(&tool.name, self.registry.default_permission(&tool.name)),
+                name: tool.name,
+                description: tool.description,
+            })
+            .collect()
+    }
+
+    /// Run a reply's tool calls, first asking the user about those that need
+    /// approval. Refused calls become error results. Results are in call order.
+    async fn run(
+        &self,
+        calls: &[ToolCallData],
+        response_tx: &Sender<AgentResponse>,
+    ) -> Vec<ToolResultData> {
+        let refuse = |call: &ToolCallData, content: String| ToolResultData {
+            tool_use_id: call.id.clone(),
+            content,
+            is_error: true,
+        };
+
+        let mut calls = calls.to_vec();
+        let mut refused: Vec<Option<ToolResultData>> = vec![None; calls.len()];
+        let mut edited = vec![false; calls.len()];
+        let mut pending = HashSet::new();
+        for (ix, call) in calls.iter().enumerate() {
+            let default = self.registry.default_permission(&call.name);
+            match self.policy.permission(&call.name, default) {
+                ToolPermission::Allow => {}
+                ToolPermission::Ask => {
+                    pending.insert(call.id.clone());
+                    let _ = response_tx.try_send(AgentResponse::ApprovalRequested(call.id.clone()));
+                }
+                ToolPermission::Deny => {
+                    refused[ix] = Some(refuse(
+                        call,
+                        format!("The user doesn't allow {} to run", call.name),
+                    ));
+                }
+            }
+        }
+
+        let mut deadline = smol::Timer::after(APPROVAL_TIMEOUT);
+        let mut timed_out = false;
+        while !pending.is_empty() {
+            // Err says whether the wait timed out rather than the chat view going away
+            let approval = smol::future::or(
+                async { self.approval_rx.recv().await.map_err(|_| false) },
+                async {
+                    (&mut deadline).await;
+                    Err(true)
+                },
+            )
+            .await;
+            let approval = match approval {
+                Ok(approval) => approval,
+                Err(expired) => {
+                    timed_out = expired;
+                    break;
+                }
+            };
+            // Decisions about calls from an earlier turn are stale
+            if !pending.remove(&approval.tool_use_id) {
+                continue;
+            }
+            let Some(ix) = calls.iter().position(|c| c.id == approval.tool_use_id) else {
+                continue;
+            };
+            match approval.decision {
+                ToolDecision::Approve { input: Some(input) } => {
+                    edited[ix] = input != calls[ix].input;
+                    calls[ix].input = input;
+                }
+                ToolDecision::Approve { input: None } => {}
+                ToolDecision::Deny { reason } => {
+                    let mut content = format!("The user denied this {} call", calls[ix].name);
+                    if !reason.trim().is_empty() {
+                        content = format!("{}: {}", content, reason.trim());
+                    }
+                    refused[ix] = Some(refuse(&calls[ix], content));
+                }
+            }
+        }
+        // Only reached with calls left pending on timeout or if the chat view went away
+        let unanswered = if timed_out {
+            format!(
+                "No one approved this call within {} minutes",
+                APPROVAL_TIMEOUT.as_secs() / 60
+            )
+        } else {
+            "No one approved this call".to_string()
+        };
+        for (ix, call) in calls.iter().enumerate() {
+            if pending.contains(&call.id) {
+                refused[ix] = Some(refuse(call, unanswered.clone()));
+            }
+        }
+
+        let approved: Vec<ToolCallData> = calls
+            .iter()
+            .zip(&refused)
+            .filter(|(_, refused)| refused.is_none())
+            .map(|(call, _)| call.clone())
+            .collect();
+        let mut results = self.registry.execute_all(&approved).await.into_iter();
+
+        refused
+            .into_iter()
+            .zip(calls.iter().zip(edited))
+            .map(|(refused, (call, edited))| {
+                refused.unwrap_or_else(|| {
+                    let mut result = results.next().expect("a result for each approved call");
+                    if edited {
+                        // The model only knows the input it asked for
+                        result.content = format!(
+                            "The user changed the input to {} before running it.\n\n{}",
+                            call.input, result.content
+                        );
+                    }
+                    result
+                })
+            })
+            .collect()
+    }
+}
+
+/// Report each step of a turn, running the tools it calls and sending their
+/// results back until the model ends its turn
+async fn run_tool_loop(
+    agent: &mut Agent,
+    tools: &ToolRunner,
+    response_tx: &Sender<AgentResponse>,
+    mut result: Result<AgentResponse>,
+) {
+    for step in 0.. {
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = response_tx.try_send(AgentResponse::Error(format!("{}", e)));
+                return;
+            }
+        };
+        let _ = response_tx.try_send(AgentResponse::ContextUsage(agent.context_usage()));
+        // Calls cut off by the output limit may be incomplete, so only run them
+        // when the model stopped to use tools
+        let tool_calls = match &response {
+            AgentResponse::ToolCallRequest { tool_calls, .. } if !response.is_done() => {
+                tool_calls.clone()
+            }
+            _ => Vec::new(),
+        };
+        let _ = response_tx.try_send(response);
+        if tool_calls.is_empty() {
+            return;
+        }
+
+        if step == MAX_TOOL_STEPS {
+            // Answer the calls so the conversation stays valid, without another step
+            agent.submit_tool_results(
+                tool_calls
+                    .iter()
+                    .map(|call| ToolResultData {
+                        tool_use_id: call.id.clone(),
+                        content: "Not run: the turn made too many tool calls".to_string(),
+                        is_error: true,
+                    })
+                    .collect(),
+            );
+            let _ = response_tx.try_send(AgentResponse::Error(format!(
+                "Stopped after {} rounds of tool calls",
+                MAX_TOOL_STEPS
+            )));
+            return;
+        }
+
+        let results = tools.run(&tool_calls, response_tx).await;
+        let _ = response_tx.try_send(AgentResponse::ToolResults(results.clone()));
+        result = agent.continue_with_tool_results(results).await;
+    }
+}
+
+/// Start tracking a fresh conversation
+fn new_conversation() -> ConversationRef {
+    ConversationRef {
+        id: format!("{}{}", CHAT_ID_PREFIX, Utc::now().timestamp_millis()),
+        title: String::new(),
+    }
+}
+
+/// List uploaded files along with the conversations referencing them
+async fn list_uploads(
+    api_key: &str,
+    file_refs: &FileReferences,
+    file_cache: &Mutex<FileCache>,
+) -> Result<Vec<UploadInfo>> {
+    let files = smol::unblock({
+        let api_key = api_key.to_string();
+        move || list_files(&api_key)
+    })
+    .await?;
+
+    Ok(files
+        .into_iter()
+        .map(|file| UploadInfo {
+            owned: file_refs.contains(&file.id)
+                || file_cache.lock().unwrap().contains_file_id(&file.id),
+            conversations: file_refs
+                .conversations(&file.id)
+                .iter()
+                .map(|c| c.title.clone())
+                .collect(),
+            file,
+        })
+        .collect())
+}
+
+/// MCP servers from one launch, all started or failed
+struct McpStarted {
+    generation: u64,
+    servers: Vec<McpServer>,
+}
+
+/// Channels MCP servers report on while the agent handles requests
+struct McpChannels {
+    started_tx: Sender<McpStarted>,
+    started_rx: Receiver<McpStarted>,
+    /// Names of servers that stopped on their own
+    exit_tx: Sender<String>,
+    exit_rx: Receiver<String>,
+}
+
+impl McpChannels {
+    fn new() -> Self {
+        let (started_tx, started_rx) = unbounded();
+        let (exit_tx, exit_rx) = unbounded();
+        Self {
+            started_tx,
+            started_rx,
+            exit_tx,
+            exit_rx,
+        }
+    }
+
+    /// Launch the configured MCP servers in the background, so requests are
+    /// handled while they start. A config that can't be read is reported as
+    /// an error.
+    fn start(&self, generation: u64, response_tx: &Sender<AgentResponse>) {
+        let started_tx = self.started_tx.clone();
+        let exit_tx = self.exit_tx.clone();
+        let response_tx = response_tx.clone();
+        smol::spawn(async move {
+            let servers = match smol::unblock(McpConfig::load).await {
+                Ok(config) => smol::unblock(move || start_servers(&config, exit_tx)).await,
+                Err(e) => {
+                    let _ = response_tx.try_send(AgentResponse::Error(e.to_string()));
+                    Vec::new()
+                }
+            };
+            let _ = started_tx
+                .send(McpStarted {
+                    generation,
+                    servers,
+                })
+                .await;
+        })
+        .detach();
+    }
+}
+
+/// What the agent loop wakes up for
+enum Incoming {
+    Request(AgentRequest),
+    McpStarted(McpStarted),
+    McpExited(String),
+}
+
+/// Offer the current tools to the model and show them in settings
+fn publish_tools(agent: &mut Agent, tools: &ToolRunner, response_tx: &Sender<AgentResponse>) {
+    agent.set_tools(tools.registry.definitions());
+    let _ = response_tx.try_send(AgentResponse::Tools(tools.infos()));
+    let _ = response_tx.try_send(AgentResponse::McpServers(tools.mcp_statuses()));
+}
+
 pub async fn handle_outgoing(
     request_rx: Receiver<AgentRequest>,
+    approval_rx: Receiver<ToolApproval>,
     response_tx: Sender<AgentResponse>,
 ) {
-    if let Some(mut agent) = Agent::builder()
-        .system_prompt(
-            "You are a helpful, succint assistant. Please respond only in markdown and no emojis."
-                .to_string(),
-        )
-        .max_tokens(4096)
-        .build(vec![])
-        .ok()
-    {
+    // Without an Anthropic key, start on a local model so the app works offline
+    let provider = ProviderKind::Anthropic
+        .create()
+        .or_else(|_| ProviderKind::Ollama.create());
+
+    // Tools offered to the model and run on its behalf
+    let mut tools = ToolRunner {
+        registry: ToolRegistry::default(),
+        policy: ToolPolicy::load(),
+        approval_rx,
+        workspace: None,
+        allow_writes: false,
+        allow_commands: false,
+        command_rules: CommandRules::load(),
+        // Started once the agent is ready
+        mcp_servers: Vec::new(),
+        mcp_generation: 0,
+    };
+    tools.rebuild();
+
+    if let Ok(mut agent) = provider.and_then(|provider| {
+        Agent::builder()
+            .provider(provider)
+            .system_prompt(DEFAULT_SYSTEM_PROMPT.to_string())
+            .build(tools.registry.definitions())
+    }) {
+        agent.stream_text_to(response_tx.clone());
+        let _ = response_tx.try_send(AgentResponse::Tools(tools.infos()));
+        let _ = response_tx.try_send(AgentResponse::McpServers(tools.mcp_statuses()));
+        let mcp = McpChannels::new();
+        mcp.start(tools.mcp_generation, &response_tx);
+
         // Get API key for file uploads
         let api_key = std::env::var("ANTHROPIC_API_KEY").unwrap_or_default();
+        // Previously uploaded files, keyed by content hash
+        let file_cache = Arc::new(Mutex::new(FileCache::load()));
+        // Which conversations sent each uploaded file
+        let mut file_refs = FileReferences::load();
+        file_refs.forget_chats();
+        let mut conversation = new_conversation();
+        let mut draft_blocks = DraftBlocks::default();
+
+        loop {
+            let incoming = smol::future::or(
+                async { request_rx.recv().await.ok().map(Incoming::Request) },
+                smol::future::or(
+                    async { mcp.started_rx.recv().await.ok().map(Incoming::McpStarted) },
+                    async { mcp.exit_rx.recv().await.ok().map(Incoming::McpExited) },
+                ),
+            )
+            .await;
+            let request = match incoming {
+                Some(Incoming::Request(request)) => request,
+                Some(Incoming::McpStarted(started)) => {
+                    if tools.servers_started(started) {
+                        publish_tools(&mut agent, &tools, &response_tx);
+                    }
+                    continue;
+                }
+                Some(Incoming::McpExited(name)) => {
+                    if tools.server_exited(&name) {
+                        publish_tools(&mut agent, &tools, &response_tx);
+                    }
+                    continue;
+                }
+                // The chat view went away
+                None => break,
+            };
 
-        while let Ok(request) = request_rx.recv().await {
             match request {
-                AgentRequest::Chat { content, files } => {
+                AgentRequest::Chat {
+                    content,
+                    files,
+                    directories,
+                    tool_choice,
+                } => {
                     // Build user content with text and any uploaded files
-                    let mut user_content = vec![ContentBlock::Text { text: content }];
+                    let mut user_content = vec![ContentBlock::text(content.clone())];
+                    let mut uploaded = Vec::new();
+                    let mut failures = Vec::new();
 
                     // Upload files and add to content
+                    let supports_uploads = agent.provider().supports_file_uploads();
+                    let capabilities = agent.capabilities();
                     for path in files {
-                        match smol::unblock({
-                            let api_key = api_key.clone();
-                            let path = path.clone();
-                            move || upload_file(&api_key, &path)
-                        })
-                        .await
-                        {
+                        if let Some(reason) = capabilities.unsupported_reason(&path) {
+                            failures.push(AttachmentFailure {
+                                path,
+                                error: format!("{} {}", agent.model(), reason),
+                            });
+                            continue;
+                        }
+
+                        if !supports_uploads {
+                            // No Files API, so send the contents inline
+                            let inline_path = path.clone();
+                            match smol::unblock(move || inline_file(&inline_path)).await {
+                                Ok(block) => user_content.push(block),
+                                Err(e) => {
+                                    tracing::error!("Failed to read file: {}", e);
+                                    failures.push(AttachmentFailure {
+                                        path,
+                                        error: e.to_string(),
+                                    });
+                                }
+                            }
+                            continue;
+                        }
+
+                        match upload_with_retry(&api_key, &file_cache, &path).await {
                             Ok(file_id) => {
-                                user_content.push(ContentBlock::Document {
-                                    source: FileSource::File { file_id },
+                                uploaded.push(file_id.clone());
+                                let source = FileSource::File { file_id };
+                                user_content.push(if is_image_file(&path) {
+                                    ContentBlock::Image {
+                                        source,
+                                        cache_control: None,
+                                    }
+                                } else {
+                                    ContentBlock::Document {
+                                        source,
+                                        cache_control: None,
+                                    }
                                 });
                             }
                             Err(e) => {
                                 tracing::error!("Failed to upload file: {}", e);
-                                let _ = response_tx.try_send(AgentResponse::Error(format!(
-                                    "Failed to upload file: {}",
-                                    e
-                                )));
+                                failures.push(AttachmentFailure {
+                                    path,
+                                    error: e.to_string(),
+                                });
                             }
                         }
                     }
 
-                    match agent.chat_step(user_content).await {
-                        Ok(response) => {
-                            let _ = response_tx.try_send(response);
+                    // Pack attached directories into the turn as text
+                    for selection in directories {
+                        let root = selection.root.clone();
+                        match smol::unblock(move || scan_directory(&selection)).await {
+                            Ok(scan) => {
+                                tracing::debug!(
+                                    "Packed {} files from {}",
+                                    scan.files.len(),
+                                    root.display()
+                                );
+                                user_content.push(ContentBlock::text(scan.to_text()));
+                            }
+                            Err(e) => {
+                                tracing::error!("Failed to read directory: {}", e);
+                                failures.push(AttachmentFailure {
+                                    path: root,
+                                    error: e.to_string(),
+                                });
+                            }
                         }
-                        Err(e) => {
-                            let _ = response_tx.try_send(AgentResponse::Error(format!("{}", e)));
+                    }
+
+                    // Never send a turn that's missing attachments the user asked about
+                    if !failures.is_empty() {
+                        let _ = response_tx.try_send(AgentResponse::TurnAborted { failures });
+                        continue;
+                    }
+
+                    if conversation.title.is_empty() {
+                        conversation.title = content
+                            .lines()
+                            .next()
+                            .unwrap_or_default()
+                            .chars()
+                            .take(CONVERSATION_TITLE_LEN)
+                            .collect();
+                    }
+                    for file_id in &uploaded {
+                        file_refs.record(file_id, conversation.clone());
+                    }
+                    if let Err(e) = file_refs.save() {
+                        tracing::warn!("{}", e);
+                    }
+
+                    // A failed summary isn't fatal; the turn may still fit
+                    match agent.compact_if_needed(&user_content).await {
+                        Ok(Some(messages)) => {
+                            let _ = response_tx.try_send(AgentResponse::Compacted { messages });
                         }
+                        Ok(None) => {}
+                        Err(e) => tracing::warn!("{}", e),
                     }
+
+                    let result = if agent.output_schema().is_some() {
+                        agent.structured_step(user_content).await
+                    } else {
+                        agent
+                            .chat_step_with_tool_choice(user_content, tool_choice)
+                            .await
+                    };
+                    run_tool_loop(&mut agent, &tools, &response_tx, result).await;
+                }
+                AgentRequest::CountTokens {
+                    content,
+                    files,
+                    directories,
+                } => {
+                    let pending = draft_content(
+                        content,
+                        files,
+                        directories,
+                        agent.capabilities(),
+                        &mut draft_blocks,
+                    )
+                    .await;
+                    let count = agent.count_tokens(pending).await;
+                    let _ = response_tx.try_send(AgentResponse::TokenCount(count));
                 }
                 AgentRequest::ClearHistory => {
                     agent.clear_conversation();
+                    agent.set_thinking_budget(None);
+                    agent.set_sampling(SamplingParams::default());
+                    agent.set_system_prompt(DEFAULT_SYSTEM_PROMPT.to_string());
+                    agent.set_enabled_tools(None);
+                    agent.set_output_schema(None);
+                    agent.set_tool_choice(None);
+                    file_refs.forget_conversation(&conversation.id);
+                    conversation = new_conversation();
+                    let _ = response_tx.try_send(AgentResponse::HistoryCleared);
+                }
+                AgentRequest::SetThinking(budget) => {
+                    agent.set_thinking_budget(budget);
+                }
+                AgentRequest::SetSystemPrompt(prompt) => {
+                    agent.set_system_prompt(prompt);
+                }
+                AgentRequest::SetTools(tools) => {
+                    agent.set_enabled_tools(tools);
+                }
+                AgentRequest::SetOutputSchema(schema) => {
+                    agent.set_output_schema(schema);
                 }
-                AgentRequest::SetModel(model) => {
+                AgentRequest::SetToolChoice(tool_choice) => {
+                    agent.set_tool_choice(tool_choice);
+                }
+                AgentRequest::SetToolPermission { name, permission } => {
+                    tools.policy.set(&name, permission);
+                    if let Err(e) = tools.policy.save() {
+                        tracing::warn!("{}", e);
+                    }
+                    let _ = response_tx.try_send(AgentResponse::Tools(tools.infos()));
+                }
+                AgentRequest::SetWorkspace {
+                    root,
+                    allow_writes,
+                    allow_commands,
+                } => {
+                    // The previous folder stays in use if the new one can't be opened
+                    let error = match root.as_deref().map(Workspace::new).transpose() {
+                        Ok(workspace) => {
+                            tools.workspace = workspace;
+                            None
+                        }
+                        Err(e) => Some(e.to_string()),
+                    };
+                    let _ = response_tx.try_send(AgentResponse::Workspace {
+                        root: tools.workspace.as_ref().map(|w| w.root().to_path_buf()),
+                        error,
+                    });
+                    tools.allow_writes = allow_writes;
+                    tools.allow_commands = allow_commands;
+                    tools.rebuild();
+                    agent.set_tools(tools.registry.definitions());
+                    let _ = response_tx.try_send(AgentResponse::Tools(tools.infos()));
+                }
+                AgentRequest::SetCommandRules(rules) => {
+                    if let Err(e) = rules.save() {
+                        tracing::warn!("{}", e);
+                    }
+                    tools.command_rules = rules;
+                    tools.rebuild();
+                }
+                AgentRequest::ReloadMcpServers => {
+                    // Stop the old servers before starting new ones
+                    tools.mcp_servers.clear();
+                    tools.mcp_generation += 1;
+                    tools.rebuild();
+                    publish_tools(&mut agent, &tools, &response_tx);
+                    mcp.start(tools.mcp_generation, &response_tx);
+                }
+                AgentRequest::SetSampling(sampling) => {
+                    agent.set_sampling(sampling);
+                }
+                AgentRequest::SetModel { model, provider } => {
+                    if agent.provider().kind() != provider {
+                        match provider.create() {
+                            Ok(provider) => agent.set_provider(provider),
+                            Err(e) => {
+                                let _ = response_tx.try_send(AgentResponse::Error(format!(
+                                    "Can't switch to {}: {}",
+                                    model, e
+                                )));
+                                continue;
+                            }
+                        }
+                    }
                     // Update the agent's model
                     tracing::debug!("Setting agent model to: {}", model);
-                    agent.set_model(model);
+                    agent.set_model(model.clone());
+                    if provider == ProviderKind::Ollama {
+                        let context_window =
+                            smol::unblock(move || ollama_context_length(&model)).await;
+                        match context_window {
+                            Ok(context_window) => agent.set_context_window(context_window),
+                            Err(e) => tracing::warn!("Can't read the context window: {}", e),
+                        }
+                    }
                     // Clear conversation when model changes
                     agent.clear_conversation();
+                    file_refs.forget_conversation(&conversation.id);
+                    conversation = new_conversation();
+                }
+                AgentRequest::ListUploads => {
+                    let response = match list_uploads(&api_key, &file_refs, &file_cache).await {
+                        Ok(uploads) => AgentResponse::Uploads(uploads),
+                        Err(e) => AgentResponse::Error(format!("Failed to list uploads: {}", e)),
+                    };
+                    let _ = response_tx.try_send(response);
+                }
+                AgentRequest::DeleteUploads(file_ids) => {
+                    for file_id in file_ids {
+                        let result = smol::unblock({
+                            let api_key = api_key.clone();
+                            let file_id = file_id.clone();
+                            move || delete_file(&api_key, &file_id)
+                        })
+                        .await;
+
+                        match result {
+                            Ok(()) => {
+                                let mut cache = file_cache.lock().unwrap();
+                                cache.remove_file_id(&file_id);
+                                if let Err(e) = cache.save() {
+                                    tracing::warn!("{}", e);
+                                }
+                                file_refs.forget(&file_id);
+                            }
+                            Err(e) => {
+                                tracing::error!("Failed to delete file: {}", e);
+                                let _ = response_tx.try_send(AgentResponse::Error(format!(
+                                    "Failed to delete file: {}",
+                                    e
+                                )));
+                            }
+                        }
+                    }
+                    if let Err(e) = file_refs.save() {
+                        tracing::warn!("{}", e);
+                    }
+
+                    let response = match list_uploads(&api_key, &file_refs, &file_cache).await {
+                        Ok(uploads) => AgentResponse::Uploads(uploads),
+                        Err(e) => AgentResponse::Error(format!("Failed to list uploads: {}", e)),
+                    };
+                    let _ = response_tx.try_send(response);
+                }
+                AgentRequest::SubmitBatch { template, inputs } => {
+                    let response = match submit_batch(
+                        &agent,
+                        &api_key,
+                        &file_cache,
+                        &mut file_refs,
+                        &template,
+                        inputs,
+                    )
+                    .await
+                    {
+                        Ok(run) => AgentResponse::BatchSubmitted(run),
+                        Err(e) => AgentResponse::Error(format!("Failed to submit batch: {}", e)),
+                    };
+                    let _ = response_tx.try_send(response);
+                }
+                AgentRequest::PollBatch(batch_id) => {
+                    let result = smol::unblock({
+                        let api_key = api_key.clone();
+                        let batch_id = batch_id.clone();
+                        move || {
+                            let job = get_batch(&api_key, &batch_id)?;
+                            let results = if job.is_ended() {
+                                batch_results(&api_key, &job)?
+                            } else {
+                                Vec::new()
+                            };
+                            Ok::<_, anyhow::Error>((job, results))
+                        }
+                    })
+                    .await;
+                    let response = match result {
+                        Ok((job, results)) => AgentResponse::BatchUpdated { job, results },
+                        Err(e) => AgentResponse::BatchPollFailed {
+                            batch_id,
+                            error: format!("Failed to check batch: {}", e),
+                        },
+                    };
+                    let _ = response_tx.try_send(response);
+                }
+                AgentRequest::RefreshModels => {
+                    if api_key.is_empty() {
+                        continue;
+                    }
+                    let result = smol::unblock({
+                        let api_key = api_key.clone();
+                        move || refresh_models(&api_key)
+                    })
+                    .await;
+                    match result {
+                        Ok(models) => {
+                            let _ = response_tx.try_send(AgentResponse::Models(models));
+                        }
+                        // The cached or static list is still usable, so don't bother the user
+                        Err(e) => tracing::warn!("Failed to refresh models: {}", e),
+                    }
+                }
+                AgentRequest::DiscoverLocalModels => {
+                    let models = smol::unblock(discover_local_models).await;
+                    let _ = response_tx.try_send(AgentResponse::LocalModels(models));
                 }
                 _ => {}
             }
@@ -87,7 +1026,7 @@ pub async fn handle_outgoing(
 pub async fn handle_incoming(
     this: WeakEntity<ChatAI>,
     response_rx: Receiver<AgentResponse>,
-    cx: &mut AsyncApp,
+    cx: &mut AsyncWindowContext,
 ) {
     loop {
         let incoming_response = response_rx.recv().await;
@@ -95,12 +1034,29 @@ pub async fn handle_incoming(
             Ok(response) => {
                 // Check if this response means we're done processing
                 let is_done = response.is_done();
+                // The rest of a turn from a chat the user has since cleared
+                let superseded = this
+                    .update(cx, |this, _| this.is_clearing())
+                    .unwrap_or(false);
+                if superseded && response.is_turn_output() {
+                    continue;
+                }
 
                 match response {
-                    AgentResponse::TextResponse { text, .. } => {
+                    AgentResponse::TextDelta(text) => {
                         if let Some(view) = this.upgrade() {
                             let _ = cx.update_entity(&view, |this, cx| {
-                                this.add_message(UiMessage::assistant(text), cx);
+                                this.append_streamed_text(&text, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::TextResponse { text, thought, .. } => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.add_message(
+                                    UiMessage::assistant(text).with_thought(thought),
+                                    cx,
+                                );
                                 // Clear loading state only if done
                                 if is_done {
                                     this.set_loading(false, cx);
@@ -108,16 +1064,160 @@ pub async fn handle_incoming(
                             });
                         }
                     }
+                    AgentResponse::ToolCallRequest {
+                        text,
+                        tool_calls,
+                        thought,
+                        ..
+                    } => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                if text.is_some() || thought.is_some() {
+                                    this.add_message(
+                                        UiMessage::assistant(text.unwrap_or_default())
+                                            .with_thought(thought),
+                                        cx,
+                                    );
+                                }
+                                for call in tool_calls {
+                                    this.add_message(UiMessage::tool_call(call), cx);
+                                }
+                                if is_done {
+                                    this.set_loading(false, cx);
+                                }
+                            });
+                        }
+                    }
+                    AgentResponse::ApprovalRequested(tool_use_id) => {
+                        let _ = this.update_in(cx, |this, window, cx| {
+                            this.request_approval(tool_use_id, window, cx);
+                        });
+                    }
+                    AgentResponse::Tools(tools) => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.set_tools(tools, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::Workspace { root, error } => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.set_workspace(root, error, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::McpServers(servers) => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.set_mcp_servers(servers, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::ToolResults(results) => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.add_tool_results(results, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::StructuredResponse { value } => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.add_message(UiMessage::structured(value), cx);
+                                this.set_loading(false, cx);
+                            });
+                        }
+                    }
                     AgentResponse::Error(err) => {
                         if let Some(view) = this.upgrade() {
                             let _ = cx.update_entity(&view, |this, cx| {
-                                this.add_message(UiMessage::error(err), cx);
+                                if !superseded {
+                                    this.add_message(UiMessage::error(err), cx);
+                                }
                                 // Always clear loading state on error
                                 this.set_loading(false, cx);
+                                this.set_uploads_loading(false, cx);
+                                this.set_batch_submitting(false, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::TurnAborted { failures } => {
+                        let _ = this.update_in(cx, |this, window, cx| {
+                            this.restore_aborted_turn(failures, window, cx);
+                        });
+                    }
+                    AgentResponse::BatchSubmitted(run) => {
+                        let _ = this.update_in(cx, |this, window, cx| {
+                            this.add_batch(run, window, cx);
+                        });
+                    }
+                    AgentResponse::BatchUpdated { job, results } => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.update_batch(job, results, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::BatchPollFailed { batch_id, error } => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.batch_poll_failed(batch_id, error, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::Models(models) => {
+                        let _ = this.update_in(cx, |this, window, cx| {
+                            this.set_hosted_models(models, window, cx);
+                        });
+                    }
+                    AgentResponse::LocalModels(models) => {
+                        let _ = this.update_in(cx, |this, window, cx| {
+                            this.add_local_models(models, window, cx);
+                        });
+                    }
+                    AgentResponse::ContextUsage(usage) => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.set_context_usage(Some(usage), cx);
+                            });
+                        }
+                    }
+                    AgentResponse::TokenCount(count) => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.set_token_count(count, cx);
+                            });
+                        }
+                    }
+                    AgentResponse::Compacted { messages } => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.add_message(
+                                    UiMessage::system(format!(
+                                        "_Summarized {} earlier messages to stay within the \
+                                        context window._",
+                                        messages
+                                    )),
+                                    cx,
+                                );
+                            });
+                        }
+                    }
+                    AgentResponse::HistoryCleared => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, _| {
+                                this.history_cleared();
+                            });
+                        }
+                    }
+                    AgentResponse::Uploads(uploads) => {
+                        if let Some(view) = this.upgrade() {
+                            let _ = cx.update_entity(&view, |this, cx| {
+                                this.set_uploads(uploads, cx);
                             });
                         }
                     }
-                    _ => {}
                 }
             }
             Err(e) => {
@@ -0,0 +1,121 @@
+use gpui::{
+    App, Div, InteractiveElement as _, ParentElement as _, SharedString,
+    StatefulInteractiveElement as _, Styled as _, Window, div, prelude::FluentBuilder as _,
+};
+use gpui_component::{ActiveTheme as _, Icon, Sizable as _, h_flex, v_flex};
+use serde_json::Value;
+use std::{collections::HashSet, rc::Rc};
+
+type ToggleHandler = Rc<dyn Fn(String, &mut Window, &mut App)>;
+
+/// Size of an object or array shown next to its key, e.g. `{3}` or `[5]`
+fn summary(value: &Value) -> Option<String> {
+    match value {
+        Value::Object(map) => Some(format!("{{{}}}", map.len())),
+        Value::Array(items) => Some(format!("[{}]", items.len())),
+        _ => None,
+    }
+}
+
+/// A collapsible view of a JSON value. Nodes are expanded unless their path
+/// (a JSON pointer such as `/lines/0`) is in `collapsed`; clicking a node
+/// calls `on_toggle` with its path.
+pub fn json_tree(
+    id: impl Into<SharedString>,
+    value: &Value,
+    collapsed: &HashSet<String>,
+    on_toggle: impl Fn(String, &mut Window, &mut App) + 'static,
+    cx: &App,
+) -> Div {
+    let on_toggle: ToggleHandler = Rc::new(on_toggle);
+    v_flex()
+        .font_family("monospace")
+        .text_sm()
+        .child(render_node(
+            &id.into(),
+            None,
+            value,
+            String::new(),
+            collapsed,
+            &on_toggle,
+            cx,
+        ))
+}
+
+fn render_node(
+    id: &SharedString,
+    key: Option<String>,
+    value: &Value,
+    path: String,
+    collapsed: &HashSet<String>,
+    on_toggle: &ToggleHandler,
+    cx: &App,
+) -> Div {
+    let key_label = key.map(|key| div().child(format!("{}:", key)));
+
+    let Some(summary) = summary(value) else {
+        let (text, color) = match value {
+            Value::String(text) => (format!("\"{}\"", text), cx.theme().success),
+            Value::Number(number) => (number.to_string(), cx.theme().info),
+            other => (other.to_string(), cx.theme().warning),
+        };
+        return h_flex()
+            .gap_1()
+            .pl_4()
+            .children(key_label)
+            .child(div().text_color(color).child(text));
+    };
+
+    let is_collapsed = collapsed.contains(&path);
+    let chevron = if is_collapsed {
+        "icons/chevron-right.svg"
+    } else {
+        "icons/chevron-down.svg"
+    };
+    let header = h_flex()
+        .id(SharedString::from(format!("{}-{}", id, path)))
+        .gap_1()
+        .cursor_pointer()
+        .child(Icon::empty().path(chevron).xsmall())
+        .children(key_label)
+        .child(div().text_color(cx.theme().muted_foreground).child(summary))
+        .on_click({
+            let on_toggle = on_toggle.clone();
+            let path = path.clone();
+            move |_, window, cx| on_toggle(path.clone(), window, cx)
+        });
+
+    let children: Vec<(String, &Value)> = match value {
+        Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(ix, v)| (ix.to_string(), v))
+            .collect(),
+        _ => vec![],
+    };
+
+    v_flex().child(header).when(!is_collapsed, |d| {
+        d.child(
+            v_flex()
+                .pl_4()
+                .children(children.into_iter().map(|(key, child)| {
+                    let child_path = format!("{}/{}", path, key);
+                    render_node(id, Some(key), child, child_path, collapsed, on_toggle, cx)
+                })),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(summary(&json!({ "a": 1, "b": 2 })).as_deref(), Some("{2}"));
+        assert_eq!(summary(&json!([1, 2, 3])).as_deref(), Some("[3]"));
+        assert_eq!(summary(&json!("text")), None);
+    }
+}
@@ -1,8 +1,15 @@
 mod assets;
+mod attachments;
+mod batches;
 mod chat;
+mod conversation_settings;
+mod directory_options;
 mod handler;
+mod json_tree;
+mod models;
 mod services;
 mod theme;
+mod uploads;
 mod window;
 
 use gpui::{AppContext as _, Application, KeyBinding, actions};
@@ -12,6 +19,7 @@ use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt as _, util::Subscr
 
 use crate::{
     assets::Assets,
+    attachments::clean_pasted_images,
     chat::ChatAI,
     theme::change_color_mode,
     window::{blur_window, get_window_options},
@@ -38,6 +46,7 @@ fn init_logging() {
 
 fn main() {
     init_logging();
+    clean_pasted_images();
 
     // Create app w/ assets
     let app = Application::new().with_assets(Assets);
@@ -0,0 +1,63 @@
+use crate::services::agent::{LocalModel, ModelInfo, ProviderKind};
+use gpui::{
+    App, IntoElement, ParentElement as _, SharedString, Styled as _, Window,
+    prelude::FluentBuilder as _,
+};
+use gpui_component::{
+    ActiveTheme as _, Icon, Sizable as _, h_flex, label::Label, select::SelectItem,
+};
+
+/// A model entry in the model select
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelOption {
+    pub id: String,
+    pub name: SharedString,
+    pub provider: ProviderKind,
+    pub deprecated: bool,
+}
+
+impl ModelOption {
+    pub fn hosted(model: ModelInfo) -> Self {
+        Self {
+            id: model.id,
+            name: model.display_name.into(),
+            provider: model.provider,
+            deprecated: model.deprecated,
+        }
+    }
+
+    pub fn local(model: LocalModel) -> Self {
+        Self {
+            name: model.id.clone().into(),
+            id: model.id,
+            provider: model.provider,
+            deprecated: false,
+        }
+    }
+}
+
+impl SelectItem for ModelOption {
+    type Value = Self;
+
+    fn title(&self) -> SharedString {
+        self.name.clone()
+    }
+
+    fn render(&self, _: &mut Window, cx: &mut App) -> impl IntoElement {
+        h_flex()
+            .gap_2()
+            .child(Icon::empty().path(self.provider.icon_path()).xsmall())
+            .child(self.name.clone())
+            .when(self.deprecated, |d| {
+                d.child(
+                    Label::new("Deprecated")
+                        .text_xs()
+                        .text_color(cx.theme().muted_foreground),
+                )
+            })
+    }
+
+    fn value(&self) -> &Self::Value {
+        self
+    }
+}
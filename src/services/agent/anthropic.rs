@@ -0,0 +1,556 @@
+//! Anthropic Messages API provider.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+
+use super::http;
+use super::provider::{ChatRequest, ChatResponse, LlmProvider, ProviderKind, Usage};
+use super::sampling::SamplingParams;
+use super::types::{CacheControl, ContentBlock, Message, ToolChoice, ToolDefinition};
+
+const MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
+const COUNT_TOKENS_URL: &str = "https://api.anthropic.com/v1/messages/count_tokens";
+
+/// Recent user turns marked for caching. With the system prompt and tools this
+/// uses all four breakpoints the API allows.
+const MESSAGE_CACHE_BREAKPOINTS: usize = 2;
+
+// Anthropic API request/response types
+#[derive(Debug, Serialize)]
+pub(super) struct AnthropicRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<[SystemBlock<'a>; 1]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<ToolDefinition>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<&'a ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<ThinkingConfig>,
+    #[serde(flatten)]
+    sampling: &'a SamplingParams,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+/// The system prompt as a text block, so it can carry a cache breakpoint
+#[derive(Debug, Serialize)]
+struct SystemBlock<'a> {
+    #[serde(rename = "type")]
+    block_type: &'static str,
+    text: &'a str,
+    cache_control: CacheControl,
+}
+
+#[derive(Debug, Serialize)]
+struct ThinkingConfig {
+    #[serde(rename = "type")]
+    config_type: &'static str,
+    budget_tokens: u32,
+}
+
+/// The parts of a request that count toward input tokens
+#[derive(Debug, Serialize)]
+struct CountTokensRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tools: &'a [ToolDefinition],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<ThinkingConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CountTokensResponse {
+    input_tokens: u32,
+}
+
+impl<'a> AnthropicRequest<'a> {
+    /// Wire format of a chat request, with prompt caching breakpoints
+    pub(super) fn new(request: &'a ChatRequest) -> Self {
+        Self {
+            model: &request.model,
+            max_tokens: request.max_tokens,
+            messages: with_cache_breakpoints(&request.messages),
+            system: Some([SystemBlock {
+                block_type: "text",
+                text: &request.system_prompt,
+                cache_control: CacheControl::ephemeral(),
+            }])
+            .filter(|_| !request.system_prompt.is_empty()),
+            tools: if request.tools.is_empty() {
+                None
+            } else {
+                // A breakpoint on the last tool caches all of them
+                let mut tools = request.tools.clone();
+                if let Some(last) = tools.last_mut() {
+                    last.cache_control = Some(CacheControl::ephemeral());
+                }
+                Some(tools)
+            },
+            // The API rejects a tool choice without tools
+            tool_choice: request
+                .tool_choice
+                .as_ref()
+                .filter(|_| !request.tools.is_empty()),
+            thinking: request.thinking_budget.map(|budget_tokens| ThinkingConfig {
+                config_type: "enabled",
+                budget_tokens,
+            }),
+            sampling: &request.sampling,
+            stream: false,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    id: String,
+    #[serde(rename = "type")]
+    response_type: String,
+    role: String,
+    content: Vec<ContentBlock>,
+    model: String,
+    stop_reason: Option<String>,
+    usage: Usage,
+}
+
+/// An event of a streamed reply
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: StreamStart,
+    },
+    ContentBlockStart {
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    ContentBlockStop {
+        index: usize,
+    },
+    MessageDelta {
+        delta: StreamStop,
+        usage: StreamUsage,
+    },
+    Error {
+        error: Value,
+    },
+    /// `ping`, `message_stop` and anything newer
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamStart {
+    model: String,
+    usage: Usage,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamStop {
+    stop_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamUsage {
+    output_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    SignatureDelta {
+        signature: String,
+    },
+    /// Citations and anything newer
+    #[serde(other)]
+    Other,
+}
+
+/// A reply put together from its stream events
+#[derive(Debug, Default)]
+struct StreamedReply {
+    model: String,
+    /// Content blocks with the tool input JSON received so far
+    blocks: Vec<(ContentBlock, String)>,
+    stop_reason: Option<String>,
+    usage: Usage,
+}
+
+impl StreamedReply {
+    fn apply(&mut self, event: StreamEvent, on_text: &mut dyn FnMut(&str)) -> Result<()> {
+        match event {
+            StreamEvent::MessageStart { message } => {
+                self.model = message.model;
+                self.usage = message.usage;
+            }
+            StreamEvent::ContentBlockStart { content_block } => {
+                if let ContentBlock::Text { text, .. } = &content_block
+                    && !text.is_empty()
+                {
+                    on_text(text);
+                }
+                self.blocks.push((content_block, String::new()));
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => {
+                let (block, json) = self
+                    .blocks
+                    .get_mut(index)
+                    .ok_or_else(|| anyhow!("Stream delta for unknown block {}", index))?;
+                match (block, delta) {
+                    (ContentBlock::Text { text, .. }, BlockDelta::TextDelta { text: delta }) => {
+                        on_text(&delta);
+                        text.push_str(&delta);
+                    }
+                    (ContentBlock::ToolUse { .. }, BlockDelta::InputJsonDelta { partial_json }) => {
+                        json.push_str(&partial_json)
+                    }
+                    (
+                        ContentBlock::Thinking { thinking, .. },
+                        BlockDelta::ThinkingDelta { thinking: delta },
+                    ) => thinking.push_str(&delta),
+                    (
+                        ContentBlock::Thinking { signature, .. },
+                        BlockDelta::SignatureDelta { signature: delta },
+                    ) => signature.push_str(&delta),
+                    _ => {}
+                }
+            }
+            StreamEvent::ContentBlockStop { index } => {
+                if let Some((ContentBlock::ToolUse { input, .. }, json)) =
+                    self.blocks.get_mut(index)
+                    && !json.is_empty()
+                {
+                    *input = serde_json::from_str(json)
+                        .map_err(|e| anyhow!("Invalid tool input in reply: {}", e))?;
+                }
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                self.stop_reason = delta.stop_reason;
+                self.usage.output_tokens = usage.output_tokens;
+            }
+            StreamEvent::Error { error } => return Err(anyhow!("API error: {}", error)),
+            StreamEvent::Other => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> ChatResponse {
+        ChatResponse {
+            model: self.model,
+            content: self.blocks.into_iter().map(|(block, _)| block).collect(),
+            stop_reason: self.stop_reason,
+            usage: self.usage,
+        }
+    }
+}
+
+/// Provider for Claude models through the Messages API
+pub struct AnthropicProvider {
+    api_key: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: String) -> Self {
+        Self { api_key }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let api_key = env::var("ANTHROPIC_API_KEY")
+            .map_err(|_| anyhow!("ANTHROPIC_API_KEY environment variable not set"))?;
+        Ok(Self::new(api_key))
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        api_headers(&self.api_key)
+    }
+}
+
+/// Headers for JSON requests to the Anthropic API
+pub(super) fn api_headers(api_key: &str) -> Vec<(String, String)> {
+    vec![
+        ("x-api-key".to_string(), api_key.to_string()),
+        ("anthropic-version".to_string(), "2023-06-01".to_string()),
+        (
+            "anthropic-beta".to_string(),
+            "files-api-2025-04-14".to_string(),
+        ),
+        ("content-type".to_string(), "application/json".to_string()),
+    ]
+}
+
+/// Copy the conversation with breakpoints on the last cacheable block of the
+/// most recent user turns, so each request reuses the prefix cached by the last one.
+/// The stored conversation stays unmarked, so old breakpoints don't pile up.
+fn with_cache_breakpoints(messages: &[Message]) -> Vec<Message> {
+    let mut messages = messages.to_vec();
+    let user_turns = messages
+        .iter_mut()
+        .rev()
+        .filter_map(|message| match message {
+            Message::User { content, .. } => Some(content),
+            Message::Assistant { .. } => None,
+        });
+
+    for content in user_turns.take(MESSAGE_CACHE_BREAKPOINTS) {
+        // Stops at the last block that accepts a breakpoint
+        content
+            .iter_mut()
+            .rev()
+            .any(|block| block.set_cache_control(Some(CacheControl::ephemeral())));
+    }
+    messages
+}
+
+impl LlmProvider for AnthropicProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Anthropic
+    }
+
+    fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let request = AnthropicRequest::new(request);
+        let body = serde_json::to_string(&request)
+            .map_err(|e| anyhow!("Failed to serialize request: {}", e))?;
+
+        let response = smolhttp::Client::new(MESSAGES_URL)
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+            .post()
+            .headers(self.headers())
+            .body(body.into())
+            .send()
+            .map_err(|e| anyhow!("API request failed: {}", e))?;
+
+        let response_text = response.text();
+
+        if response_text.contains("\"error\"") && response_text.contains("\"type\"") {
+            return Err(anyhow!("API error: {}", response_text));
+        }
+
+        let api_response: AnthropicResponse =
+            serde_json::from_str(&response_text).map_err(|e| {
+                anyhow!(
+                    "Failed to parse response: {}. Response: {}",
+                    e,
+                    response_text
+                )
+            })?;
+
+        Ok(ChatResponse {
+            model: api_response.model,
+            content: api_response.content,
+            stop_reason: api_response.stop_reason,
+            usage: api_response.usage,
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<ChatResponse> {
+        let mut request = AnthropicRequest::new(request);
+        request.stream = true;
+        let body = serde_json::to_vec(&request)
+            .map_err(|e| anyhow!("Failed to serialize request: {}", e))?;
+
+        let mut response = http::send_streaming("POST", MESSAGES_URL, self.headers(), body)
+            .map_err(|e| anyhow!("API request failed: {}", e))?;
+        let status = response.status_code();
+        if status >= 400 {
+            return Err(anyhow!("API error ({}): {}", status, response.text()));
+        }
+
+        let mut reply = StreamedReply::default();
+        while let Some(data) = response.next_event()? {
+            let event: StreamEvent = serde_json::from_str(&data)
+                .map_err(|e| anyhow!("Failed to parse stream event: {}. Event: {}", e, data))?;
+            reply.apply(event, on_text)?;
+        }
+        if reply.stop_reason.is_none() {
+            return Err(anyhow!("The reply stream ended early"));
+        }
+        Ok(reply.finish())
+    }
+
+    fn count_tokens(&self, request: &ChatRequest) -> Result<Option<u32>> {
+        let request = CountTokensRequest {
+            model: &request.model,
+            messages: &request.messages,
+            system: Some(request.system_prompt.as_str()).filter(|s| !s.is_empty()),
+            tools: &request.tools,
+            thinking: request.thinking_budget.map(|budget_tokens| ThinkingConfig {
+                config_type: "enabled",
+                budget_tokens,
+            }),
+        };
+        let body = serde_json::to_string(&request)
+            .map_err(|e| anyhow!("Failed to serialize request: {}", e))?;
+
+        let response = smolhttp::Client::new(COUNT_TOKENS_URL)
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+            .post()
+            .headers(self.headers())
+            .body(body.into())
+            .send()
+            .map_err(|e| anyhow!("Token count request failed: {}", e))?;
+
+        let response_text = response.text();
+        let count: CountTokensResponse = serde_json::from_str(&response_text)
+            .map_err(|_| anyhow!("Token count error: {}", response_text))?;
+        Ok(Some(count.input_tokens))
+    }
+
+    fn supports_file_uploads(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_breakpoints_on_recent_user_turns() {
+        let user = |content| Message::User {
+            role: "user".to_string(),
+            content,
+        };
+        let assistant = Message::Assistant {
+            role: "assistant".to_string(),
+            content: vec![ContentBlock::text("Hi")],
+        };
+        let tool_result = ContentBlock::ToolResult {
+            tool_use_id: "toolu_1".to_string(),
+            content: "42".to_string(),
+            is_error: None,
+            cache_control: None,
+        };
+        let messages = vec![
+            user(vec![ContentBlock::text("First")]),
+            assistant.clone(),
+            user(vec![tool_result]),
+            assistant,
+            user(vec![ContentBlock::text("Last")]),
+        ];
+
+        let cached = |message: &Message| match message {
+            Message::User { content, .. } => serde_json::to_value(content.last())
+                .unwrap()
+                .get("cache_control")
+                .is_some(),
+            Message::Assistant { .. } => false,
+        };
+        let marked: Vec<bool> = with_cache_breakpoints(&messages)
+            .iter()
+            .map(cached)
+            .collect();
+        assert_eq!(marked, [false, false, true, false, true]);
+        assert!(!messages.iter().any(cached));
+    }
+
+    #[test]
+    fn test_tool_choice_wire_format() {
+        let wire = |choice: &ToolChoice| serde_json::to_value(choice).unwrap();
+        assert_eq!(
+            wire(&ToolChoice::Auto {
+                disable_parallel_tool_use: false
+            }),
+            json!({ "type": "auto" })
+        );
+        assert_eq!(
+            wire(&ToolChoice::Any {
+                disable_parallel_tool_use: true
+            }),
+            json!({ "type": "any", "disable_parallel_tool_use": true })
+        );
+        assert_eq!(
+            wire(&ToolChoice::tool("weather")),
+            json!({ "type": "tool", "name": "weather" })
+        );
+        assert_eq!(
+            ToolChoice::tool("weather").relaxed(),
+            ToolChoice::Auto {
+                disable_parallel_tool_use: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_streamed_reply() {
+        let events = [
+            json!({ "type": "message_start", "message": {
+                "model": "claude-sonnet-4-5",
+                "usage": { "input_tokens": 12, "output_tokens": 1 }
+            } }),
+            json!({ "type": "content_block_start", "index": 0,
+                "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "ping" }),
+            json!({ "type": "content_block_delta", "index": 0,
+                "delta": { "type": "text_delta", "text": "Let me " } }),
+            json!({ "type": "content_block_delta", "index": 0,
+                "delta": { "type": "text_delta", "text": "check." } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": {
+                "type": "tool_use", "id": "toolu_1", "name": "weather", "input": {}
+            } }),
+            json!({ "type": "content_block_delta", "index": 1,
+                "delta": { "type": "input_json_delta", "partial_json": "{\"city\": " } }),
+            json!({ "type": "content_block_delta", "index": 1,
+                "delta": { "type": "input_json_delta", "partial_json": "\"Oslo\"}" } }),
+            json!({ "type": "content_block_stop", "index": 1 }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" },
+                "usage": { "output_tokens": 30 } }),
+            json!({ "type": "message_stop" }),
+        ];
+        let mut streamed = Vec::new();
+        let mut reply = StreamedReply::default();
+        for event in events {
+            let event = serde_json::from_value(event).unwrap();
+            reply
+                .apply(event, &mut |text| streamed.push(text.to_string()))
+                .unwrap();
+        }
+        let response = reply.finish();
+
+        assert_eq!(streamed, ["Let me ", "check."]);
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(response.usage.input_tokens, 12);
+        assert_eq!(response.usage.output_tokens, 30);
+        assert!(matches!(
+            &response.content[0],
+            ContentBlock::Text { text, .. } if text == "Let me check."
+        ));
+        assert!(matches!(
+            &response.content[1],
+            ContentBlock::ToolUse { input, .. } if input == &json!({ "city": "Oslo" })
+        ));
+
+        let error = serde_json::from_value(json!({
+            "type": "error",
+            "error": { "type": "overloaded_error", "message": "Overloaded" }
+        }))
+        .unwrap();
+        assert!(StreamedReply::default().apply(error, &mut |_| {}).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+//! Message Batches API client for running one prompt over many inputs.
+//!
+//! Each input becomes its own request, built from a prompt template. Batches
+//! run asynchronously on the server, so the UI polls them until they end and
+//! then fetches the results.
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::PathBuf;
+
+use super::anthropic::{AnthropicRequest, api_headers};
+use super::provider::ChatRequest;
+use super::types::ContentBlock;
+
+const BATCHES_URL: &str = "https://api.anthropic.com/v1/messages/batches";
+
+/// Placeholder in a prompt template replaced by each input
+pub const INPUT_PLACEHOLDER: &str = "{{input}}";
+
+/// One item a batch prompt runs over
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BatchInput {
+    /// A file attached to the prompt; its name fills the placeholder
+    File(PathBuf),
+    /// A line of text that fills the placeholder
+    Text(String),
+}
+
+impl BatchInput {
+    /// Short name shown in the results table
+    pub fn label(&self) -> String {
+        match self {
+            BatchInput::File(path) => path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string()),
+            BatchInput::Text(text) => text.clone(),
+        }
+    }
+}
+
+/// Fill a prompt template with an input. Templates without the placeholder
+/// get the input appended.
+pub fn render_template(template: &str, input: &str) -> String {
+    if template.contains(INPUT_PLACEHOLDER) {
+        template.replace(INPUT_PLACEHOLDER, input)
+    } else {
+        format!("{}\n\n{}", template.trim_end(), input)
+    }
+}
+
+/// ID of the request for the input at `ix`, so results can be matched back
+fn custom_id(ix: usize) -> String {
+    format!("input-{}", ix)
+}
+
+fn input_index(custom_id: &str) -> Option<usize> {
+    custom_id.strip_prefix("input-")?.parse().ok()
+}
+
+/// Requests in each state, as reported by the API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestCounts {
+    pub processing: u32,
+    pub succeeded: u32,
+    pub errored: u32,
+    pub canceled: u32,
+    pub expired: u32,
+}
+
+/// A message batch on the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchJob {
+    pub id: String,
+    /// `in_progress`, `canceling` or `ended`
+    pub processing_status: String,
+    pub request_counts: RequestCounts,
+    pub created_at: DateTime<Utc>,
+    pub results_url: Option<String>,
+}
+
+impl BatchJob {
+    pub fn is_ended(&self) -> bool {
+        self.processing_status == "ended"
+    }
+}
+
+/// How a single request in a batch finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchResultStatus {
+    Succeeded,
+    Errored,
+    Canceled,
+    Expired,
+}
+
+impl BatchResultStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchResultStatus::Succeeded => "succeeded",
+            BatchResultStatus::Errored => "errored",
+            BatchResultStatus::Canceled => "canceled",
+            BatchResultStatus::Expired => "expired",
+        }
+    }
+}
+
+/// The outcome of one request in a batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub custom_id: String,
+    pub status: BatchResultStatus,
+    /// The reply text, or the error message
+    pub output: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResultLine {
+    custom_id: String,
+    result: ResultBody,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResultBody {
+    Succeeded { message: ResultMessage },
+    Errored { error: Value },
+    Canceled,
+    Expired,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResultMessage {
+    content: Vec<ContentBlock>,
+}
+
+impl From<ResultLine> for BatchResult {
+    fn from(line: ResultLine) -> Self {
+        let (status, output) = match line.result {
+            ResultBody::Succeeded { message } => {
+                let text: Vec<String> = message
+                    .content
+                    .into_iter()
+                    .filter_map(|block| match block {
+                        ContentBlock::Text { text, .. } => Some(text),
+                        _ => None,
+                    })
+                    .collect();
+                (BatchResultStatus::Succeeded, text.join("\n"))
+            }
+            ResultBody::Errored { error } => {
+                let message = error
+                    .pointer("/error/message")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| error.to_string());
+                (BatchResultStatus::Errored, message)
+            }
+            ResultBody::Canceled => (BatchResultStatus::Canceled, String::new()),
+            ResultBody::Expired => (BatchResultStatus::Expired, String::new()),
+        };
+        Self {
+            custom_id: line.custom_id,
+            status,
+            output,
+        }
+    }
+}
+
+/// A submitted batch with its inputs and, once it has ended, its results
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchRun {
+    pub job: BatchJob,
+    pub inputs: Vec<BatchInput>,
+    pub results: Vec<BatchResult>,
+}
+
+/// A results table row
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchRow {
+    pub input: String,
+    pub status: BatchResultStatus,
+    pub output: String,
+}
+
+impl BatchRun {
+    /// Results in input order, labelled with their inputs
+    pub fn rows(&self) -> Vec<BatchRow> {
+        let mut results: Vec<&BatchResult> = self.results.iter().collect();
+        results.sort_by_key(|r| input_index(&r.custom_id));
+        results
+            .into_iter()
+            .map(|result| BatchRow {
+                input: input_index(&result.custom_id)
+                    .and_then(|ix| self.inputs.get(ix))
+                    .map(BatchInput::label)
+                    .unwrap_or_else(|| result.custom_id.clone()),
+                status: result.status,
+                output: result.output.clone(),
+            })
+            .collect()
+    }
+
+    /// Results as CSV with a header row
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("input,status,output\n");
+        for row in self.rows() {
+            out.push_str(&format!(
+                "{},{},{}\n",
+                csv_field(&row.input),
+                row.status.as_str(),
+                csv_field(&row.output)
+            ));
+        }
+        out
+    }
+
+    /// Results as JSON Lines, one object per input
+    pub fn to_jsonl(&self) -> String {
+        self.rows()
+            .iter()
+            .map(|row| json!(row).to_string() + "\n")
+            .collect()
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parse an API response, turning error bodies into errors
+fn parse_response<T: serde::de::DeserializeOwned>(response_text: &str, what: &str) -> Result<T> {
+    if response_text.contains("\"error\"") && response_text.contains("\"type\"") {
+        return Err(anyhow!("{} error: {}", what, response_text));
+    }
+    serde_json::from_str(response_text).map_err(|e| {
+        anyhow!(
+            "Failed to parse {}: {}. Response: {}",
+            what.to_lowercase(),
+            e,
+            response_text
+        )
+    })
+}
+
+/// Submit one request per input. `requests` are in input order.
+pub fn create_batch(api_key: &str, requests: &[ChatRequest]) -> Result<BatchJob> {
+    let requests: Vec<Value> = requests
+        .iter()
+        .enumerate()
+        .map(|(ix, request)| {
+            json!({
+                "custom_id": custom_id(ix),
+                "params": AnthropicRequest::new(request),
+            })
+        })
+        .collect();
+    let body = json!({ "requests": requests }).to_string();
+
+    let response = smolhttp::Client::new(BATCHES_URL)
+        .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+        .post()
+        .headers(api_headers(api_key))
+        .body(body.into())
+        .send()
+        .map_err(|e| anyhow!("Batch request failed: {}", e))?;
+
+    let job: BatchJob = parse_response(&response.text(), "Batch")?;
+    tracing::debug!("Created batch {} with {} requests", job.id, requests.len());
+    Ok(job)
+}
+
+/// Fetch the current status of a batch
+pub fn get_batch(api_key: &str, batch_id: &str) -> Result<BatchJob> {
+    let response = smolhttp::Client::new(&format!("{}/{}", BATCHES_URL, batch_id))
+        .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+        .get()
+        .headers(api_headers(api_key))
+        .send()
+        .map_err(|e| anyhow!("Batch status request failed: {}", e))?;
+
+    parse_response(&response.text(), "Batch")
+}
+
+/// Download the results of an ended batch
+pub fn batch_results(api_key: &str, job: &BatchJob) -> Result<Vec<BatchResult>> {
+    let url = job
+        .results_url
+        .as_deref()
+        .ok_or_else(|| anyhow!("Batch {} has no results yet", job.id))?;
+
+    let response = smolhttp::Client::new(url)
+        .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+        .get()
+        .headers(api_headers(api_key))
+        .send()
+        .map_err(|e| anyhow!("Batch results request failed: {}", e))?;
+
+    parse_results(&response.text())
+}
+
+/// Parse the JSON Lines results file
+fn parse_results(text: &str) -> Result<Vec<BatchResult>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str::<ResultLine>(line)
+                .map(BatchResult::from)
+                .map_err(|e| anyhow!("Failed to parse batch result: {}. Line: {}", e, line))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_table_and_export() {
+        assert_eq!(
+            render_template("Summarize {{input}}.", "a.pdf"),
+            "Summarize a.pdf."
+        );
+        assert_eq!(
+            render_template("Translate:\n", "hola"),
+            "Translate:\n\nhola"
+        );
+
+        let results = parse_results(concat!(
+            r#"{"custom_id":"input-1","result":{"type":"errored","error":{"type":"error","#,
+            r#""error":{"type":"invalid_request_error","message":"Too long"}}}}"#,
+            "\n",
+            r#"{"custom_id":"input-0","result":{"type":"succeeded","message":{"id":"msg_1","#,
+            r#""content":[{"type":"text","text":"Hello, \"world\""}]}}}"#,
+            "\n"
+        ))
+        .unwrap();
+
+        let run = BatchRun {
+            job: BatchJob {
+                id: "msgbatch_1".to_string(),
+                processing_status: "ended".to_string(),
+                request_counts: RequestCounts::default(),
+                created_at: Utc::now(),
+                results_url: None,
+            },
+            inputs: vec![
+                BatchInput::File(PathBuf::from("/tmp/report.pdf")),
+                BatchInput::Text("second".to_string()),
+            ],
+            results,
+        };
+
+        assert_eq!(
+            run.to_csv(),
+            "input,status,output\nreport.pdf,succeeded,\"Hello, \"\"world\"\"\"\n\
+             second,errored,Too long\n"
+        );
+        assert!(run.to_jsonl().starts_with(r#"{"input":"report.pdf","#));
+    }
+}
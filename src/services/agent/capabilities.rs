@@ -0,0 +1,164 @@
+//! What each model can do: context window, output limit, and input types,
+//! plus input pricing for cost estimates.
+//!
+//! Looked up by model ID prefix so dated snapshots share an entry.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::files::get_mime_type;
+
+/// Output limit used when none is requested, capped by the model's maximum.
+/// Large enough for long answers without waiting minutes on a non-streamed response.
+const DEFAULT_MAX_TOKENS: u32 = 8_192;
+
+/// Smallest extended thinking budget the API accepts
+pub const MIN_THINKING_BUDGET: u32 = 1_024;
+
+/// Limits and features of a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelCapabilities {
+    /// Total tokens the model can attend to, input and output combined
+    pub context_window: u32,
+    /// Largest `max_tokens` the model accepts
+    pub max_output_tokens: u32,
+    /// Accepts image blocks
+    pub vision: bool,
+    /// Accepts PDF document blocks
+    pub pdf: bool,
+    /// Supports extended thinking
+    pub thinking: bool,
+}
+
+impl ModelCapabilities {
+    /// Assumed for models missing from the catalog, such as local ones.
+    /// Images are allowed so local vision models work; the server rejects them otherwise.
+    pub const UNKNOWN: Self = Self {
+        context_window: 8_192,
+        max_output_tokens: 4_096,
+        vision: true,
+        pdf: false,
+        thinking: false,
+    };
+
+    /// The output limit to send: the requested value, or the default,
+    /// kept within what the model accepts
+    pub fn max_tokens(&self, requested: Option<u32>) -> u32 {
+        requested
+            .unwrap_or(DEFAULT_MAX_TOKENS)
+            .min(self.max_output_tokens)
+    }
+
+    /// Why a file can't be sent to this model, if it can't
+    pub fn unsupported_reason(&self, path: &Path) -> Option<&'static str> {
+        let mime_type = get_mime_type(path);
+        if mime_type.starts_with("image/") && !self.vision {
+            Some("doesn't accept images")
+        } else if mime_type == "application/pdf" && !self.pdf {
+            Some("doesn't accept PDFs")
+        } else {
+            None
+        }
+    }
+}
+
+const fn caps(
+    context_window: u32,
+    max_output_tokens: u32,
+    vision: bool,
+    pdf: bool,
+    thinking: bool,
+) -> ModelCapabilities {
+    ModelCapabilities {
+        context_window,
+        max_output_tokens,
+        vision,
+        pdf,
+        thinking,
+    }
+}
+
+/// Capabilities keyed by model ID prefix; more specific prefixes come first
+const CATALOG: &[(&str, ModelCapabilities)] = &[
+    ("claude-opus-4-5", caps(200_000, 64_000, true, true, true)),
+    ("claude-sonnet-4-5", caps(200_000, 64_000, true, true, true)),
+    ("claude-haiku-4-5", caps(200_000, 64_000, true, true, true)),
+    ("claude-opus-4-1", caps(200_000, 32_000, true, true, true)),
+    ("claude-opus-4", caps(200_000, 32_000, true, true, true)),
+    ("claude-sonnet-4", caps(200_000, 64_000, true, true, true)),
+    ("claude-3-7-sonnet", caps(200_000, 64_000, true, true, true)),
+    ("claude-3-5-sonnet", caps(200_000, 8_192, true, true, false)),
+    ("claude-3-5-haiku", caps(200_000, 8_192, true, true, false)),
+    ("claude-3-opus", caps(200_000, 4_096, true, false, false)),
+    ("claude-3-haiku", caps(200_000, 4_096, true, false, false)),
+    // Claude models newer than this list
+    ("claude-", caps(200_000, 32_000, true, true, true)),
+    ("gpt-4.1", caps(1_047_576, 32_768, true, true, false)),
+    ("gpt-4o", caps(128_000, 16_384, true, true, false)),
+];
+
+/// Input price in US dollars per million tokens, keyed by model ID prefix
+const INPUT_PRICES: &[(&str, f64)] = &[
+    ("claude-opus-4-5", 5.0),
+    ("claude-opus-4", 15.0),
+    ("claude-sonnet-4", 3.0),
+    ("claude-haiku-4-5", 1.0),
+    ("claude-3-7-sonnet", 3.0),
+    ("claude-3-5-sonnet", 3.0),
+    ("claude-3-5-haiku", 0.8),
+    ("claude-3-opus", 15.0),
+    ("claude-3-haiku", 0.25),
+    ("gpt-4.1", 2.0),
+    ("gpt-4o-mini", 0.15),
+    ("gpt-4o", 2.5),
+];
+
+/// What sending this many input tokens to the model costs, if the price is known
+pub fn input_cost(model_id: &str, tokens: u32) -> Option<f64> {
+    INPUT_PRICES
+        .iter()
+        .find(|(prefix, _)| model_id.starts_with(prefix))
+        .map(|(_, price)| price * tokens as f64 / 1_000_000.0)
+}
+
+/// Whether the model is in the catalog, so its limits are known rather than assumed
+pub fn is_cataloged(model_id: &str) -> bool {
+    CATALOG
+        .iter()
+        .any(|(prefix, _)| model_id.starts_with(prefix))
+}
+
+/// Look up a model's capabilities
+pub fn capabilities(model_id: &str) -> ModelCapabilities {
+    CATALOG
+        .iter()
+        .find(|(prefix, _)| model_id.starts_with(prefix))
+        .map(|(_, caps)| *caps)
+        .unwrap_or(ModelCapabilities::UNKNOWN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_by_prefix() {
+        let opus = capabilities("claude-opus-4-1-20250805");
+        assert_eq!(opus.max_output_tokens, 32_000);
+        assert!(opus.thinking);
+
+        let haiku = capabilities("claude-3-haiku-20240307");
+        assert!(!haiku.pdf);
+        assert_eq!(haiku.max_tokens(None), 4_096);
+        assert_eq!(opus.max_tokens(None), DEFAULT_MAX_TOKENS);
+        assert_eq!(opus.max_tokens(Some(64_000)), 32_000);
+
+        let newer = capabilities("claude-sonnet-5-20270101");
+        assert_eq!(newer.context_window, 200_000);
+        assert!(newer.thinking);
+
+        assert_eq!(capabilities("llama3.2:latest"), ModelCapabilities::UNKNOWN);
+        assert_eq!(input_cost("gpt-4o-mini", 1_000_000), Some(0.15));
+        assert_eq!(input_cost("llama3.2:latest", 1_000), None);
+    }
+}
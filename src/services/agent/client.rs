@@ -1,69 +1,76 @@
-//! Agent client for communicating with the Anthropic API.
+//! Agent client for communicating with an LLM provider.
 
 use anyhow::{Result, anyhow};
-use serde::{Deserialize, Serialize};
-use std::env;
-
-use super::messages::{AgentResponse, ToolCallData, ToolResultData};
-use super::types::{ContentBlock, Message, Tool, ToolDefinition};
+use async_channel::Sender;
+use serde_json::Value;
+use std::{sync::Arc, time::Instant};
+
+use super::anthropic::AnthropicProvider;
+use super::capabilities::{
+    MIN_THINKING_BUDGET, ModelCapabilities, capabilities, input_cost, is_cataloged,
+};
+use super::context::{
+    COMPACT_RATIO, ContextUsage, SUMMARY_PROMPT, TokenCount, compaction_split,
+    estimate_block_tokens, estimate_messages_tokens, estimate_preamble_tokens, summary_message,
+    transcript,
+};
+use super::messages::{AgentResponse, ThoughtData, ToolCallData, ToolResultData};
+use super::model_list::DEFAULT_MODEL;
+use super::provider::{ChatRequest, ChatResponse, LlmProvider, Usage};
+use super::sampling::SamplingParams;
+use super::structured::{STRUCTURED_TOOL_DESCRIPTION, STRUCTURED_TOOL_NAME, validate};
+use super::types::{ContentBlock, Message, Tool, ToolChoice, ToolDefinition};
 
 /// Agent that can converse with an LLM and execute tools
 #[derive(Clone)]
 pub struct Agent {
-    api_key: String,
+    provider: Arc<dyn LlmProvider>,
     model: String,
     system_prompt: String,
     tools: Vec<Tool>,
     conversation: Vec<Message>,
-    max_tokens: u32,
-}
-
-// Anthropic API request/response types
-#[derive(Debug, Serialize)]
-struct AnthropicRequest {
-    model: String,
-    max_tokens: u32,
-    messages: Vec<Message>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    system: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<ToolDefinition>>,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
-struct AnthropicResponse {
-    id: String,
-    #[serde(rename = "type")]
-    response_type: String,
-    role: String,
-    content: Vec<ContentBlock>,
-    model: String,
-    stop_reason: Option<String>,
-    usage: Usage,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
-struct Usage {
-    input_tokens: u32,
-    output_tokens: u32,
+    /// Requested output limit; `None` uses the model's default
+    max_tokens: Option<u32>,
+    /// Extended thinking budget; `None` disables thinking
+    thinking_budget: Option<u32>,
+    sampling: SamplingParams,
+    /// Names of the tools offered to the model; `None` offers all of them
+    enabled_tools: Option<Vec<String>>,
+    /// JSON Schema replies must match; `None` allows free-form replies
+    output_schema: Option<Value>,
+    /// How the model may use tools; `None` leaves it to the provider's default
+    tool_choice: Option<ToolChoice>,
+    /// Tokens reported for the last request and response, with the conversation
+    /// length they cover
+    reported_tokens: Option<(u32, usize)>,
+    /// Usage of the last request, for its prompt cache counts
+    last_usage: Usage,
+    /// Context window reported by a local server, overriding the catalog
+    context_window: Option<u32>,
+    /// Where reply text is sent as it streams in; `None` waits for whole replies
+    text_tx: Option<Sender<AgentResponse>>,
 }
 
 #[allow(dead_code)]
 impl Agent {
     /// Create a new agent with the given tools
     pub fn new(tools: Vec<Tool>) -> Result<Self> {
-        let api_key = env::var("ANTHROPIC_API_KEY")
-            .map_err(|_| anyhow!("ANTHROPIC_API_KEY environment variable not set"))?;
-
         Ok(Self {
-            api_key,
-            model: "claude-haiku-4-5-20251001".to_string(),
+            provider: Arc::new(AnthropicProvider::from_env()?),
+            model: DEFAULT_MODEL.to_string(),
             system_prompt: Self::default_system_prompt(),
             tools,
             conversation: Vec::new(),
-            max_tokens: 4096,
+            max_tokens: None,
+            thinking_budget: None,
+            sampling: SamplingParams::default(),
+            enabled_tools: None,
+            output_schema: None,
+            tool_choice: None,
+            reported_tokens: None,
+            last_usage: Usage::default(),
+            context_window: None,
+            text_tx: None,
         })
     }
 
@@ -85,21 +92,100 @@ impl Agent {
         self.system_prompt = prompt;
     }
 
+    /// The model conversations are sent to
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
     /// Set the model
     pub fn set_model(&mut self, model: String) {
         self.model = model;
+        self.context_window = None;
+    }
+
+    /// Use the context window a local server reported for the current model
+    pub fn set_context_window(&mut self, context_window: Option<u32>) {
+        self.context_window = context_window;
+    }
+
+    /// Enable extended thinking with the given budget, or disable it with `None`
+    pub fn set_thinking_budget(&mut self, budget: Option<u32>) {
+        self.thinking_budget = budget;
+    }
+
+    /// Override the sampling defaults, checked before each request
+    pub fn set_sampling(&mut self, sampling: SamplingParams) {
+        self.sampling = sampling;
+    }
+
+    /// Stream reply text to `tx` as `TextDelta`s before each whole reply
+    pub fn stream_text_to(&mut self, tx: Sender<AgentResponse>) {
+        self.text_tx = Some(tx);
+    }
+
+    /// Replace the tools the agent can offer to the model
+    pub fn set_tools(&mut self, tools: Vec<Tool>) {
+        self.tools = tools;
+    }
+
+    /// Limit the tools offered to the model, or offer all of them with `None`
+    pub fn set_enabled_tools(&mut self, tools: Option<Vec<String>>) {
+        self.enabled_tools = tools;
+    }
+
+    /// The budget actually used: `None` when thinking is off or unsupported by the model
+    fn active_thinking_budget(&self) -> Option<u32> {
+        self.thinking_budget
+            .filter(|_| self.capabilities().thinking)
+    }
+
+    /// Require replies to match a JSON Schema, or allow free-form replies with `None`
+    pub fn set_output_schema(&mut self, schema: Option<Value>) {
+        self.output_schema = schema;
+    }
+
+    pub fn output_schema(&self) -> Option<&Value> {
+        self.output_schema.as_ref()
+    }
+
+    /// Set how the model may use tools, or use the provider's default with `None`
+    pub fn set_tool_choice(&mut self, tool_choice: Option<ToolChoice>) {
+        self.tool_choice = tool_choice;
+    }
+
+    pub fn tool_choice(&self) -> Option<&ToolChoice> {
+        self.tool_choice.as_ref()
+    }
+
+    /// What the current model supports
+    pub fn capabilities(&self) -> ModelCapabilities {
+        let mut capabilities = capabilities(&self.model);
+        if let Some(context_window) = self.context_window {
+            capabilities.context_window = context_window;
+        }
+        capabilities
+    }
+
+    /// Switch the backend used for inference
+    pub fn set_provider(&mut self, provider: Arc<dyn LlmProvider>) {
+        self.provider = provider;
+    }
+
+    /// The backend used for inference
+    pub fn provider(&self) -> &Arc<dyn LlmProvider> {
+        &self.provider
     }
 
     /// Set max tokens
     pub fn set_max_tokens(&mut self, max_tokens: u32) {
-        self.max_tokens = max_tokens;
+        self.max_tokens = Some(max_tokens);
     }
 
     /// Add a user message to the conversation
     pub fn add_user_message(&mut self, content: String) {
         self.conversation.push(Message::User {
             role: "user".to_string(),
-            content: vec![ContentBlock::Text { text: content }],
+            content: vec![ContentBlock::text(content)],
         });
     }
 
@@ -111,14 +197,30 @@ impl Agent {
         });
     }
 
+    /// Remember the tokens reported for the conversation so far
+    fn record_usage(&mut self, usage: Usage) {
+        let reported = usage.input_tokens
+            + usage.cache_creation_input_tokens
+            + usage.cache_read_input_tokens
+            + usage.output_tokens;
+        self.reported_tokens = Some((reported, self.conversation.len()));
+        self.last_usage = usage;
+    }
+
     /// Get all tool definitions in a format suitable for the LLM
     pub fn get_tool_definitions(&self) -> Vec<ToolDefinition> {
         self.tools
             .iter()
+            .filter(|tool| {
+                self.enabled_tools
+                    .as_ref()
+                    .is_none_or(|enabled| enabled.contains(&tool.name))
+            })
             .map(|tool| ToolDefinition {
                 name: tool.name.clone(),
                 description: tool.description.clone(),
                 input_schema: tool.input_schema.clone(),
+                cache_control: None,
             })
             .collect()
     }
@@ -126,22 +228,66 @@ impl Agent {
     /// Process a single step in the conversation
     /// Returns either tool calls that need execution, or a final text response
     pub async fn chat_step(&mut self, user_content: Vec<ContentBlock>) -> Result<AgentResponse> {
+        self.chat_step_with_tool_choice(user_content, None).await
+    }
+
+    /// Process a single step, overriding the agent's tool choice for it
+    /// unless `tool_choice` is `None`
+    pub async fn chat_step_with_tool_choice(
+        &mut self,
+        user_content: Vec<ContentBlock>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<AgentResponse> {
+        let tool_choice = tool_choice.or_else(|| self.tool_choice.clone());
+
         // Add user message
         self.conversation.push(Message::User {
             role: "user".to_string(),
             content: user_content,
         });
 
+        let response = self.run_step(tool_choice).await;
+        // Remove the failed user message from conversation, unless it was answered
+        if response.is_err() && matches!(self.conversation.last(), Some(Message::User { .. })) {
+            self.conversation.pop();
+        }
+        response
+    }
+
+    /// Send the results of the last reply's tool calls and run the next step.
+    /// A forced tool choice is relaxed to `auto` so the model can finish its turn.
+    /// The results stay in the conversation even if the step fails, so every
+    /// tool call keeps its answer.
+    pub async fn continue_with_tool_results(
+        &mut self,
+        results: Vec<ToolResultData>,
+    ) -> Result<AgentResponse> {
+        self.submit_tool_results(results);
+        let tool_choice = self.tool_choice.as_ref().map(ToolChoice::relaxed);
+        self.run_step(tool_choice).await
+    }
+
+    /// Run inference on the conversation as it stands and record the reply
+    async fn run_step(&mut self, tool_choice: Option<ToolChoice>) -> Result<AgentResponse> {
+        let mut agent_clone = self.clone_for_inference_with(tool_choice);
+        let request = &agent_clone.request;
+        request.sampling.validate(
+            request.thinking_budget.is_some(),
+            Some(self.provider.kind()),
+        )?;
+        let forces_tool_use = request
+            .tool_choice
+            .as_ref()
+            .is_some_and(ToolChoice::forces_tool_use);
+        if forces_tool_use && request.tools.is_empty() {
+            return Err(anyhow!("Tool use is required, but no tools are enabled"));
+        }
+
         // Run inference in a blocking task since smolhttp is synchronous
-        let mut agent_clone = self.clone_for_inference();
-        let response = match smol::unblock(move || agent_clone.run_inference()).await {
-            Ok(response) => response,
-            Err(e) => {
-                // Remove the failed user message from conversation
-                self.conversation.pop();
-                return Err(e);
-            }
-        };
+        let started = Instant::now();
+        let text_tx = self.text_tx.clone();
+        let (response, first_text) =
+            smol::unblock(move || agent_clone.run_inference(text_tx)).await?;
 
         tracing::debug!(
             usage = ?response.usage,
@@ -152,14 +298,17 @@ impl Agent {
 
         // Add assistant response to conversation
         self.add_assistant_message(response.content.clone());
+        self.record_usage(response.usage);
 
         // Parse the response content
         let mut tool_calls = Vec::new();
         let mut text_response = String::new();
+        let mut thinking = Vec::new();
+        let mut redacted = false;
 
         for block in &response.content {
             match block {
-                ContentBlock::Text { text } => {
+                ContentBlock::Text { text, .. } => {
                     text_response = text.clone();
                     tracing::debug!("ContentBlock::Text: {}", text);
                 }
@@ -174,12 +323,25 @@ impl Agent {
                 ContentBlock::ToolResult { .. } => {
                     tracing::debug!("ContentBlock::ToolResult: shouldn't happen");
                 }
-                ContentBlock::Document { .. } => {
-                    tracing::debug!("ContentBlock::Document: shouldn't happen");
+                ContentBlock::Document { .. } | ContentBlock::Image { .. } => {
+                    tracing::debug!("ContentBlock::Document/Image: shouldn't happen");
                 }
+                ContentBlock::Thinking { thinking: text, .. } => thinking.push(text.clone()),
+                ContentBlock::RedactedThinking { .. } => redacted = true,
             }
         }
 
+        // Thinking comes before the text, so it ends when the first text arrives.
+        // Without text, the whole request time stands in for it.
+        let thought = (!thinking.is_empty() || redacted).then(|| ThoughtData {
+            text: thinking.join("\n\n"),
+            redacted,
+            duration_secs: first_text
+                .unwrap_or_else(Instant::now)
+                .duration_since(started)
+                .as_secs(),
+        });
+
         if !tool_calls.is_empty() {
             return Ok(AgentResponse::ToolCallRequest {
                 text: if text_response.is_empty() {
@@ -189,6 +351,7 @@ impl Agent {
                 },
                 tool_calls,
                 stop_reason: response.stop_reason,
+                thought,
             });
         }
 
@@ -196,12 +359,103 @@ impl Agent {
             return Ok(AgentResponse::TextResponse {
                 text: text_response,
                 stop_reason: response.stop_reason,
+                thought,
             });
         }
 
         Err(anyhow!("No text or tool calls in assistant response"))
     }
 
+    /// Run a turn whose reply must match the output schema. The model is made
+    /// to call a tool taking the schema as input. An invalid reply is sent back
+    /// with the validation errors once before giving up.
+    pub async fn structured_step(
+        &mut self,
+        user_content: Vec<ContentBlock>,
+    ) -> Result<AgentResponse> {
+        let schema = self
+            .output_schema
+            .clone()
+            .ok_or_else(|| anyhow!("No output schema set"))?;
+        self.sampling.validate(false, Some(self.provider.kind()))?;
+
+        let AgentForInference {
+            provider,
+            mut request,
+        } = self.clone_for_inference_with(Some(ToolChoice::tool(STRUCTURED_TOOL_NAME)));
+        request.messages.push(Message::User {
+            role: "user".to_string(),
+            content: user_content.clone(),
+        });
+        request.tools = vec![ToolDefinition {
+            name: STRUCTURED_TOOL_NAME.to_string(),
+            description: STRUCTURED_TOOL_DESCRIPTION.to_string(),
+            input_schema: schema.clone(),
+            cache_control: None,
+        }];
+
+        let mut retried = false;
+        loop {
+            let response = smol::unblock({
+                let provider = provider.clone();
+                let request = request.clone();
+                move || provider.chat(&request)
+            })
+            .await?;
+
+            let Some((id, value)) = response.content.iter().find_map(|block| match block {
+                ContentBlock::ToolUse { id, name, input } if name == STRUCTURED_TOOL_NAME => {
+                    Some((id.clone(), input.clone()))
+                }
+                _ => None,
+            }) else {
+                return Err(anyhow!("{} didn't reply with JSON", self.model));
+            };
+
+            match validate(&schema, &value) {
+                Ok(()) => {
+                    // Keep the reply as text so the history has no unanswered tool call
+                    self.conversation.push(Message::User {
+                        role: "user".to_string(),
+                        content: user_content,
+                    });
+                    let json = serde_json::to_string_pretty(&value)
+                        .map_err(|e| anyhow!("Failed to format reply: {}", e))?;
+                    self.add_assistant_message(vec![ContentBlock::text(json)]);
+                    self.record_usage(response.usage);
+                    return Ok(AgentResponse::StructuredResponse { value });
+                }
+                Err(errors) if !retried => {
+                    tracing::debug!(?errors, "Structured reply didn't match the schema");
+                    retried = true;
+                    request.messages.push(Message::Assistant {
+                        role: "assistant".to_string(),
+                        content: response.content,
+                    });
+                    request.messages.push(Message::User {
+                        role: "user".to_string(),
+                        content: vec![ContentBlock::ToolResult {
+                            tool_use_id: id,
+                            content: format!(
+                                "The input doesn't match the schema:\n- {}\nCall the tool \
+                                again with corrected input.",
+                                errors.join("\n- ")
+                            ),
+                            is_error: Some(true),
+                            cache_control: None,
+                        }],
+                    });
+                }
+                Err(errors) => {
+                    return Err(anyhow!(
+                        "The reply didn't match the schema: {}",
+                        errors.join("; ")
+                    ));
+                }
+            }
+        }
+    }
+
     /// Submit tool results back to the agent
     pub fn submit_tool_results(&mut self, results: Vec<ToolResultData>) {
         let content_blocks: Vec<ContentBlock> = results
@@ -210,6 +464,7 @@ impl Agent {
                 tool_use_id: result.tool_use_id,
                 content: result.content,
                 is_error: Some(result.is_error),
+                cache_control: None,
             })
             .collect();
 
@@ -221,14 +476,160 @@ impl Agent {
 
     /// Clone the agent state needed for inference (without tools)
     fn clone_for_inference(&self) -> AgentForInference {
+        self.clone_for_inference_with(self.tool_choice.clone())
+    }
+
+    /// Clone the agent state needed for inference with the given tool choice.
+    /// Extended thinking is left out when the choice forces a tool call, since
+    /// the API only allows thinking with `auto` or `none`.
+    fn clone_for_inference_with(&self, tool_choice: Option<ToolChoice>) -> AgentForInference {
+        let capabilities = self.capabilities();
+        let mut max_tokens = capabilities.max_tokens(self.max_tokens);
+        let thinking_budget = self.active_thinking_budget().filter(|_| {
+            !tool_choice
+                .as_ref()
+                .is_some_and(ToolChoice::forces_tool_use)
+        });
+        if let Some(budget) = thinking_budget {
+            // max_tokens covers both thinking and the reply
+            max_tokens = (max_tokens + budget).min(capabilities.max_output_tokens);
+        }
+        let thinking_budget = thinking_budget.map(|budget| {
+            budget
+                .min(max_tokens.saturating_sub(MIN_THINKING_BUDGET))
+                .max(MIN_THINKING_BUDGET)
+        });
+
         AgentForInference {
-            api_key: self.api_key.clone(),
+            provider: self.provider.clone(),
+            request: ChatRequest {
+                model: self.model.clone(),
+                system_prompt: self.system_prompt.clone(),
+                max_tokens,
+                messages: self.conversation.clone(),
+                tools: self.get_tool_definitions(),
+                tool_choice,
+                thinking_budget,
+                sampling: self.sampling.clone(),
+                context_window: self.context_window,
+            },
+        }
+    }
+
+    /// A request with this agent's model and settings that sends only
+    /// `content`, without the conversation or tools
+    pub fn standalone_request(&self, content: Vec<ContentBlock>) -> ChatRequest {
+        let mut request = self.clone_for_inference().request;
+        request.messages = vec![Message::User {
+            role: "user".to_string(),
+            content,
+        }];
+        request.tools = Vec::new();
+        request.tool_choice = None;
+        request
+    }
+
+    /// How much of the model's context window the conversation fills.
+    /// Uses the last reported token count plus an estimate of anything added since.
+    pub fn context_usage(&self) -> ContextUsage {
+        let used = match self.reported_tokens {
+            Some((tokens, len)) if len <= self.conversation.len() => {
+                tokens as usize + estimate_messages_tokens(&self.conversation[len..])
+            }
+            _ => {
+                estimate_preamble_tokens(&self.system_prompt, &self.get_tool_definitions())
+                    + estimate_messages_tokens(&self.conversation)
+            }
+        };
+        ContextUsage {
+            used: used as u32,
+            limit: self.capabilities().context_window,
+            cache_read: self.last_usage.cache_read_input_tokens,
+            cache_write: self.last_usage.cache_creation_input_tokens,
+        }
+    }
+
+    /// Count the input tokens of the next request if `pending` were sent.
+    /// Falls back to an estimate when the provider can't count exactly.
+    pub async fn count_tokens(&self, pending: Vec<ContentBlock>) -> TokenCount {
+        let estimate = self.context_usage().used
+            + pending.iter().map(estimate_block_tokens).sum::<usize>() as u32;
+        let AgentForInference {
+            provider,
+            mut request,
+        } = self.clone_for_inference();
+        request.messages.push(Message::User {
+            role: "user".to_string(),
+            content: pending,
+        });
+
+        let counted = smol::unblock(move || provider.count_tokens(&request))
+            .await
+            .inspect_err(|e| tracing::warn!("Token count failed: {}", e))
+            .ok()
+            .flatten();
+        let tokens = counted.unwrap_or(estimate);
+        TokenCount {
+            tokens,
+            exact: counted.is_some(),
+            limit: self.capabilities().context_window,
+            limit_known: self.context_window.is_some() || is_cataloged(&self.model),
+            cost: input_cost(&self.model, tokens),
+        }
+    }
+
+    /// Summarize older turns if sending `pending` would come close to the context limit.
+    /// Returns how many messages were replaced by the summary.
+    pub async fn compact_if_needed(&mut self, pending: &[ContentBlock]) -> Result<Option<usize>> {
+        let capabilities = self.capabilities();
+        let request_tokens = self.context_usage().used as usize
+            + pending.iter().map(estimate_block_tokens).sum::<usize>()
+            + capabilities.max_tokens(self.max_tokens) as usize;
+        if (request_tokens as f32) < capabilities.context_window as f32 * COMPACT_RATIO {
+            return Ok(None);
+        }
+        let Some(split) = compaction_split(&self.conversation) else {
+            return Ok(None);
+        };
+
+        let request = ChatRequest {
             model: self.model.clone(),
-            system_prompt: self.system_prompt.clone(),
-            tool_definitions: self.get_tool_definitions(),
-            conversation: self.conversation.clone(),
-            max_tokens: self.max_tokens,
+            system_prompt: SUMMARY_PROMPT.to_string(),
+            max_tokens: capabilities.max_tokens(None),
+            messages: vec![Message::User {
+                role: "user".to_string(),
+                content: vec![ContentBlock::text(transcript(&self.conversation[..split]))],
+            }],
+            tools: Vec::new(),
+            tool_choice: None,
+            thinking_budget: None,
+            sampling: SamplingParams::default(),
+            context_window: self.context_window,
+        };
+        let provider = self.provider.clone();
+        let response = smol::unblock(move || provider.chat(&request))
+            .await
+            .map_err(|e| anyhow!("Failed to summarize the conversation: {}", e))?;
+
+        let summary: Vec<&str> = response
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if summary.is_empty() {
+            return Err(anyhow!("The conversation summary was empty"));
         }
+
+        tracing::debug!(messages = split, "Compacted conversation");
+        let recent = self.conversation.split_off(split);
+        self.conversation = std::iter::once(summary_message(&summary.join("\n")))
+            .chain(recent)
+            .collect();
+        self.reported_tokens = None;
+        Ok(Some(split))
     }
 
     /// Get the current conversation history
@@ -240,88 +641,57 @@ impl Agent {
     /// Clear the conversation history
     pub fn clear_conversation(&mut self) {
         self.conversation.clear();
+        self.reported_tokens = None;
+        self.last_usage = Usage::default();
     }
 }
 
 /// A lightweight version of Agent for running inference without tool execution
 struct AgentForInference {
-    api_key: String,
-    model: String,
-    system_prompt: String,
-    tool_definitions: Vec<ToolDefinition>,
-    conversation: Vec<Message>,
-    max_tokens: u32,
+    provider: Arc<dyn LlmProvider>,
+    request: ChatRequest,
 }
 
 impl AgentForInference {
-    fn run_inference(&mut self) -> Result<AnthropicResponse> {
-        let tool_defs = if self.tool_definitions.is_empty() {
-            None
-        } else {
-            Some(self.tool_definitions.clone())
-        };
-
-        let request = AnthropicRequest {
-            model: self.model.clone(),
-            max_tokens: self.max_tokens,
-            messages: self.conversation.clone(),
-            system: Some(self.system_prompt.clone()),
-            tools: tool_defs,
+    /// Run the request, streaming reply text to `text_tx` if set.
+    /// Also returns when the first text arrived.
+    fn run_inference(
+        &mut self,
+        text_tx: Option<Sender<AgentResponse>>,
+    ) -> Result<(ChatResponse, Option<Instant>)> {
+        let Some(text_tx) = text_tx else {
+            return Ok((self.provider.chat(&self.request)?, None));
         };
-
-        let body = serde_json::to_string(&request)
-            .map_err(|e| anyhow!("Failed to serialize request: {}", e))?;
-
-        let response = smolhttp::Client::new("https://api.anthropic.com/v1/messages")
-            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
-            .post()
-            .headers(vec![
-                ("x-api-key".to_string(), self.api_key.clone()),
-                ("anthropic-version".to_string(), "2023-06-01".to_string()),
-                (
-                    "anthropic-beta".to_string(),
-                    "files-api-2025-04-14".to_string(),
-                ),
-                ("content-type".to_string(), "application/json".to_string()),
-            ])
-            .body(body.into())
-            .send()
-            .map_err(|e| anyhow!("API request failed: {}", e))?;
-
-        let response_text = response.text();
-
-        if response_text.contains("\"error\"") && response_text.contains("\"type\"") {
-            return Err(anyhow!("API error: {}", response_text));
-        }
-
-        let api_response: AnthropicResponse =
-            serde_json::from_str(&response_text).map_err(|e| {
-                anyhow!(
-                    "Failed to parse response: {}. Response: {}",
-                    e,
-                    response_text
-                )
-            })?;
-
-        Ok(api_response)
+        let mut first_text = None;
+        let response = self.provider.chat_stream(&self.request, &mut |text| {
+            first_text.get_or_insert_with(Instant::now);
+            let _ = text_tx.try_send(AgentResponse::TextDelta(text.to_string()));
+        })?;
+        Ok((response, first_text))
     }
 }
 
 /// Builder for creating agents with custom configuration
 pub struct AgentBuilder {
     api_key: Option<String>,
+    provider: Option<Arc<dyn LlmProvider>>,
     model: String,
     system_prompt: String,
-    max_tokens: u32,
+    max_tokens: Option<u32>,
+    sampling: SamplingParams,
+    tool_choice: Option<ToolChoice>,
 }
 
 impl Default for AgentBuilder {
     fn default() -> Self {
         Self {
             api_key: None,
-            model: "claude-haiku-4-5-20251001".to_string(),
+            provider: None,
+            model: DEFAULT_MODEL.to_string(),
             system_prompt: Agent::default_system_prompt(),
-            max_tokens: 4096,
+            max_tokens: None,
+            sampling: SamplingParams::default(),
+            tool_choice: None,
         }
     }
 }
@@ -333,6 +703,11 @@ impl AgentBuilder {
         self
     }
 
+    pub fn provider(mut self, provider: Arc<dyn LlmProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
     pub fn model(mut self, model: String) -> Self {
         self.model = model;
         self
@@ -344,24 +719,59 @@ impl AgentBuilder {
     }
 
     pub fn max_tokens(mut self, max_tokens: u32) -> Self {
-        self.max_tokens = max_tokens;
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.sampling.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.sampling.top_p = Some(top_p);
+        self
+    }
+
+    pub fn top_k(mut self, top_k: u32) -> Self {
+        self.sampling.top_k = Some(top_k);
+        self
+    }
+
+    pub fn stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.sampling.stop_sequences = stop_sequences;
+        self
+    }
+
+    pub fn tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
         self
     }
 
     pub fn build(self, tools: Vec<Tool>) -> Result<Agent> {
-        let api_key = match self.api_key {
-            Some(key) => key,
-            None => env::var("ANTHROPIC_API_KEY")
-                .map_err(|_| anyhow!("ANTHROPIC_API_KEY environment variable not set"))?,
+        let provider: Arc<dyn LlmProvider> = match (self.provider, self.api_key) {
+            (Some(provider), _) => provider,
+            (None, Some(key)) => Arc::new(AnthropicProvider::new(key)),
+            (None, None) => Arc::new(AnthropicProvider::from_env()?),
         };
+        self.sampling.validate(false, Some(provider.kind()))?;
 
         Ok(Agent {
-            api_key,
+            provider,
             model: self.model,
             system_prompt: self.system_prompt,
             tools,
             conversation: Vec::new(),
             max_tokens: self.max_tokens,
+            thinking_budget: None,
+            sampling: self.sampling,
+            enabled_tools: None,
+            output_schema: None,
+            tool_choice: self.tool_choice,
+            reported_tokens: None,
+            last_usage: Usage::default(),
+            context_window: None,
+            text_tx: None,
         })
     }
 }
@@ -381,4 +791,27 @@ mod tests {
 
         assert!(agent.is_ok());
     }
+
+    #[test]
+    fn test_thinking_budget_fits_max_tokens() {
+        let mut agent = Agent::builder()
+            .api_key("test-key".to_string())
+            .model("claude-opus-4-1-20250805".to_string())
+            .build(vec![])
+            .unwrap();
+        agent.set_thinking_budget(Some(31_000));
+        let request = agent.clone_for_inference().request;
+        assert_eq!(request.max_tokens, 32_000);
+        assert_eq!(request.thinking_budget, Some(32_000 - MIN_THINKING_BUDGET));
+
+        // Thinking can't be combined with a forced tool call
+        agent.set_tool_choice(Some(ToolChoice::Any {
+            disable_parallel_tool_use: false,
+        }));
+        assert_eq!(agent.clone_for_inference().request.thinking_budget, None);
+
+        agent.set_tool_choice(None);
+        agent.set_model("claude-3-5-haiku-20241022".to_string());
+        assert_eq!(agent.clone_for_inference().request.thinking_budget, None);
+    }
 }
@@ -0,0 +1,502 @@
+//! The `run_command` tool, running programs in the workspace folder.
+//!
+//! Commands run without a shell, with a scrubbed environment and a time limit,
+//! in their own process group so nothing they start outlives them. Which
+//! programs may run is narrowed by the user's allow and deny lists, but the
+//! lists are advisory: an allowed program such as `make` or `cargo` can still
+//! start anything. Every call asks for approval unless the user changes the
+//! tool's permission, and that approval is the real safeguard.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    ffi::OsString,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::tools::{CancelToken, ToolHandler};
+use super::types::Tool;
+use super::workspace::{MAX_TOOL_OUTPUT_BYTES, Workspace};
+use crate::services::paths::app_dir;
+
+/// Time a command gets when the model doesn't ask for a limit
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest time limit the model may ask for
+const MAX_COMMAND_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How often a running command is checked for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait for a command's output after it exits
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Name the command tool is registered under
+pub const RUN_COMMAND_TOOL_NAME: &str = "run_command";
+
+/// Programs that run other programs given as arguments, which would get
+/// around the lists. Shells and interpreters run any code they're given.
+const WRAPPER_PROGRAMS: &[&str] = &[
+    "sh",
+    "bash",
+    "zsh",
+    "fish",
+    "dash",
+    "ksh",
+    "csh",
+    "tcsh",
+    "cmd",
+    "powershell",
+    "pwsh",
+    "env",
+    "timeout",
+    "nice",
+    "nohup",
+    "stdbuf",
+    "setsid",
+    "time",
+    "watch",
+    "xargs",
+    "parallel",
+    "chroot",
+    "busybox",
+    "sudo",
+    "doas",
+    "python",
+    "python2",
+    "python3",
+    "perl",
+    "ruby",
+    "node",
+    "deno",
+    "bun",
+    "php",
+    "lua",
+    "tclsh",
+    "osascript",
+    "awk",
+    "gawk",
+];
+
+/// `find` actions that delete files or run commands
+const FIND_ACTIONS: &[&str] = &["-delete", "-exec", "-execdir", "-ok", "-okdir"];
+
+/// Environment variables passed to commands. Everything else, API keys
+/// included, is removed.
+const PASSED_ENV_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "LANG",
+    "LC_ALL",
+    "TERM",
+    "TMPDIR",
+    "TEMP",
+    "TMP",
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+    "SYSTEMROOT",
+    "USERPROFILE",
+];
+
+/// Programs and subcommands the model may or may not run. Each entry is a
+/// program followed by subcommand words, such as `git push`.
+///
+/// The lists are advisory. They stop the model from calling a program by
+/// name, not a permitted program from starting it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandRules {
+    /// If not empty, only commands starting with one of these may run
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Commands starting with one of these never run, even if allowed
+    #[serde(default)]
+    pub denied: Vec<String>,
+}
+
+impl Default for CommandRules {
+    fn default() -> Self {
+        Self {
+            allowed: Vec::new(),
+            // Shells and other wrappers are always refused; see `WRAPPER_PROGRAMS`
+            denied: [
+                "rm", "sudo", "su", "curl", "wget", "ssh", "scp", "dd", "mkfs", "shutdown",
+                "reboot", "git push",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+        }
+    }
+}
+
+impl CommandRules {
+    fn path() -> PathBuf {
+        app_dir().join("command_rules.json")
+    }
+
+    /// Load the user's rules, falling back to the defaults
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create settings directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize command rules: {}", e))?;
+        std::fs::write(path, json).map_err(|e| anyhow!("Failed to write command rules: {}", e))
+    }
+
+    /// Check a command's words against the lists. Programs that run other
+    /// commands are refused whatever the lists say.
+    pub fn check(&self, args: &[String]) -> Result<()> {
+        let program = program_name(&args[0]);
+        if WRAPPER_PROGRAMS.contains(&program.as_str()) {
+            return Err(anyhow!(
+                "`{}` can run other commands, so it's blocked",
+                program
+            ));
+        }
+        if program == "find" && args.iter().any(|arg| FIND_ACTIONS.contains(&arg.as_str())) {
+            return Err(anyhow!(
+                "`find` can only search, not delete or run commands"
+            ));
+        }
+        if let Some(rule) = self.denied.iter().find(|rule| matches_denied(rule, args)) {
+            return Err(anyhow!("`{}` commands are blocked", rule));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|rule| matches_rule(rule, args)) {
+            return Err(anyhow!(
+                "Only these commands are allowed: {}",
+                self.allowed.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A program's file name without its extension, so `/bin/rm` and `rm.exe`
+/// are both `rm`
+fn program_name(program: &str) -> String {
+    Path::new(program)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Whether the command starts with the rule's words once flags are skipped,
+/// so `git --no-pager status` matches `git status`. Values given to flags
+/// aren't skipped, since they can't be told apart from subcommands:
+/// `git -C repo status` doesn't match.
+fn matches_rule(rule: &str, args: &[String]) -> bool {
+    let words: Vec<&str> = rule.split_whitespace().collect();
+    if words.is_empty() || program_name(&args[0]) != words[0] {
+        return false;
+    }
+    let mut rest = args[1..].iter().filter(|arg| !arg.starts_with('-'));
+    words[1..]
+        .iter()
+        .all(|word| rest.next().map(String::as_str) == Some(*word))
+}
+
+/// Whether the command runs the rule's program with its words among the
+/// arguments, in order. Flags and their values may come between them, so
+/// `git --no-pager push` and `git -C repo push` match `git push`.
+fn matches_denied(rule: &str, args: &[String]) -> bool {
+    let words: Vec<&str> = rule.split_whitespace().collect();
+    if words.is_empty() || program_name(&args[0]) != words[0] {
+        return false;
+    }
+    let mut rest = args[1..].iter();
+    words[1..]
+        .iter()
+        .all(|word| rest.any(|arg| !arg.starts_with('-') && arg == word))
+}
+
+/// The variables from `vars` that commands may see
+pub(super) fn scrub_env(
+    vars: impl Iterator<Item = (OsString, OsString)>,
+) -> Vec<(OsString, OsString)> {
+    vars.filter(|(name, _)| {
+        PASSED_ENV_VARS
+            .iter()
+            .any(|passed| name.to_string_lossy().eq_ignore_ascii_case(passed))
+    })
+    .collect()
+}
+
+/// Keep the end of a command's output, where errors and summaries usually are
+fn tail_output(bytes: &[u8], limit: usize) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    if text.len() <= limit {
+        return text.trim_end().to_string();
+    }
+    let mut start = text.len() - limit;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!(
+        "[Truncated: showing the last {} of {} bytes]\n{}",
+        text.len() - start,
+        text.len(),
+        text[start..].trim_end()
+    )
+}
+
+/// Read a pipe to the end on its own thread, so a full pipe can't block the command
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        let _ = tx.send(bytes);
+    });
+    rx
+}
+
+/// Kill the command and everything it started. On Unix the command leads its
+/// own process group, so the whole group is signalled.
+pub(super) fn kill_tree(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: `kill` has no memory effects; a negative PID names the group
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+pub struct RunCommand {
+    workspace: Workspace,
+    rules: CommandRules,
+}
+
+impl RunCommand {
+    pub fn new(workspace: Workspace, rules: CommandRules) -> Self {
+        Self { workspace, rules }
+    }
+
+    fn run(&self, args: &[String], timeout: Duration, cancel: &CancelToken) -> Result<String> {
+        let mut command = Command::new(&args[0]);
+        command
+            .args(&args[1..])
+            .current_dir(self.workspace.root())
+            .env_clear()
+            .envs(scrub_env(std::env::vars_os()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow!("Can't run {}: {}", args[0], e))?;
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child
+                .try_wait()
+                .map_err(|e| anyhow!("Can't wait for {}: {}", args[0], e))?
+            {
+                break status;
+            }
+            if started.elapsed() > timeout || cancel.is_cancelled() {
+                kill_tree(&mut child);
+                let _ = child.wait();
+                cancel.check()?;
+                return Err(anyhow!("Command timed out after {:?}", timeout));
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        // Anything the command left running in the background would keep
+        // the pipes open
+        kill_tree(&mut child);
+        let drain_deadline = Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+        let drain = |rx: mpsc::Receiver<Vec<u8>>| {
+            rx.recv_timeout(drain_deadline.saturating_duration_since(Instant::now()))
+                .unwrap_or_default()
+        };
+        let stdout = drain(stdout);
+        let stderr = drain(stderr);
+        let exit_code = status
+            .code()
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| "none (killed by a signal)".to_string());
+        Ok(format!(
+            "Exit code: {}\n\nstdout:\n{}\n\nstderr:\n{}",
+            exit_code,
+            tail_output(&stdout, MAX_TOOL_OUTPUT_BYTES / 2),
+            tail_output(&stderr, MAX_TOOL_OUTPUT_BYTES / 2)
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+struct RunCommandInput {
+    command: String,
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+}
+
+impl ToolHandler for RunCommand {
+    fn definition(&self) -> Tool {
+        Tool {
+            name: RUN_COMMAND_TOOL_NAME.to_string(),
+            description: format!(
+                "Run a command such as `cargo test` in the workspace folder and get its exit \
+                code, stdout and stderr. The command runs without a shell, so pipes, \
+                redirects and variables aren't available. Long output keeps its end. \
+                The time limit defaults to {} seconds.",
+                DEFAULT_COMMAND_TIMEOUT.as_secs()
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "description": "Program and arguments" },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": MAX_COMMAND_TIMEOUT.as_secs()
+                    }
+                },
+                "required": ["command"]
+            }),
+        }
+    }
+
+    fn call(&self, input: &Value, cancel: &CancelToken) -> Result<String> {
+        let input: RunCommandInput =
+            serde_json::from_value(input.clone()).map_err(|e| anyhow!("Invalid input: {}", e))?;
+        let args = shlex::split(&input.command)
+            .filter(|args| !args.is_empty())
+            .ok_or_else(|| anyhow!("Can't parse command: {}", input.command))?;
+        self.rules.check(&args)?;
+
+        let timeout = input
+            .timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_COMMAND_TIMEOUT)
+            .min(MAX_COMMAND_TIMEOUT);
+        self.run(&args, timeout, cancel)
+    }
+
+    /// The command enforces its own limit; this only catches a stuck reader
+    fn timeout(&self) -> Duration {
+        MAX_COMMAND_TIMEOUT + Duration::from_secs(5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &str) -> Vec<String> {
+        shlex::split(command).unwrap()
+    }
+
+    #[test]
+    fn test_command_rules() {
+        let rules = CommandRules::default();
+        assert!(rules.check(&words("git log --oneline")).is_ok());
+        assert!(rules.check(&words("git push origin main")).is_err());
+        assert!(rules.check(&words("/bin/rm -rf target")).is_err());
+        assert!(rules.check(&words("rmdir empty")).is_ok());
+        assert!(rules.check(&words("bash -c 'rm -rf /'")).is_err());
+        assert!(rules.check(&words("git --no-pager push")).is_err());
+        assert!(rules.check(&words("git -C repo push --force")).is_err());
+        assert!(rules.check(&words("timeout 5 rm -rf target")).is_err());
+        assert!(
+            rules
+                .check(&words("/usr/bin/python3 -c 'print(1)'"))
+                .is_err()
+        );
+        assert!(rules.check(&words("find . -name '*.tmp' -delete")).is_err());
+        assert!(rules.check(&words("find . -name '*.rs'")).is_ok());
+
+        let rules = CommandRules {
+            allowed: vec!["cargo".to_string(), "git status".to_string()],
+            denied: vec!["cargo publish".to_string()],
+        };
+        assert!(rules.check(&words("cargo test --workspace")).is_ok());
+        assert!(rules.check(&words("cargo publish")).is_err());
+        assert!(rules.check(&words("git status")).is_ok());
+        assert!(rules.check(&words("git --no-pager status --short")).is_ok());
+        assert!(rules.check(&words("git commit")).is_err());
+        // Allow rules are stricter than deny rules about what comes between words
+        assert!(rules.check(&words("git -C repo status")).is_err());
+        assert!(rules.check(&words("git push status")).is_err());
+    }
+
+    #[test]
+    fn test_scrub_env() {
+        let vars = [
+            ("PATH", "/usr/bin"),
+            ("ANTHROPIC_API_KEY", "sk-ant-secret"),
+            ("OPENAI_API_KEY", "sk-secret"),
+            ("HOME", "/home/me"),
+        ]
+        .map(|(name, value)| (OsString::from(name), OsString::from(value)));
+        let names: Vec<OsString> = scrub_env(vars.into_iter())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, [OsString::from("PATH"), OsString::from("HOME")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_command() {
+        let workspace = Workspace::new(&std::env::temp_dir()).unwrap();
+        let rules = CommandRules {
+            allowed: Vec::new(),
+            denied: Vec::new(),
+        };
+        let tool = RunCommand::new(workspace, rules);
+        let cancel = CancelToken::default();
+
+        // Shells are refused by the rules, so run one directly
+        let output = tool
+            .run(
+                &words("sh -c 'echo out; echo err >&2; exit 3'"),
+                DEFAULT_COMMAND_TIMEOUT,
+                &cancel,
+            )
+            .unwrap();
+        assert_eq!(output, "Exit code: 3\n\nstdout:\nout\n\nstderr:\nerr");
+
+        // A process left in the background is killed with the command, so
+        // its output is complete rather than cut off waiting for the pipe
+        let output = tool
+            .run(
+                &words("sh -c 'sleep 30 & echo started'"),
+                DEFAULT_COMMAND_TIMEOUT,
+                &cancel,
+            )
+            .unwrap();
+        assert!(output.contains("stdout:\nstarted"));
+
+        let started = Instant::now();
+        let error = tool
+            .call(&json!({ "command": "sleep 5", "timeout_secs": 1 }), &cancel)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Command timed out after 1s");
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+}
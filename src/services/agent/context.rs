@@ -0,0 +1,233 @@
+//! Conversation size tracking and compaction.
+//!
+//! Sizes are estimated from text length, corrected by the token counts the
+//! provider reports after each turn. When a request would come close to the
+//! model's context window, older turns are summarized into a single message.
+//! Drafts can be counted exactly before sending when the provider supports it.
+
+use serde::{Deserialize, Serialize};
+
+use super::directory::estimate_tokens;
+use super::types::{ContentBlock, Message, ToolDefinition};
+
+/// Share of the context window at which the UI warns about the conversation size
+const WARNING_RATIO: f32 = 0.8;
+
+/// Share of the context window a request may use before older turns are compacted
+pub const COMPACT_RATIO: f32 = 0.9;
+
+/// User turns kept verbatim when compacting
+pub const KEEP_RECENT_TURNS: usize = 2;
+
+/// Rough cost of an image; the API scales images to about this size
+const IMAGE_TOKENS: usize = 1_600;
+
+/// Rough cost of a PDF, whose page count isn't known here
+const DOCUMENT_TOKENS: usize = 3_000;
+
+/// Instructions for summarizing older turns
+pub const SUMMARY_PROMPT: &str = "You compress chat transcripts. Summarize the conversation \
+    below so it can replace the original messages. Keep decisions, facts, file names, code \
+    identifiers, tool results that matter later and open questions. Reply with the summary only.";
+
+/// How much of the context window the conversation uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextUsage {
+    pub used: u32,
+    pub limit: u32,
+    /// Input tokens of the last request read from the prompt cache
+    pub cache_read: u32,
+    /// Input tokens of the last request written to the prompt cache
+    pub cache_write: u32,
+}
+
+impl ContextUsage {
+    pub fn ratio(&self) -> f32 {
+        self.used as f32 / self.limit.max(1) as f32
+    }
+
+    /// Whether the UI should warn that the limit is close
+    pub fn is_near_limit(&self) -> bool {
+        self.ratio() >= WARNING_RATIO
+    }
+}
+
+/// Input tokens a drafted message would send, counted before sending
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TokenCount {
+    pub tokens: u32,
+    /// Counted by the provider rather than estimated from text length
+    pub exact: bool,
+    /// The model's context window
+    pub limit: u32,
+    /// Whether `limit` is the model's own rather than a default for unknown models
+    pub limit_known: bool,
+    /// Input cost in US dollars, if the model's price is known
+    pub cost: Option<f64>,
+}
+
+impl TokenCount {
+    /// Whether the request looks too big for the context window
+    pub fn exceeds_limit(&self) -> bool {
+        self.tokens > self.limit
+    }
+
+    /// Whether the request certainly can't fit. An assumed limit only warns.
+    pub fn blocks_send(&self) -> bool {
+        self.limit_known && self.exceeds_limit()
+    }
+}
+
+/// Estimate the tokens in a content block
+pub fn estimate_block_tokens(block: &ContentBlock) -> usize {
+    match block {
+        ContentBlock::Text { text, .. } => estimate_tokens(text),
+        ContentBlock::ToolUse { name, input, .. } => {
+            estimate_tokens(name) + estimate_tokens(&input.to_string())
+        }
+        ContentBlock::ToolResult { content, .. } => estimate_tokens(content),
+        ContentBlock::Image { .. } => IMAGE_TOKENS,
+        ContentBlock::Document { .. } => DOCUMENT_TOKENS,
+        ContentBlock::Thinking { thinking, .. } => estimate_tokens(thinking),
+        ContentBlock::RedactedThinking { data } => estimate_tokens(data),
+    }
+}
+
+/// Estimate the tokens in a list of messages
+pub fn estimate_messages_tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .flat_map(|message| match message {
+            Message::User { content, .. } | Message::Assistant { content, .. } => content,
+        })
+        .map(estimate_block_tokens)
+        .sum()
+}
+
+/// Estimate the tokens in the system prompt and tool definitions
+pub fn estimate_preamble_tokens(system_prompt: &str, tools: &[ToolDefinition]) -> usize {
+    estimate_tokens(system_prompt)
+        + tools
+            .iter()
+            .map(|tool| {
+                estimate_tokens(&tool.name)
+                    + estimate_tokens(&tool.description)
+                    + estimate_tokens(&tool.input_schema.to_string())
+            })
+            .sum::<usize>()
+}
+
+/// A user message typed by the user, as opposed to one carrying tool results
+fn starts_turn(message: &Message) -> bool {
+    match message {
+        Message::User { content, .. } => !content
+            .iter()
+            .any(|block| matches!(block, ContentBlock::ToolResult { .. })),
+        Message::Assistant { .. } => false,
+    }
+}
+
+/// Index of the first message kept verbatim when compacting, or `None` if
+/// there's nothing older to summarize. Splitting at the start of a user turn
+/// keeps every tool use together with its result.
+pub fn compaction_split(messages: &[Message]) -> Option<usize> {
+    let turn_starts: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .filter(|(_, message)| starts_turn(message))
+        .map(|(ix, _)| ix)
+        .collect();
+
+    let split = *turn_starts.iter().rev().nth(KEEP_RECENT_TURNS - 1)?;
+    (split > 0).then_some(split)
+}
+
+/// Plain-text rendering of messages for the summarizer. Attachments and
+/// thinking are left out; tool calls are shown by name and input.
+pub fn transcript(messages: &[Message]) -> String {
+    let mut out = String::new();
+    for message in messages {
+        let (speaker, content) = match message {
+            Message::User { content, .. } => ("User", content),
+            Message::Assistant { content, .. } => ("Assistant", content),
+        };
+        for block in content {
+            let line = match block {
+                ContentBlock::Text { text, .. } => format!("{}: {}", speaker, text),
+                ContentBlock::ToolUse { name, input, .. } => {
+                    format!("{} called {} with {}", speaker, name, input)
+                }
+                ContentBlock::ToolResult { content, .. } => format!("Tool result: {}", content),
+                ContentBlock::Image { .. } => format!("{} attached an image", speaker),
+                ContentBlock::Document { .. } => format!("{} attached a document", speaker),
+                ContentBlock::Thinking { .. } | ContentBlock::RedactedThinking { .. } => continue,
+            };
+            out.push_str(&line);
+            out.push_str("\n\n");
+        }
+    }
+    out
+}
+
+/// The synthetic message that replaces summarized turns
+pub fn summary_message(summary: &str) -> Message {
+    Message::User {
+        role: "user".to_string(),
+        content: vec![ContentBlock::text(format!(
+            "<conversation_summary>\nEarlier messages were summarized to save space:\n{}\n\
+            </conversation_summary>",
+            summary.trim()
+        ))],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(text: &str) -> Message {
+        Message::User {
+            role: "user".to_string(),
+            content: vec![ContentBlock::text(text)],
+        }
+    }
+
+    fn assistant(content: ContentBlock) -> Message {
+        Message::Assistant {
+            role: "assistant".to_string(),
+            content: vec![content],
+        }
+    }
+
+    #[test]
+    fn test_compaction_split_keeps_tool_pairs() {
+        let tool_use = ContentBlock::ToolUse {
+            id: "toolu_1".to_string(),
+            name: "search".to_string(),
+            input: serde_json::json!({}),
+        };
+        let tool_result = Message::User {
+            role: "user".to_string(),
+            content: vec![ContentBlock::ToolResult {
+                tool_use_id: "toolu_1".to_string(),
+                content: "found".to_string(),
+                is_error: None,
+                cache_control: None,
+            }],
+        };
+        let messages = vec![
+            user("one"),
+            assistant(ContentBlock::text("a")),
+            user("two"),
+            assistant(tool_use),
+            tool_result,
+            assistant(ContentBlock::text("b")),
+            user("three"),
+        ];
+
+        // Turns "two" (with its tool loop) and "three" are kept
+        assert_eq!(compaction_split(&messages), Some(2));
+        assert_eq!(compaction_split(&messages[2..]), None);
+        assert!(transcript(&messages[..2]).starts_with("User: one"));
+    }
+}
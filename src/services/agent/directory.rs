@@ -0,0 +1,234 @@
+//! Packing a directory of text files into a single user turn.
+//!
+//! Walks a folder respecting `.gitignore` plus user include/exclude globs,
+//! skips binaries and oversized files, and renders each file with a path header.
+
+use anyhow::{Result, anyhow};
+use ignore::{WalkBuilder, overrides::OverrideBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Read as _,
+    path::{Path, PathBuf},
+};
+
+/// Files larger than this are skipped rather than packed
+pub const MAX_PACKED_FILE_BYTES: u64 = 256 * 1024;
+
+/// Bytes sniffed when deciding whether a file is binary
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+/// A directory chosen for attachment along with its filters
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DirectorySelection {
+    pub root: PathBuf,
+    /// Globs a file must match to be included; empty means everything
+    pub include: Vec<String>,
+    /// Globs excluding files, applied after `include`
+    pub exclude: Vec<String>,
+}
+
+#[allow(dead_code)]
+impl DirectorySelection {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            include: vec![],
+            exclude: vec![],
+        }
+    }
+}
+
+/// Why a file in the directory was left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    Binary,
+    TooLarge,
+    Unreadable,
+}
+
+/// A text file read from the directory
+#[derive(Debug, Clone)]
+pub struct PackedFile {
+    /// Path relative to the selection root
+    pub path: String,
+    pub contents: String,
+}
+
+/// Result of walking a directory selection
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryScan {
+    pub root: PathBuf,
+    pub files: Vec<PackedFile>,
+    pub skipped: Vec<(String, SkipReason)>,
+}
+
+#[allow(dead_code)]
+impl DirectoryScan {
+    /// Total bytes of packed file contents
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.contents.len() as u64).sum()
+    }
+
+    /// Rough token estimate for the packed text
+    pub fn estimated_tokens(&self) -> usize {
+        estimate_tokens(&self.to_text())
+    }
+
+    /// Render all files with path headers, ready to send as a text block
+    pub fn to_text(&self) -> String {
+        let root_name = self
+            .root
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.root.display().to_string());
+
+        let mut text = format!("<directory path=\"{}\">\n", root_name);
+        for file in &self.files {
+            text.push_str(&format!("<file path=\"{}\">\n", file.path));
+            text.push_str(&file.contents);
+            if !file.contents.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str("</file>\n");
+        }
+        text.push_str("</directory>");
+        text
+    }
+}
+
+/// Approximate token count using the common ~4 characters per token heuristic
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Whether the first few KB of a file look like binary data
+pub(super) fn looks_binary(path: &Path) -> std::io::Result<bool> {
+    let mut buf = vec![0; BINARY_SNIFF_BYTES];
+    let read = std::fs::File::open(path)?.read(&mut buf)?;
+    Ok(buf[..read].contains(&0))
+}
+
+/// Walk a directory selection and read every eligible text file
+pub fn scan_directory(selection: &DirectorySelection) -> Result<DirectoryScan> {
+    let root = &selection.root;
+    if !root.is_dir() {
+        return Err(anyhow!("{} is not a directory", root.display()));
+    }
+
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in &selection.include {
+        overrides
+            .add(glob)
+            .map_err(|e| anyhow!("Invalid include glob {}: {}", glob, e))?;
+    }
+    for glob in &selection.exclude {
+        overrides
+            .add(&format!("!{}", glob))
+            .map_err(|e| anyhow!("Invalid exclude glob {}: {}", glob, e))?;
+    }
+    let overrides = overrides
+        .build()
+        .map_err(|e| anyhow!("Invalid globs: {}", e))?;
+
+    let walker = WalkBuilder::new(root)
+        .overrides(overrides)
+        .require_git(false)
+        .sort_by_file_path(|a, b| a.cmp(b))
+        .build();
+
+    let mut scan = DirectoryScan {
+        root: root.clone(),
+        ..Default::default()
+    };
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::debug!("Skipping unreadable entry: {}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+
+        let path = entry.path();
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        if size > MAX_PACKED_FILE_BYTES {
+            scan.skipped.push((relative, SkipReason::TooLarge));
+            continue;
+        }
+
+        match looks_binary(path) {
+            Ok(true) => {
+                scan.skipped.push((relative, SkipReason::Binary));
+                continue;
+            }
+            Ok(false) => {}
+            Err(_) => {
+                scan.skipped.push((relative, SkipReason::Unreadable));
+                continue;
+            }
+        }
+
+        match std::fs::read_to_string(path) {
+            Ok(contents) => scan.files.push(PackedFile {
+                path: relative,
+                contents,
+            }),
+            // Not valid UTF-8, so treat it like any other binary
+            Err(_) => scan.skipped.push((relative, SkipReason::Binary)),
+        }
+    }
+
+    Ok(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_respects_gitignore_globs_and_binaries() {
+        let root = std::env::temp_dir().join(format!("chatai-dir-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join(".gitignore"), "ignored.txt\n").unwrap();
+        std::fs::write(root.join("ignored.txt"), "nope").unwrap();
+        std::fs::write(root.join("README.md"), "# Readme").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(root.join("src/data.bin"), [0u8, 1, 2, 3]).unwrap();
+
+        let scan = scan_directory(&DirectorySelection::new(root.clone())).unwrap();
+        let paths: Vec<&str> = scan.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["README.md", "src/main.rs"]);
+        assert_eq!(
+            scan.skipped,
+            vec![("src/data.bin".to_string(), SkipReason::Binary)]
+        );
+        assert!(scan.to_text().contains("<file path=\"src/main.rs\">"));
+
+        let mut selection = DirectorySelection::new(root.clone());
+        selection.include = vec!["*.rs".to_string(), "*.md".to_string()];
+        selection.exclude = vec!["README.md".to_string()];
+        let scan = scan_directory(&selection).unwrap();
+        let paths: Vec<&str> = scan.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["src/main.rs"]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+}
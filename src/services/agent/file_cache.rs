@@ -0,0 +1,229 @@
+//! Local cache mapping file contents to previously uploaded Files API IDs.
+//!
+//! Files are keyed by the SHA-256 of their contents, so attaching the same
+//! document in several conversations only uploads it once.
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use super::files::{get_file_metadata, upload_bytes};
+use crate::services::paths::app_dir;
+
+/// How long a cached file ID is trusted before re-checking it with the API
+const VALIDATION_TTL_HOURS: i64 = 24;
+
+/// A previously uploaded file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedFile {
+    pub file_id: String,
+    pub filename: String,
+    pub size_bytes: u64,
+    pub uploaded_at: DateTime<Utc>,
+    pub validated_at: DateTime<Utc>,
+}
+
+impl CachedFile {
+    /// Whether the entry should be re-validated against the Files API
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        now - self.validated_at > Duration::hours(VALIDATION_TTL_HOURS)
+    }
+}
+
+/// Content-hash → file ID cache persisted as JSON
+#[derive(Debug, Default)]
+pub struct FileCache {
+    path: PathBuf,
+    entries: HashMap<String, CachedFile>,
+}
+
+#[allow(dead_code)]
+impl FileCache {
+    /// Load the cache from the default location, starting empty if missing
+    pub fn load() -> Self {
+        Self::load_from(app_dir().join("file_cache.json"))
+    }
+
+    /// Load the cache from a specific file
+    pub fn load_from(path: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        Self { path, entries }
+    }
+
+    /// Write the cache back to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create cache directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.entries)
+            .map_err(|e| anyhow!("Failed to serialize file cache: {}", e))?;
+        std::fs::write(&self.path, json).map_err(|e| anyhow!("Failed to write file cache: {}", e))
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&CachedFile> {
+        self.entries.get(hash)
+    }
+
+    pub fn insert(&mut self, hash: String, entry: CachedFile) {
+        self.entries.insert(hash, entry);
+    }
+
+    pub fn remove(&mut self, hash: &str) -> Option<CachedFile> {
+        self.entries.remove(hash)
+    }
+
+    /// Drop any entry pointing at the given file ID
+    pub fn remove_file_id(&mut self, file_id: &str) {
+        self.entries.retain(|_, entry| entry.file_id != file_id);
+    }
+
+    /// Whether the file ID came from an upload through this cache
+    pub fn contains_file_id(&self, file_id: &str) -> bool {
+        self.entries.values().any(|entry| entry.file_id == file_id)
+    }
+
+    /// Record that a cached entry was confirmed to still exist remotely
+    pub fn mark_validated(&mut self, hash: &str, now: DateTime<Utc>) {
+        if let Some(entry) = self.entries.get_mut(hash) {
+            entry.validated_at = now;
+        }
+    }
+
+    /// Iterate over all cached entries
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &CachedFile)> {
+        self.entries.iter()
+    }
+}
+
+/// Hex-encoded SHA-256 of the given bytes
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Upload a file, reusing a cached file ID when the same contents were uploaded before.
+///
+/// Stale cache entries are checked against the Files API and transparently
+/// re-uploaded if the remote file has been deleted or can't be checked.
+/// The cache is only locked between network calls, so other uploads aren't held up.
+pub fn upload_file_cached(
+    api_key: &str,
+    cache: &Mutex<FileCache>,
+    path: &PathBuf,
+) -> Result<String> {
+    let file_bytes = std::fs::read(path)
+        .map_err(|e| anyhow!("Failed to read file {}: {}", path.display(), e))?;
+    let hash = content_hash(&file_bytes);
+    let now = Utc::now();
+
+    let cached = cache.lock().unwrap().get(&hash).cloned();
+    if let Some(entry) = cached {
+        if !entry.is_stale(now) {
+            tracing::debug!(
+                "Reusing cached file {} -> {}",
+                path.display(),
+                entry.file_id
+            );
+            return Ok(entry.file_id);
+        }
+
+        match get_file_metadata(api_key, &entry.file_id) {
+            Ok(Some(_)) => {
+                tracing::debug!("Validated cached file {}", entry.file_id);
+                let mut cache = cache.lock().unwrap();
+                cache.mark_validated(&hash, now);
+                save_cache(&cache);
+                return Ok(entry.file_id);
+            }
+            Ok(None) => {
+                tracing::debug!("Cached file {} was deleted, re-uploading", entry.file_id);
+            }
+            Err(e) => {
+                // A file that can't be confirmed might be gone; a fresh upload is safe
+                tracing::warn!(
+                    "Failed to validate cached file {}, re-uploading: {}",
+                    entry.file_id,
+                    e
+                );
+            }
+        }
+        cache.lock().unwrap().remove(&hash);
+    }
+
+    let size_bytes = file_bytes.len() as u64;
+    let file_id = upload_bytes(api_key, path, file_bytes)?;
+
+    let mut cache = cache.lock().unwrap();
+    cache.insert(
+        hash,
+        CachedFile {
+            file_id: file_id.clone(),
+            filename: path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("file")
+                .to_string(),
+            size_bytes,
+            uploaded_at: now,
+            validated_at: now,
+        },
+    );
+    save_cache(&cache);
+
+    Ok(file_id)
+}
+
+fn save_cache(cache: &FileCache) {
+    if let Err(e) = cache.save() {
+        tracing::warn!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(validated_at: DateTime<Utc>) -> CachedFile {
+        CachedFile {
+            file_id: "file_123".to_string(),
+            filename: "spec.pdf".to_string(),
+            size_bytes: 42,
+            uploaded_at: validated_at,
+            validated_at,
+        }
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(
+            content_hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_staleness() {
+        let now = Utc::now();
+        assert!(!entry(now).is_stale(now));
+        assert!(entry(now - Duration::hours(VALIDATION_TTL_HOURS + 1)).is_stale(now));
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let path = std::env::temp_dir().join(format!("chatai-cache-{}.json", std::process::id()));
+        let mut cache = FileCache::load_from(path.clone());
+        cache.insert("hash".to_string(), entry(Utc::now()));
+        cache.save().unwrap();
+
+        let reloaded = FileCache::load_from(path.clone());
+        assert_eq!(reloaded.get("hash").unwrap().file_id, "file_123");
+
+        let _ = std::fs::remove_file(path);
+    }
+}
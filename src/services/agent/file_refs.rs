@@ -0,0 +1,123 @@
+//! Tracks which conversations reference each uploaded file.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::services::paths::app_dir;
+
+/// Prefix of chat conversation IDs. Chats aren't saved, so their references
+/// don't outlive the app; batch references are kept until the file is deleted.
+pub const CHAT_ID_PREFIX: &str = "conv-";
+
+/// A conversation that sent a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationRef {
+    pub id: String,
+    pub title: String,
+}
+
+/// File ID → referencing conversations, persisted as JSON
+#[derive(Debug, Default)]
+pub struct FileReferences {
+    path: PathBuf,
+    refs: HashMap<String, Vec<ConversationRef>>,
+}
+
+#[allow(dead_code)]
+impl FileReferences {
+    /// Load references from the default location, starting empty if missing
+    pub fn load() -> Self {
+        Self::load_from(app_dir().join("file_refs.json"))
+    }
+
+    /// Load references from a specific file
+    pub fn load_from(path: PathBuf) -> Self {
+        let refs = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        Self { path, refs }
+    }
+
+    /// Write references back to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create references directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.refs)
+            .map_err(|e| anyhow!("Failed to serialize file references: {}", e))?;
+        std::fs::write(&self.path, json)
+            .map_err(|e| anyhow!("Failed to write file references: {}", e))
+    }
+
+    /// Record that a conversation sent the given file
+    pub fn record(&mut self, file_id: &str, conversation: ConversationRef) {
+        let refs = self.refs.entry(file_id.to_string()).or_default();
+        if !refs.iter().any(|r| r.id == conversation.id) {
+            refs.push(conversation);
+        }
+    }
+
+    /// Conversations that sent the given file
+    pub fn conversations(&self, file_id: &str) -> &[ConversationRef] {
+        self.refs
+            .get(file_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Drop all references to a deleted file
+    pub fn forget(&mut self, file_id: &str) {
+        self.refs.remove(file_id);
+    }
+
+    /// Drop an ended conversation's references. Its files stay known as
+    /// uploaded by this app.
+    pub fn forget_conversation(&mut self, conversation_id: &str) {
+        for refs in self.refs.values_mut() {
+            refs.retain(|r| r.id != conversation_id);
+        }
+    }
+
+    /// Drop references from chats of earlier runs, which can't be continued
+    pub fn forget_chats(&mut self) {
+        for refs in self.refs.values_mut() {
+            refs.retain(|r| !r.id.starts_with(CHAT_ID_PREFIX));
+        }
+    }
+
+    /// Whether this app ever recorded the file
+    pub fn contains(&self, file_id: &str) -> bool {
+        self.refs.contains_key(file_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_deduplicates_conversations() {
+        let mut refs = FileReferences::default();
+        let conversation = ConversationRef {
+            id: "conv-1".to_string(),
+            title: "Summarize spec".to_string(),
+        };
+
+        refs.record("file_1", conversation.clone());
+        refs.record("file_1", conversation.clone());
+
+        assert_eq!(refs.conversations("file_1"), &[conversation]);
+        assert!(refs.conversations("file_2").is_empty());
+
+        refs.forget_conversation("conv-1");
+        assert!(refs.conversations("file_1").is_empty());
+        assert!(refs.contains("file_1"));
+
+        refs.forget("file_1");
+        assert!(!refs.contains("file_1"));
+    }
+}
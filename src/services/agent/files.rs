@@ -1,17 +1,60 @@
 //! Files API client for uploading files to Anthropic.
 
 use anyhow::{Result, anyhow};
-use serde::Deserialize;
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const FILES_URL: &str = "https://api.anthropic.com/v1/files";
+
+/// Page size used when listing files
+const LIST_PAGE_SIZE: u32 = 100;
 
 #[derive(Debug, Deserialize)]
 struct FileUploadResponse {
     id: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct FileListResponse {
+    data: Vec<FileMetadata>,
+    has_more: bool,
+    last_id: Option<String>,
+}
+
+/// Metadata for a file stored with the Files API
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub id: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size_bytes: u64,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub downloadable: bool,
+}
+
+/// Headers shared by every Files API request
+fn files_headers(api_key: &str) -> Vec<(String, String)> {
+    vec![
+        ("x-api-key".to_string(), api_key.to_string()),
+        ("anthropic-version".to_string(), "2023-06-01".to_string()),
+        (
+            "anthropic-beta".to_string(),
+            "files-api-2025-04-14".to_string(),
+        ),
+    ]
+}
+
 /// Get MIME type from file extension
-fn get_mime_type(path: &PathBuf) -> &'static str {
-    match path.extension().and_then(|e| e.to_str()) {
+pub fn get_mime_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .as_deref()
+    {
         Some("pdf") => "application/pdf",
         Some("txt") => "text/plain",
         Some("md") => "text/plain",
@@ -25,15 +68,26 @@ fn get_mime_type(path: &PathBuf) -> &'static str {
     }
 }
 
+/// Whether the file should be sent as an image block rather than a document
+pub fn is_image_file(path: &Path) -> bool {
+    get_mime_type(path).starts_with("image/")
+}
+
 /// Upload a file to the Anthropic Files API
+#[allow(dead_code)]
 pub fn upload_file(api_key: &str, path: &PathBuf) -> Result<String> {
+    let file_bytes = std::fs::read(path)
+        .map_err(|e| anyhow!("Failed to read file {}: {}", path.display(), e))?;
+
+    upload_bytes(api_key, path, file_bytes)
+}
+
+/// Upload already-read file contents to the Anthropic Files API
+pub fn upload_bytes(api_key: &str, path: &Path, file_bytes: Vec<u8>) -> Result<String> {
     let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
 
     let mime_type = get_mime_type(path);
 
-    let file_bytes = std::fs::read(path)
-        .map_err(|e| anyhow!("Failed to read file {}: {}", path.display(), e))?;
-
     // Build multipart form data manually
     let boundary = "----AnthropicFileBoundary";
     let mut body = Vec::new();
@@ -54,21 +108,16 @@ pub fn upload_file(api_key: &str, path: &PathBuf) -> Result<String> {
     // End boundary
     body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
 
-    let response = smolhttp::Client::new("https://api.anthropic.com/v1/files")
+    let mut headers = files_headers(api_key);
+    headers.push((
+        "content-type".to_string(),
+        format!("multipart/form-data; boundary={}", boundary),
+    ));
+
+    let response = smolhttp::Client::new(FILES_URL)
         .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
         .post()
-        .headers(vec![
-            ("x-api-key".to_string(), api_key.to_string()),
-            ("anthropic-version".to_string(), "2023-06-01".to_string()),
-            (
-                "anthropic-beta".to_string(),
-                "files-api-2025-04-14".to_string(),
-            ),
-            (
-                "content-type".to_string(),
-                format!("multipart/form-data; boundary={}", boundary),
-            ),
-        ])
+        .headers(headers)
         .body(body)
         .send()
         .map_err(|e| anyhow!("File upload request failed: {}", e))?;
@@ -97,3 +146,117 @@ pub fn upload_file(api_key: &str, path: &PathBuf) -> Result<String> {
 
     Ok(upload_response.id)
 }
+
+/// Fetch metadata for an uploaded file.
+/// Returns `None` if the file no longer exists on the server.
+pub fn get_file_metadata(api_key: &str, file_id: &str) -> Result<Option<FileMetadata>> {
+    let response = smolhttp::Client::new(&format!("{}/{}", FILES_URL, file_id))
+        .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+        .get()
+        .headers(files_headers(api_key))
+        .send()
+        .map_err(|e| anyhow!("File metadata request failed: {}", e))?;
+
+    if response.status_code() == 404 {
+        return Ok(None);
+    }
+
+    let response_text = response.text();
+
+    if response_text.contains("\"error\"") {
+        return Err(anyhow!("File metadata error: {}", response_text));
+    }
+
+    let metadata: FileMetadata = serde_json::from_str(&response_text).map_err(|e| {
+        anyhow!(
+            "Failed to parse file metadata: {}. Response: {}",
+            e,
+            response_text
+        )
+    })?;
+
+    Ok(Some(metadata))
+}
+
+/// List every file uploaded to the organization, following pagination
+pub fn list_files(api_key: &str) -> Result<Vec<FileMetadata>> {
+    let mut files = Vec::new();
+    let mut after_id: Option<String> = None;
+
+    loop {
+        let mut url = format!("{}?limit={}", FILES_URL, LIST_PAGE_SIZE);
+        if let Some(after_id) = &after_id {
+            url.push_str(&format!("&after_id={}", after_id));
+        }
+
+        let response = smolhttp::Client::new(&url)
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+            .get()
+            .headers(files_headers(api_key))
+            .send()
+            .map_err(|e| anyhow!("File list request failed: {}", e))?;
+
+        let response_text = response.text();
+
+        if response_text.contains("\"error\"") {
+            return Err(anyhow!("File list error: {}", response_text));
+        }
+
+        let page: FileListResponse = serde_json::from_str(&response_text).map_err(|e| {
+            anyhow!(
+                "Failed to parse file list: {}. Response: {}",
+                e,
+                response_text
+            )
+        })?;
+
+        files.extend(page.data);
+
+        match page.last_id {
+            Some(last_id) if page.has_more => after_id = Some(last_id),
+            _ => break,
+        }
+    }
+
+    Ok(files)
+}
+
+/// Delete an uploaded file. Files that are already gone are treated as deleted.
+pub fn delete_file(api_key: &str, file_id: &str) -> Result<()> {
+    let response = smolhttp::Client::new(&format!("{}/{}", FILES_URL, file_id))
+        .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+        .delete()
+        .headers(files_headers(api_key))
+        .send()
+        .map_err(|e| anyhow!("File delete request failed: {}", e))?;
+
+    if response.status_code() == 404 {
+        return Ok(());
+    }
+
+    let response_text = response.text();
+
+    if response_text.contains("\"error\"") {
+        return Err(anyhow!("File delete error: {}", response_text));
+    }
+
+    tracing::debug!("Deleted file {}", file_id);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_type_ignores_extension_case() {
+        assert_eq!(get_mime_type(Path::new("photo.JPG")), "image/jpeg");
+        assert!(is_image_file(Path::new("scan.Png")));
+        assert_eq!(get_mime_type(Path::new("notes.MD")), "text/plain");
+        assert_eq!(
+            get_mime_type(Path::new("archive")),
+            "application/octet-stream"
+        );
+    }
+}
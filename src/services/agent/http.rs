@@ -0,0 +1,366 @@
+//! HTTP transport shared by the providers.
+//!
+//! smolhttp always negotiates TLS, so plain `http://` URLs (local model
+//! servers) are sent over a bare TCP stream instead. smolhttp also buffers
+//! whole responses, so streamed replies use their own connection.
+
+use anyhow::{Result, anyhow};
+use minihttpse::Response;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs as _},
+    time::Duration,
+};
+
+/// How long to wait for a plain HTTP server to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a plain HTTP server may go without sending or accepting anything.
+/// Local models on slow hardware can take minutes before a whole reply is ready.
+const PLAIN_IO_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long a streamed response may go without sending anything.
+/// Servers send keep-alive events well within this.
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Where a request goes
+#[derive(Debug, PartialEq)]
+struct Endpoint {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+/// Split an `http://` or `https://` URL into its parts
+fn parse_url(url: &str) -> Option<Endpoint> {
+    let (tls, rest) = match url.strip_prefix("https://") {
+        Some(rest) => (true, rest),
+        None => (false, url.strip_prefix("http://")?),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(ix) => (&rest[..ix], rest[ix..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, if tls { 443 } else { 80 }),
+    };
+    Some(Endpoint {
+        tls,
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+/// Open a TCP connection to the endpoint
+fn connect(endpoint: &Endpoint, url: &str) -> Result<TcpStream> {
+    let addr = (endpoint.host.as_str(), endpoint.port)
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("Failed to resolve {}: {}", endpoint.host, e))?
+        .next()
+        .ok_or_else(|| anyhow!("Failed to resolve {}", endpoint.host))?;
+    TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+        .map_err(|e| anyhow!("Failed to connect to {}: {}", url, e))
+}
+
+/// Write the request line, headers and body
+fn write_request(
+    stream: &mut impl Write,
+    method: &str,
+    endpoint: &Endpoint,
+    headers: Vec<(String, String)>,
+    body: Option<&[u8]>,
+) -> Result<()> {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: Close\r\n",
+        method, endpoint.path, endpoint.host, endpoint.port
+    );
+    if let Some(body) = body {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes())?;
+    if let Some(body) = body {
+        stream.write_all(body)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+/// Send a request over plain TCP
+fn send_plain(
+    method: &str,
+    url: &str,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+) -> Result<Response> {
+    let endpoint = parse_url(url)
+        .filter(|endpoint| !endpoint.tls)
+        .ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
+    let mut stream = connect(&endpoint, url)?;
+    stream.set_read_timeout(Some(PLAIN_IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(PLAIN_IO_TIMEOUT))?;
+    write_request(&mut stream, method, &endpoint, headers, body.as_deref())?;
+
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| anyhow!("No response from {}: {}", url, e))?;
+    Response::new(response).map_err(|e| anyhow!("Failed to parse response: {:?}", e))
+}
+
+/// Send a request, using TLS for `https://` URLs and plain TCP otherwise
+pub fn send(
+    method: &str,
+    url: &str,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+) -> Result<Response> {
+    if url.starts_with("http://") {
+        return send_plain(method, url, headers, body);
+    }
+
+    let mut client =
+        smolhttp::Client::new(url).map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+    match method {
+        "GET" => client.get(),
+        "POST" => client.post(),
+        "DELETE" => client.delete(),
+        other => return Err(anyhow!("Unsupported method {}", other)),
+    };
+    client.headers(headers);
+    if let Some(body) = body {
+        client.body(body);
+    }
+    client.send().map_err(|e| anyhow!("Request failed: {}", e))
+}
+
+/// A connection that may or may not be encrypted
+trait Connection: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Connection for T {}
+
+/// Decodes a `Transfer-Encoding: chunked` body
+struct ChunkedReader<R> {
+    inner: R,
+    /// Bytes left in the current chunk
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            // Chunk extensions follow a `;`
+            let size = line.split(';').next().unwrap_or_default().trim();
+            self.remaining = usize::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size"))?;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read;
+        if self.remaining == 0 {
+            // Each chunk ends with a line break
+            self.inner.read_line(&mut String::new())?;
+        }
+        Ok(read)
+    }
+}
+
+/// A response whose body is read as it arrives
+pub struct StreamingResponse {
+    status: u16,
+    body: Box<dyn BufRead + Send>,
+}
+
+impl StreamingResponse {
+    pub fn status_code(&self) -> u16 {
+        self.status
+    }
+
+    /// Read the rest of the body, for error responses
+    pub fn text(mut self) -> String {
+        let mut text = String::new();
+        let _ = self.body.read_to_string(&mut text);
+        text
+    }
+
+    /// The data of the next server-sent event, or `None` at the end of the stream
+    pub fn next_event(&mut self) -> Result<Option<String>> {
+        next_event(&mut self.body)
+    }
+}
+
+/// Read one server-sent event, joining its `data:` lines. Event names, IDs
+/// and comments are skipped; the providers put the event type in the data.
+fn next_event(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut data: Option<String> = None;
+    loop {
+        let mut line = String::new();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| anyhow!("Failed to read response stream: {}", e))?;
+        if read == 0 {
+            return Ok(data);
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            // A blank line ends the event
+            if data.is_some() {
+                return Ok(data);
+            }
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("data:") {
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match &mut data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => data = Some(value.to_string()),
+            }
+        }
+    }
+}
+
+/// Send a request and return once the response headers arrive, leaving the
+/// body to be read as the server sends it
+pub fn send_streaming(
+    method: &str,
+    url: &str,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+) -> Result<StreamingResponse> {
+    let endpoint = parse_url(url).ok_or_else(|| anyhow!("Invalid URL: {}", url))?;
+    let stream = connect(&endpoint, url)?;
+    stream.set_read_timeout(Some(STREAM_IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(STREAM_IDLE_TIMEOUT))?;
+    let mut stream: Box<dyn Connection> = if endpoint.tls {
+        let connector =
+            native_tls::TlsConnector::new().map_err(|e| anyhow!("Failed to set up TLS: {}", e))?;
+        Box::new(
+            connector
+                .connect(&endpoint.host, stream)
+                .map_err(|e| anyhow!("TLS handshake with {} failed: {}", endpoint.host, e))?,
+        )
+    } else {
+        Box::new(stream)
+    };
+    write_request(&mut stream, method, &endpoint, headers, Some(&body))?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid response from {}: {}", url, status_line.trim()))?;
+
+    let mut chunked = false;
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<u64>().ok();
+            }
+        }
+    }
+
+    let body: Box<dyn BufRead + Send> = if chunked {
+        Box::new(BufReader::new(ChunkedReader {
+            inner: reader,
+            remaining: 0,
+            done: false,
+        }))
+    } else if let Some(length) = content_length {
+        Box::new(reader.take(length))
+    } else {
+        Box::new(reader)
+    };
+    Ok(StreamingResponse { status, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("http://localhost:11434/api/chat"),
+            Some(Endpoint {
+                tls: false,
+                host: "localhost".to_string(),
+                port: 11434,
+                path: "/api/chat".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_url("https://example.com"),
+            Some(Endpoint {
+                tls: true,
+                host: "example.com".to_string(),
+                port: 443,
+                path: "/".to_string(),
+            })
+        );
+        assert_eq!(parse_url("ftp://example.com"), None);
+    }
+
+    #[test]
+    fn test_chunked_server_sent_events() {
+        // The first event is split across chunks
+        let parts = [
+            "event: message\r\ndata: {\"a\":\r\n",
+            "data: 1}\r\n\r\ndata: [DONE]\r\n\r\n",
+        ];
+        let mut body = String::new();
+        for part in parts {
+            body.push_str(&format!("{:x};ext=1\r\n{}\r\n", part.len(), part));
+        }
+        body.push_str("0\r\n\r\n");
+        let mut reader = BufReader::new(ChunkedReader {
+            inner: body.as_bytes(),
+            remaining: 0,
+            done: false,
+        });
+        assert_eq!(
+            next_event(&mut reader).unwrap().as_deref(),
+            Some("{\"a\":\n1}")
+        );
+        assert_eq!(next_event(&mut reader).unwrap().as_deref(), Some("[DONE]"));
+        assert_eq!(next_event(&mut reader).unwrap(), None);
+    }
+}
@@ -0,0 +1,478 @@
+//! Models served from this machine by Ollama or a llama.cpp server.
+//!
+//! Neither needs an API key. Server locations come from `OLLAMA_HOST` and
+//! `LLAMA_CPP_URL`, defaulting to their standard localhost ports.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::HashMap, env};
+
+use super::http;
+use super::openai::OpenAiProvider;
+use super::provider::{ChatRequest, ChatResponse, LlmProvider, ProviderKind, Usage};
+use super::types::{ContentBlock, FileSource, Message, ToolChoice, ToolDefinition};
+
+const DEFAULT_OLLAMA_HOST: &str = "http://localhost:11434";
+const DEFAULT_LLAMA_CPP_URL: &str = "http://localhost:8080/v1";
+
+/// A model found on a local server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalModel {
+    pub id: String,
+    pub provider: ProviderKind,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaTag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTag {
+    name: String,
+}
+
+/// The parts of `/api/show` that tell the context window
+#[derive(Debug, Deserialize)]
+struct OllamaShow {
+    /// Modelfile parameters, one `name value` pair per line
+    #[serde(default)]
+    parameters: String,
+    /// Architecture details, with keys such as `llama.context_length`
+    #[serde(default)]
+    model_info: HashMap<String, Value>,
+}
+
+impl OllamaShow {
+    /// The window set with `num_ctx`, or else the one the model was trained with
+    fn context_length(&self) -> Option<u32> {
+        let num_ctx = self.parameters.lines().find_map(|line| {
+            let (name, value) = line.split_once(char::is_whitespace)?;
+            (name == "num_ctx").then(|| value.trim().parse().ok())?
+        });
+        num_ctx.or_else(|| {
+            self.model_info
+                .iter()
+                .find(|(key, _)| key.ends_with(".context_length"))
+                .and_then(|(_, value)| value.as_u64())
+                .map(|length| length.min(u32::MAX as u64) as u32)
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    model: String,
+    message: OllamaMessage,
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: u32,
+    #[serde(default)]
+    eval_count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaMessage {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunction,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaFunction {
+    name: String,
+    arguments: Value,
+}
+
+/// Base URL of the Ollama server
+fn ollama_host() -> String {
+    let host = env::var("OLLAMA_HOST").unwrap_or_else(|_| DEFAULT_OLLAMA_HOST.to_string());
+    // OLLAMA_HOST is often set without a scheme, e.g. 127.0.0.1:11434
+    let host = if host.contains("://") {
+        host
+    } else {
+        format!("http://{}", host)
+    };
+    host.trim_end_matches('/').to_string()
+}
+
+/// Base URL of the llama.cpp server's OpenAI-compatible API
+fn llama_cpp_url() -> String {
+    env::var("LLAMA_CPP_URL")
+        .unwrap_or_else(|_| DEFAULT_LLAMA_CPP_URL.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Provider for a llama.cpp server
+pub fn llama_cpp_provider() -> OpenAiProvider {
+    OpenAiProvider::compatible(ProviderKind::LlamaCpp, llama_cpp_url())
+}
+
+/// GET a JSON document from a local server
+fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
+    let response = http::send("GET", url, vec![], None)?;
+    if response.status_code() >= 400 {
+        return Err(anyhow!("{} returned {}", url, response.status_code()));
+    }
+    let text = response.text();
+    serde_json::from_str(&text).map_err(|e| anyhow!("Failed to parse {}: {}", url, e))
+}
+
+/// List the models installed on any reachable local server.
+/// Servers that aren't running are skipped.
+pub fn discover_local_models() -> Vec<LocalModel> {
+    let mut models = Vec::new();
+
+    match get_json::<OllamaTags>(&format!("{}/api/tags", ollama_host())) {
+        Ok(tags) => models.extend(tags.models.into_iter().map(|tag| LocalModel {
+            id: tag.name,
+            provider: ProviderKind::Ollama,
+        })),
+        Err(e) => tracing::debug!("Ollama not available: {}", e),
+    }
+
+    match get_json::<ModelList>(&format!("{}/models", llama_cpp_url())) {
+        Ok(list) => models.extend(list.data.into_iter().map(|model| LocalModel {
+            id: model.id,
+            provider: ProviderKind::LlamaCpp,
+        })),
+        Err(e) => tracing::debug!("llama.cpp not available: {}", e),
+    }
+
+    models
+}
+
+/// The context window of an Ollama model, if the server reports one
+pub fn ollama_context_length(model: &str) -> Result<Option<u32>> {
+    let url = format!("{}/api/show", ollama_host());
+    let response = http::send(
+        "POST",
+        &url,
+        vec![("content-type".to_string(), "application/json".to_string())],
+        Some(json!({ "model": model }).to_string().into()),
+    )?;
+    if response.status_code() >= 400 {
+        return Err(anyhow!("{} returned {}", url, response.status_code()));
+    }
+    let show: OllamaShow = serde_json::from_str(&response.text())
+        .map_err(|e| anyhow!("Failed to parse {}: {}", url, e))?;
+    Ok(show.context_length())
+}
+
+/// Provider for Ollama's native `/api/chat` endpoint
+pub struct OllamaProvider {
+    host: String,
+}
+
+impl OllamaProvider {
+    pub fn from_env() -> Self {
+        Self {
+            host: ollama_host(),
+        }
+    }
+}
+
+/// Convert a tool definition to Ollama's function tool format
+fn tool_to_ollama(tool: &ToolDefinition) -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": tool.name,
+            "description": tool.description,
+            "parameters": tool.input_schema,
+        }
+    })
+}
+
+/// Convert the conversation to Ollama chat messages.
+/// Ollama doesn't use tool call IDs, so tool results are matched back by name.
+fn messages_to_ollama(system_prompt: &str, messages: &[Message]) -> Result<Vec<Value>> {
+    let mut out = Vec::new();
+    if !system_prompt.is_empty() {
+        out.push(json!({ "role": "system", "content": system_prompt }));
+    }
+
+    let mut tool_names: HashMap<&str, &str> = HashMap::new();
+
+    for message in messages {
+        match message {
+            Message::User { content, .. } => {
+                let mut text = Vec::new();
+                let mut images = Vec::new();
+
+                for block in content {
+                    match block {
+                        ContentBlock::Text { text: t, .. } => text.push(t.as_str()),
+                        ContentBlock::Image {
+                            source: FileSource::Base64 { data, .. },
+                            ..
+                        } => images.push(data.clone()),
+                        ContentBlock::ToolResult {
+                            tool_use_id,
+                            content,
+                            ..
+                        } => {
+                            let tool_name = tool_names
+                                .get(tool_use_id.as_str())
+                                .copied()
+                                .unwrap_or_default();
+                            out.push(json!({
+                                "role": "tool",
+                                "content": content,
+                                "tool_name": tool_name,
+                            }));
+                        }
+                        ContentBlock::Document { .. } => {
+                            return Err(anyhow!("Local models can't read PDF attachments"));
+                        }
+                        ContentBlock::Image {
+                            source: FileSource::File { .. },
+                            ..
+                        }
+                        | ContentBlock::ToolUse { .. }
+                        | ContentBlock::Thinking { .. }
+                        | ContentBlock::RedactedThinking { .. } => {}
+                    }
+                }
+
+                if !text.is_empty() || !images.is_empty() {
+                    let mut message = json!({ "role": "user", "content": text.join("\n\n") });
+                    if !images.is_empty() {
+                        message["images"] = json!(images);
+                    }
+                    out.push(message);
+                }
+            }
+            Message::Assistant { content, .. } => {
+                let mut text = Vec::new();
+                let mut tool_calls = Vec::new();
+
+                for block in content {
+                    match block {
+                        ContentBlock::Text { text: t, .. } => text.push(t.as_str()),
+                        ContentBlock::ToolUse { id, name, input } => {
+                            tool_names.insert(id, name);
+                            tool_calls.push(json!({
+                                "function": { "name": name, "arguments": input },
+                            }));
+                        }
+                        _ => {}
+                    }
+                }
+
+                let mut message = json!({ "role": "assistant", "content": text.join("\n") });
+                if !tool_calls.is_empty() {
+                    message["tool_calls"] = Value::Array(tool_calls);
+                }
+                out.push(message);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Build the `/api/chat` body for a request
+fn request_to_ollama(request: &ChatRequest) -> Result<Value> {
+    let mut body = json!({
+        "model": request.model,
+        "messages": messages_to_ollama(&request.system_prompt, &request.messages)?,
+        "stream": false,
+        "options": { "num_predict": request.max_tokens },
+    });
+    // Ollama has no `tool_choice`: tools can't be forced, only left out
+    let no_tools = request.tool_choice == Some(ToolChoice::None);
+    if !request.tools.is_empty() && !no_tools {
+        body["tools"] = request.tools.iter().map(tool_to_ollama).collect();
+    }
+    let sampling = &request.sampling;
+    if let Some(temperature) = sampling.temperature {
+        body["options"]["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = sampling.top_p {
+        body["options"]["top_p"] = json!(top_p);
+    }
+    if let Some(top_k) = sampling.top_k {
+        body["options"]["top_k"] = json!(top_k);
+    }
+    if !sampling.stop_sequences.is_empty() {
+        body["options"]["stop"] = json!(sampling.stop_sequences);
+    }
+    // Without `num_ctx` Ollama loads the model with its small default
+    // window, not the one conversations are compacted against
+    if let Some(context_window) = request.context_window {
+        body["options"]["num_ctx"] = json!(context_window);
+    }
+    Ok(body)
+}
+
+/// Convert an Ollama chat response into an assistant turn
+fn response_from_ollama(response: OllamaChatResponse) -> ChatResponse {
+    let mut content = Vec::new();
+    if !response.message.content.is_empty() {
+        content.push(ContentBlock::text(response.message.content));
+    }
+
+    let has_tool_calls = !response.message.tool_calls.is_empty();
+    for (ix, call) in response.message.tool_calls.into_iter().enumerate() {
+        content.push(ContentBlock::ToolUse {
+            id: format!("call_{}", ix),
+            name: call.function.name,
+            input: call.function.arguments,
+        });
+    }
+
+    let stop_reason = match response.done_reason.as_deref() {
+        _ if has_tool_calls => "tool_use",
+        Some("length") => "max_tokens",
+        _ => "end_turn",
+    };
+
+    ChatResponse {
+        model: response.model,
+        content,
+        stop_reason: Some(stop_reason.to_string()),
+        usage: Usage {
+            input_tokens: response.prompt_eval_count,
+            output_tokens: response.eval_count,
+            ..Default::default()
+        },
+    }
+}
+
+impl LlmProvider for OllamaProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Ollama
+    }
+
+    fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let body = request_to_ollama(request)?;
+        let response = http::send(
+            "POST",
+            &format!("{}/api/chat", self.host),
+            vec![("content-type".to_string(), "application/json".to_string())],
+            Some(body.to_string().into()),
+        )
+        .map_err(|e| anyhow!("API request failed: {}", e))?;
+
+        let status = response.status_code();
+        let response_text = response.text();
+
+        if status >= 400 {
+            return Err(anyhow!("API error ({}): {}", status, response_text));
+        }
+
+        let response: OllamaChatResponse = serde_json::from_str(&response_text).map_err(|e| {
+            anyhow!(
+                "Failed to parse response: {}. Response: {}",
+                e,
+                response_text
+            )
+        })?;
+
+        Ok(response_from_ollama(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ollama_tool_round_trip() {
+        let response: OllamaChatResponse = serde_json::from_value(json!({
+            "model": "llama3.2",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "function": { "name": "weather", "arguments": { "city": "Oslo" } }
+                }]
+            },
+            "done_reason": "stop",
+            "prompt_eval_count": 12,
+            "eval_count": 3
+        }))
+        .unwrap();
+
+        let response = response_from_ollama(response);
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+
+        let messages = vec![
+            Message::Assistant {
+                role: "assistant".to_string(),
+                content: response.content,
+            },
+            Message::User {
+                role: "user".to_string(),
+                content: vec![ContentBlock::ToolResult {
+                    tool_use_id: "call_0".to_string(),
+                    content: "Sunny".to_string(),
+                    is_error: None,
+                    cache_control: None,
+                }],
+            },
+        ];
+
+        let out = messages_to_ollama("", &messages).unwrap();
+        assert_eq!(
+            out[0]["tool_calls"][0]["function"]["arguments"]["city"],
+            "Oslo"
+        );
+        assert_eq!(out[1]["role"], "tool");
+        assert_eq!(out[1]["tool_name"], "weather");
+    }
+
+    #[test]
+    fn test_ollama_context_length() {
+        let show: OllamaShow = serde_json::from_value(json!({
+            "parameters": "stop \"<|eot_id|>\"",
+            "model_info": { "general.architecture": "llama", "llama.context_length": 131072 }
+        }))
+        .unwrap();
+        assert_eq!(show.context_length(), Some(131_072));
+
+        let show: OllamaShow = serde_json::from_value(json!({
+            "parameters": "num_ctx                        16384\ntemperature 0.7",
+            "model_info": { "llama.context_length": 131072 }
+        }))
+        .unwrap();
+        assert_eq!(show.context_length(), Some(16_384));
+
+        // The window in use is sent, so Ollama doesn't fall back to its default
+        let mut request = ChatRequest {
+            model: "llama3.2".to_string(),
+            system_prompt: String::new(),
+            max_tokens: 1024,
+            messages: Vec::new(),
+            tools: Vec::new(),
+            tool_choice: None,
+            thinking_budget: None,
+            sampling: Default::default(),
+            context_window: Some(131_072),
+        };
+        let body = request_to_ollama(&request).unwrap();
+        assert_eq!(body["options"]["num_ctx"], 131_072);
+        request.context_window = None;
+        let body = request_to_ollama(&request).unwrap();
+        assert!(body["options"].get("num_ctx").is_none());
+    }
+}
@@ -0,0 +1,698 @@
+//! Model Context Protocol client for servers run over stdio.
+//!
+//! Servers are listed in `mcp_servers.json` in the same shape other MCP
+//! clients use. Each one is launched as a child process speaking
+//! newline-delimited JSON-RPC. Its tools are registered with the agent under
+//! `<server>__<tool>` names, and their calls are forwarded to `tools/call`.
+
+use anyhow::{Result, anyhow};
+use async_channel::Sender;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::command::{kill_tree, scrub_env};
+use super::tools::{CancelToken, ToolHandler};
+use super::types::Tool;
+use crate::services::paths::app_dir;
+
+/// Protocol revision sent in `initialize`
+const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// Time a server has to start and answer the handshake and tool listing
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How often a waiting tool call checks whether it was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Most `tools/list` pages read from one server, in case it keeps sending cursors
+const MAX_TOOL_PAGES: usize = 20;
+
+/// Longest tool name the model APIs accept
+const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// How to launch one server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct McpServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Variables set for the server, on top of the scrubbed environment
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// The servers to launch, by name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct McpConfig {
+    #[serde(rename = "mcpServers", default)]
+    pub servers: BTreeMap<String, McpServerConfig>,
+}
+
+impl McpConfig {
+    pub fn path() -> PathBuf {
+        app_dir().join("mcp_servers.json")
+    }
+
+    /// Load the configured servers. A missing file means no servers.
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| anyhow!("Invalid {}: {}", path.display(), e))
+    }
+}
+
+/// Whether a server started, as shown in settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum McpServerState {
+    /// Connected, offering this many tools
+    Running(usize),
+    Failed(String),
+    /// Started, then stopped on its own
+    Exited,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct McpServerStatus {
+    pub name: String,
+    pub state: McpServerState,
+    /// Tools left out because their name, once made valid, is already taken
+    #[serde(default)]
+    pub skipped_tools: Vec<String>,
+}
+
+/// Requests waiting for an answer, by JSON-RPC ID
+type Pending = Arc<Mutex<HashMap<u64, mpsc::Sender<Result<Value>>>>>;
+
+/// A connection to a running server. The server is stopped when this is dropped.
+pub struct McpClient {
+    name: String,
+    child: Mutex<Child>,
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: Pending,
+    /// Set once the server closes its stdout
+    exited: Arc<AtomicBool>,
+    /// Set when this client stops the server, so that exit isn't reported
+    stopping: Arc<AtomicBool>,
+    next_id: AtomicU64,
+}
+
+/// Write one JSON-RPC message as a line
+fn send(stdin: &Mutex<ChildStdin>, message: &Value) -> Result<()> {
+    let mut stdin = stdin.lock().unwrap();
+    serde_json::to_writer(&mut *stdin, message)
+        .map_err(|e| anyhow!("Failed to send message: {}", e))?;
+    stdin
+        .write_all(b"\n")
+        .and_then(|_| stdin.flush())
+        .map_err(|e| anyhow!("Failed to send message: {}", e))
+}
+
+impl McpClient {
+    /// Launch a server and complete the `initialize` handshake. The server's
+    /// name is sent on `exit_tx` if it later stops on its own.
+    pub fn connect(name: &str, config: &McpServerConfig, exit_tx: Sender<String>) -> Result<Self> {
+        let mut command = Command::new(&config.command);
+        command
+            .args(&config.args)
+            .env_clear()
+            .envs(scrub_env(std::env::vars_os()))
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Launchers like npx start the real server as a child, so the server
+        // leads its own group and stopping it stops them too
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow!("Can't start {}: {}", config.command, e))?;
+
+        let stdin = Arc::new(Mutex::new(child.stdin.take().expect("stdin is piped")));
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let pending = Pending::default();
+        let exited = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        thread::spawn({
+            let name = name.to_string();
+            move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    tracing::debug!("MCP server {}: {}", name, line);
+                }
+            }
+        });
+        thread::spawn({
+            let name = name.to_string();
+            let stdin = stdin.clone();
+            let pending = pending.clone();
+            let exited = exited.clone();
+            let stopping = stopping.clone();
+            move || {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    handle_message(&name, &line, &stdin, &pending);
+                }
+                exited.store(true, Ordering::SeqCst);
+                for (_, tx) in pending.lock().unwrap().drain() {
+                    let _ = tx.send(Err(anyhow!("MCP server {} exited", name)));
+                }
+                if !stopping.load(Ordering::SeqCst) {
+                    tracing::warn!("MCP server {} exited", name);
+                    let _ = exit_tx.try_send(name);
+                }
+            }
+        });
+
+        let client = Self {
+            name: name.to_string(),
+            child: Mutex::new(child),
+            stdin,
+            pending,
+            exited,
+            stopping,
+            next_id: AtomicU64::new(1),
+        };
+
+        let result = client.request(
+            "initialize",
+            json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": { "name": "chatai", "version": env!("CARGO_PKG_VERSION") }
+            }),
+            CONNECT_TIMEOUT,
+            &CancelToken::default(),
+        )?;
+        if result.get("protocolVersion").is_none() {
+            return Err(anyhow!("{} sent an invalid initialize result", name));
+        }
+        send(
+            &client.stdin,
+            &json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+        )?;
+        Ok(client)
+    }
+
+    /// Send a request and wait for its result. A request that times out or is
+    /// cancelled is cancelled on the server too.
+    pub fn request(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+        cancel: &CancelToken,
+    ) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        // Checked after registering, so a request can't miss the exit
+        if self.exited.load(Ordering::SeqCst) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(anyhow!("MCP server {} exited", self.name));
+        }
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(e) = send(&self.stdin, &message) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(wait.min(CANCEL_POLL_INTERVAL)) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Timeout)
+                    if Instant::now() < deadline && !cancel.is_cancelled() => {}
+                Err(_) => break,
+            }
+        }
+
+        self.pending.lock().unwrap().remove(&id);
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": id, "reason": "Timed out" }
+        });
+        if let Err(e) = send(&self.stdin, &notification) {
+            tracing::debug!("MCP server {}: {}", self.name, e);
+        }
+        cancel.check()?;
+        Err(anyhow!(
+            "{} didn't answer {} within {:?}",
+            self.name,
+            method,
+            timeout
+        ))
+    }
+
+    /// Whether the server has stopped
+    pub fn has_exited(&self) -> bool {
+        self.exited.load(Ordering::SeqCst)
+    }
+
+    /// Every tool the server offers, following up to `MAX_TOOL_PAGES` pages
+    pub fn list_tools(&self) -> Result<Vec<Tool>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        for _ in 0..MAX_TOOL_PAGES {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request(
+                "tools/list",
+                params,
+                CONNECT_TIMEOUT,
+                &CancelToken::default(),
+            )?;
+            let page: ToolsPage = serde_json::from_value(result)
+                .map_err(|e| anyhow!("{} sent an invalid tool list: {}", self.name, e))?;
+            tools.extend(page.tools.into_iter().map(|tool| Tool {
+                name: tool.name,
+                description: tool.description.unwrap_or_default(),
+                input_schema: tool.input_schema,
+            }));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return Ok(tools),
+            }
+        }
+        tracing::warn!(
+            "MCP server {} listed more than {} pages of tools; using the first {}",
+            self.name,
+            MAX_TOOL_PAGES,
+            tools.len()
+        );
+        Ok(tools)
+    }
+
+    /// Run a tool, returning its text content
+    pub fn call_tool(
+        &self,
+        name: &str,
+        arguments: &Value,
+        timeout: Duration,
+        cancel: &CancelToken,
+    ) -> Result<String> {
+        let result = self.request(
+            "tools/call",
+            json!({ "name": name, "arguments": arguments }),
+            timeout,
+            cancel,
+        )?;
+        let result: CallToolResult = serde_json::from_value(result)
+            .map_err(|e| anyhow!("{} sent an invalid tool result: {}", self.name, e))?;
+
+        let text = result
+            .content
+            .iter()
+            .map(|block| match block["type"].as_str() {
+                Some("text") => block["text"].as_str().unwrap_or_default().to_string(),
+                Some(kind) => format!("[{} content]", kind),
+                None => block.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if result.is_error {
+            Err(anyhow!(text))
+        } else {
+            Ok(text)
+        }
+    }
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        let mut child = self.child.lock().unwrap();
+        kill_tree(&mut child);
+        let _ = child.wait();
+    }
+}
+
+/// Route a line from the server: results go to the waiting request, requests
+/// from the server get an answer, and anything else is logged
+fn handle_message(name: &str, line: &str, stdin: &Mutex<ChildStdin>, pending: &Pending) {
+    let Ok(message) = serde_json::from_str::<Value>(line) else {
+        tracing::debug!("MCP server {} wrote a non-JSON line: {}", name, line);
+        return;
+    };
+    let id = message.get("id").cloned().unwrap_or(Value::Null);
+
+    if let Some(method) = message["method"].as_str() {
+        // Notifications need no answer
+        if id.is_null() {
+            return;
+        }
+        let reply = if method == "ping" {
+            json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+        } else {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": format!("Method not found: {}", method) }
+            })
+        };
+        if let Err(e) = send(stdin, &reply) {
+            tracing::warn!("MCP server {}: {}", name, e);
+        }
+        return;
+    }
+
+    let Some(tx) = id
+        .as_u64()
+        .and_then(|id| pending.lock().unwrap().remove(&id))
+    else {
+        return;
+    };
+    let result = match message.get("error") {
+        Some(error) => Err(anyhow!(
+            "{}",
+            error["message"].as_str().unwrap_or("Unknown error")
+        )),
+        None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+    };
+    let _ = tx.send(result);
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolsPage {
+    tools: Vec<McpToolInfo>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct McpToolInfo {
+    name: String,
+    description: Option<String>,
+    #[serde(default = "empty_object_schema")]
+    input_schema: Value,
+}
+
+fn empty_object_schema() -> Value {
+    json!({ "type": "object" })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallToolResult {
+    #[serde(default)]
+    content: Vec<Value>,
+    #[serde(default)]
+    is_error: bool,
+}
+
+/// The name a server's tool is registered under, kept to the characters
+/// and length the model APIs accept
+fn tool_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_TOOL_NAME_LEN)
+        .collect()
+}
+
+/// A server's tool, registered with the agent
+struct McpTool {
+    client: Arc<McpClient>,
+    /// Name on the server
+    remote_name: String,
+    definition: Tool,
+}
+
+impl ToolHandler for McpTool {
+    fn definition(&self) -> Tool {
+        self.definition.clone()
+    }
+
+    fn call(&self, input: &Value, cancel: &CancelToken) -> Result<String> {
+        self.client
+            .call_tool(&self.remote_name, input, self.timeout(), cancel)
+    }
+}
+
+/// A configured server with the tools it offers
+pub struct McpServer {
+    pub status: McpServerStatus,
+    pub tools: Vec<Arc<dyn ToolHandler>>,
+    /// The connection, if the server started
+    client: Option<Arc<McpClient>>,
+}
+
+impl McpServer {
+    /// Launch a server and list its tools. Failures are kept in the status.
+    pub fn start(name: &str, config: &McpServerConfig, exit_tx: Sender<String>) -> Self {
+        let connected = McpClient::connect(name, config, exit_tx).and_then(|client| {
+            let tools = client.list_tools()?;
+            Ok((Arc::new(client), tools))
+        });
+        let (state, tools, client) = match connected {
+            Ok((client, tools)) => {
+                let tools: Vec<Arc<dyn ToolHandler>> = tools
+                    .into_iter()
+                    .map(|tool| {
+                        Arc::new(McpTool {
+                            client: client.clone(),
+                            definition: Tool {
+                                name: tool_name(name, &tool.name),
+                                ..tool.clone()
+                            },
+                            remote_name: tool.name,
+                        }) as Arc<dyn ToolHandler>
+                    })
+                    .collect();
+                (McpServerState::Running(tools.len()), tools, Some(client))
+            }
+            Err(e) => {
+                tracing::warn!("MCP server {} failed to start: {}", name, e);
+                (McpServerState::Failed(e.to_string()), Vec::new(), None)
+            }
+        };
+        let mut server = Self {
+            status: McpServerStatus {
+                name: name.to_string(),
+                state,
+                skipped_tools: Vec::new(),
+            },
+            tools,
+            client,
+        };
+        server.skip_taken_names(&mut HashSet::new());
+        server
+    }
+
+    /// Leave out tools whose name is in `taken` or repeated within the server,
+    /// since registering them would replace the earlier tool. The names kept
+    /// are added to `taken`.
+    fn skip_taken_names(&mut self, taken: &mut HashSet<String>) {
+        let skipped = &mut self.status.skipped_tools;
+        self.tools.retain(|tool| {
+            let name = tool.definition().name;
+            if taken.insert(name.clone()) {
+                return true;
+            }
+            tracing::warn!("MCP tool {} is offered twice; keeping the first", name);
+            skipped.push(name);
+            false
+        });
+        if let McpServerState::Running(count) = &mut self.status.state {
+            *count = self.tools.len();
+        }
+    }
+
+    /// Whether the server started and has since stopped
+    pub fn has_exited(&self) -> bool {
+        self.client
+            .as_ref()
+            .is_some_and(|client| client.has_exited())
+    }
+
+    /// Drop the tools of a server that stopped
+    pub fn mark_exited(&mut self) {
+        self.status.state = McpServerState::Exited;
+        self.tools.clear();
+        self.client = None;
+    }
+}
+
+/// Launch every configured server at once, waiting for all of them. Servers
+/// that stop later are reported on `exit_tx` by name. When two servers' tools
+/// end up with the same name, the server listed first keeps it.
+pub fn start_servers(config: &McpConfig, exit_tx: Sender<String>) -> Vec<McpServer> {
+    let mut servers: Vec<McpServer> = thread::scope(|scope| {
+        let handles: Vec<_> = config
+            .servers
+            .iter()
+            .map(|(name, server)| {
+                let exit_tx = exit_tx.clone();
+                scope.spawn(move || McpServer::start(name, server, exit_tx))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .collect()
+    });
+    let mut taken = HashSet::new();
+    for server in &mut servers {
+        server.skip_taken_names(&mut taken);
+    }
+    servers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Set when the test binary is launched as the fixture server
+    const FIXTURE_ENV: &str = "CHATAI_MCP_FIXTURE";
+
+    /// A tiny MCP server with an `echo` tool and a failing `fail` tool, which
+    /// stops the server when called with `exit`, plus two tools whose names
+    /// clash once made valid. It only runs when the test
+    /// binary launches itself with `FIXTURE_ENV` set; otherwise this test
+    /// does nothing.
+    #[test]
+    fn mcp_fixture_server() {
+        if std::env::var_os(FIXTURE_ENV).is_none() {
+            return;
+        }
+        let stdout = std::io::stdout();
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let result = match request["method"].as_str().unwrap_or_default() {
+                "initialize" => json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "fixture", "version": "1.0" }
+                }),
+                "tools/list" if request["params"]["cursor"].is_null() => json!({
+                    "tools": [{
+                        "name": "echo",
+                        "description": "Echo the text",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": { "text": { "type": "string" } }
+                        }
+                    }],
+                    "nextCursor": "2"
+                }),
+                "tools/list" => json!({
+                    "tools": [{ "name": "fail" }, { "name": "fail." }, { "name": "fail?" }]
+                }),
+                "tools/call" => {
+                    let params = &request["params"];
+                    if params["arguments"]["exit"] == true {
+                        std::process::exit(0);
+                    }
+                    json!({
+                        "content": [{
+                            "type": "text",
+                            "text": params["arguments"]["text"].as_str().unwrap_or("failed")
+                        }],
+                        "isError": params["name"] == "fail"
+                    })
+                }
+                _ => continue,
+            };
+            let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+            let mut stdout = stdout.lock();
+            writeln!(stdout, "{}", response).unwrap();
+            stdout.flush().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mcp_fixture_tools() {
+        let config = McpServerConfig {
+            command: std::env::current_exe().unwrap().display().to_string(),
+            args: ["mcp_fixture_server", "--nocapture", "--quiet"]
+                .map(str::to_string)
+                .to_vec(),
+            env: HashMap::from([(FIXTURE_ENV.to_string(), "1".to_string())]),
+        };
+        let (exit_tx, exit_rx) = async_channel::unbounded();
+        let mut server = McpServer::start("my fixture", &config, exit_tx.clone());
+        assert_eq!(server.status.state, McpServerState::Running(3));
+        assert_eq!(server.status.skipped_tools, ["my_fixture__fail_"]);
+
+        let names: Vec<String> = server.tools.iter().map(|t| t.definition().name).collect();
+        assert_eq!(
+            names,
+            ["my_fixture__echo", "my_fixture__fail", "my_fixture__fail_"]
+        );
+        assert_eq!(server.tools[0].definition().description, "Echo the text");
+
+        let cancel = CancelToken::default();
+        let echo = server.tools[0]
+            .call(&json!({ "text": "hello" }), &cancel)
+            .unwrap();
+        assert_eq!(echo, "hello");
+        let error = server.tools[1].call(&json!({}), &cancel).unwrap_err();
+        assert_eq!(error.to_string(), "failed");
+
+        // A server that stops on its own is reported
+        assert!(!server.has_exited());
+        assert!(
+            server.tools[1]
+                .call(&json!({ "exit": true }), &cancel)
+                .is_err()
+        );
+        assert_eq!(smol::block_on(exit_rx.recv()).unwrap(), "my fixture");
+        assert!(server.has_exited());
+        server.mark_exited();
+        assert_eq!(server.status.state, McpServerState::Exited);
+        assert!(server.tools.is_empty());
+
+        let missing = McpServer::start(
+            "missing",
+            &McpServerConfig {
+                command: "chatai-no-such-mcp-server".to_string(),
+                args: Vec::new(),
+                env: HashMap::new(),
+            },
+            exit_tx.clone(),
+        );
+        assert!(matches!(missing.status.state, McpServerState::Failed(_)));
+
+        // Servers whose names clash once made valid can't both register tools
+        let config = McpConfig {
+            servers: BTreeMap::from([
+                ("my fixture".to_string(), config.clone()),
+                ("my.fixture".to_string(), config),
+            ]),
+        };
+        let servers = start_servers(&config, exit_tx);
+        assert_eq!(servers[0].tools.len(), 3);
+        assert!(servers[1].tools.is_empty());
+        assert_eq!(servers[1].status.state, McpServerState::Running(0));
+        assert_eq!(servers[1].status.skipped_tools.len(), 4);
+    }
+}
@@ -3,6 +3,20 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::path::PathBuf;
+
+use super::batches::{BatchInput, BatchJob, BatchResult, BatchRun};
+use super::command::CommandRules;
+use super::context::{ContextUsage, TokenCount};
+use super::directory::DirectorySelection;
+use super::files::FileMetadata;
+use super::local::LocalModel;
+use super::mcp::McpServerStatus;
+use super::model_list::ModelInfo;
+use super::permissions::ToolPermission;
+use super::provider::ProviderKind;
+use super::sampling::SamplingParams;
+use super::types::ToolChoice;
 
 // ============================================================================
 // Agent Communication Types
@@ -11,17 +25,73 @@ use serde_json::Value;
 /// Messages sent from UI to Agent
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AgentRequest {
-    /// Start a new chat with a user message and optional files
+    /// Start a new chat with a user message, optional files and packed directories
     Chat {
         content: String,
         files: Vec<std::path::PathBuf>,
+        directories: Vec<DirectorySelection>,
+        /// Tool choice for this message only; `None` keeps the conversation's
+        #[serde(default)]
+        tool_choice: Option<ToolChoice>,
+    },
+    /// Count the input tokens a drafted message would send, without sending it
+    CountTokens {
+        content: String,
+        files: Vec<std::path::PathBuf>,
+        directories: Vec<DirectorySelection>,
     },
     /// Provide results for tool calls
     ToolResults(Vec<ToolResultData>),
     /// Clear conversation history
     ClearHistory,
-    /// Change the LLM model
-    SetModel(String),
+    /// Enable extended thinking with a token budget, or disable it with `None`
+    SetThinking(Option<u32>),
+    /// Replace the system prompt for the rest of the conversation
+    SetSystemPrompt(String),
+    /// Limit the tools offered to the model, or offer all of them with `None`
+    SetTools(Option<Vec<String>>),
+    /// Require replies matching a JSON Schema, or allow free-form replies with `None`
+    SetOutputSchema(Option<Value>),
+    /// Set how the model may use tools, or use the provider's default with `None`
+    SetToolChoice(Option<ToolChoice>),
+    /// Change whether a tool runs without asking, and remember the choice
+    SetToolPermission {
+        name: String,
+        permission: ToolPermission,
+    },
+    /// Offer file tools for a workspace folder, or remove them with `None`.
+    /// Tools that edit files or run commands are only offered when allowed.
+    SetWorkspace {
+        root: Option<PathBuf>,
+        allow_writes: bool,
+        allow_commands: bool,
+    },
+    /// Change which commands `run_command` may run, and remember the lists
+    SetCommandRules(CommandRules),
+    /// Restart the MCP servers, reading their configuration again
+    ReloadMcpServers,
+    /// Override temperature, top-p/top-k and stop sequences
+    SetSampling(SamplingParams),
+    /// Change the LLM model and the provider serving it
+    SetModel {
+        model: String,
+        provider: ProviderKind,
+    },
+    /// List files uploaded to the Files API
+    ListUploads,
+    /// Delete uploaded files by ID
+    DeleteUploads(Vec<String>),
+    /// Run a prompt template over each input with the Message Batches API
+    SubmitBatch {
+        template: String,
+        inputs: Vec<BatchInput>,
+    },
+    /// Check on a batch, fetching its results once it has ended
+    PollBatch(String),
+    /// Fetch the hosted model list from the Models API
+    RefreshModels,
+    /// Look for models served on this machine
+    DiscoverLocalModels,
 }
 
 /// Messages sent from Agent to UI
@@ -32,14 +102,59 @@ pub enum AgentResponse {
         text: Option<String>,
         tool_calls: Vec<ToolCallData>,
         stop_reason: Option<String>,
+        thought: Option<ThoughtData>,
     },
+    /// Reply text as it streams in, before the `TextResponse` or
+    /// `ToolCallRequest` carrying the whole reply
+    TextDelta(String),
     /// Agent has a text response ready
     TextResponse {
         text: String,
         stop_reason: Option<String>,
+        thought: Option<ThoughtData>,
     },
+    /// A reply matching the output schema
+    StructuredResponse { value: Value },
+    /// Results of the tool calls in the last `ToolCallRequest`, in call order
+    ToolResults(Vec<ToolResultData>),
+    /// A tool call is waiting for the user's approval, by tool use ID
+    ApprovalRequested(String),
+    /// The registered tools and their permissions
+    Tools(Vec<ToolInfo>),
+    /// The workspace folder in use, and why a newly chosen one was refused
+    Workspace {
+        root: Option<PathBuf>,
+        error: Option<String>,
+    },
+    /// Whether each configured MCP server is running
+    McpServers(Vec<McpServerStatus>),
     /// Agent encountered an error
     Error(String),
+    /// Files currently stored with the Files API
+    Uploads(Vec<UploadInfo>),
+    /// The turn was not sent because some attachments couldn't be prepared
+    TurnAborted { failures: Vec<AttachmentFailure> },
+    /// A batch was created
+    BatchSubmitted(BatchRun),
+    /// A batch's latest status, with its results once it has ended
+    BatchUpdated {
+        job: BatchJob,
+        results: Vec<BatchResult>,
+    },
+    /// Checking on a batch failed; it's checked again later
+    BatchPollFailed { batch_id: String, error: String },
+    /// Hosted models, freshly fetched from the Models API
+    Models(Vec<ModelInfo>),
+    /// Models found on local servers
+    LocalModels(Vec<LocalModel>),
+    /// How full the context window is after the last turn
+    ContextUsage(ContextUsage),
+    /// Input tokens of the drafted message
+    TokenCount(TokenCount),
+    /// Older messages were summarized to stay within the context window
+    Compacted { messages: usize },
+    /// A `ClearHistory` request was handled; later replies belong to the new chat
+    HistoryCleared,
 }
 
 impl AgentResponse {
@@ -61,11 +176,66 @@ impl AgentResponse {
                     Some("end_turn") | Some("max_tokens") | None
                 )
             }
+            AgentResponse::TextDelta(_) => false,
+            AgentResponse::StructuredResponse { .. } => true,
+            AgentResponse::ToolResults(_) => false,
+            AgentResponse::ApprovalRequested(_) => false,
+            AgentResponse::Tools(_) => false,
+            AgentResponse::Workspace { .. } => false,
+            AgentResponse::McpServers(_) => false,
             AgentResponse::Error(_) => true, // Errors always end the processing
+            AgentResponse::Uploads(_) => true,
+            AgentResponse::TurnAborted { .. } => true,
+            AgentResponse::BatchSubmitted(_) => true,
+            AgentResponse::BatchUpdated { .. } => true,
+            AgentResponse::BatchPollFailed { .. } => true,
+            AgentResponse::Models(_) => true,
+            AgentResponse::LocalModels(_) => true,
+            AgentResponse::ContextUsage(_) => false,
+            AgentResponse::Compacted { .. } => false,
+            AgentResponse::TokenCount(_) => false,
+            AgentResponse::HistoryCleared => false,
+        }
+    }
+
+    /// Whether this is part of a turn's reply, and so belongs to the chat the
+    /// turn was sent in
+    pub fn is_turn_output(&self) -> bool {
+        match self {
+            AgentResponse::ToolCallRequest { .. }
+            | AgentResponse::TextDelta(_)
+            | AgentResponse::TextResponse { .. }
+            | AgentResponse::StructuredResponse { .. }
+            | AgentResponse::ToolResults(_)
+            | AgentResponse::TurnAborted { .. }
+            | AgentResponse::ContextUsage(_)
+            | AgentResponse::Compacted { .. } => true,
+            AgentResponse::ApprovalRequested(_)
+            | AgentResponse::Tools(_)
+            | AgentResponse::Workspace { .. }
+            | AgentResponse::McpServers(_)
+            | AgentResponse::Error(_)
+            | AgentResponse::Uploads(_)
+            | AgentResponse::BatchSubmitted(_)
+            | AgentResponse::BatchUpdated { .. }
+            | AgentResponse::BatchPollFailed { .. }
+            | AgentResponse::Models(_)
+            | AgentResponse::LocalModels(_)
+            | AgentResponse::TokenCount(_)
+            | AgentResponse::HistoryCleared => false,
         }
     }
 }
 
+/// Extended thinking that preceded a response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThoughtData {
+    pub text: String,
+    /// Some of the reasoning was encrypted and can't be shown
+    pub redacted: bool,
+    pub duration_secs: u64,
+}
+
 /// Data for a tool call that needs to be executed
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCallData {
@@ -82,6 +252,53 @@ pub struct ToolResultData {
     pub is_error: bool,
 }
 
+/// The user's answer to a tool call awaiting approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ToolDecision {
+    /// Run the call, with input edited by the user if given
+    Approve { input: Option<Value> },
+    /// Don't run the call; the reason is passed on to the model
+    Deny { reason: String },
+}
+
+/// A decision about one tool call, sent on the approval channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolApproval {
+    pub tool_use_id: String,
+    pub decision: ToolDecision,
+}
+
+/// A registered tool as listed in settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolInfo {
+    pub name: String,
+    pub description: String,
+    pub permission: ToolPermission,
+}
+
+/// An attachment that failed to upload or be read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentFailure {
+    pub path: std::path::PathBuf,
+    pub error: String,
+}
+
+/// An uploaded file along with the conversations that sent it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadInfo {
+    pub file: FileMetadata,
+    pub conversations: Vec<String>,
+    /// Uploaded by this app, rather than another tool or user of the organization
+    pub owned: bool,
+}
+
+impl UploadInfo {
+    /// Whether this app uploaded the file and no known conversation references it
+    pub fn is_orphan(&self) -> bool {
+        self.owned && self.conversations.is_empty()
+    }
+}
+
 // ============================================================================
 // UI Message Types
 // ============================================================================
@@ -103,6 +320,12 @@ pub struct UiMessage {
     pub content: String,
     pub timestamp: DateTime<Utc>,
     pub metadata: Option<MessageMetadata>,
+    /// Reasoning shown collapsed above an assistant reply
+    #[serde(default)]
+    pub thought: Option<ThoughtData>,
+    /// A structured reply, shown as a JSON tree
+    #[serde(default)]
+    pub json: Option<Value>,
 }
 
 /// Additional metadata for messages
@@ -111,6 +334,9 @@ pub struct MessageMetadata {
     pub tool_name: Option<String>,
     pub is_error: bool,
     pub tool_input: Option<Value>,
+    /// Links a tool call to its result
+    #[serde(default)]
+    pub tool_use_id: Option<String>,
 }
 
 impl UiMessage {
@@ -121,6 +347,8 @@ impl UiMessage {
             content: content.into(),
             timestamp: Utc::now(),
             metadata: None,
+            thought: None,
+            json: None,
         }
     }
 
@@ -131,21 +359,72 @@ impl UiMessage {
             content: content.into(),
             timestamp: Utc::now(),
             metadata: None,
+            thought: None,
+            json: None,
+        }
+    }
+
+    /// Create a notice from the app, such as a compaction note
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: MessageRole::System,
+            content: content.into(),
+            timestamp: Utc::now(),
+            metadata: None,
+            thought: None,
+            json: None,
         }
     }
 
+    /// Create an assistant message holding a structured reply
+    pub fn structured(value: Value) -> Self {
+        Self {
+            role: MessageRole::Assistant,
+            content: serde_json::to_string_pretty(&value).unwrap_or_default(),
+            timestamp: Utc::now(),
+            metadata: None,
+            thought: None,
+            json: Some(value),
+        }
+    }
+
+    /// Attach the reasoning behind an assistant reply
+    pub fn with_thought(mut self, thought: Option<ThoughtData>) -> Self {
+        self.thought = thought;
+        self
+    }
+
     /// Create a new tool call message
-    #[allow(dead_code)]
-    pub fn tool_call(tool_name: String, tool_input: Value) -> Self {
+    pub fn tool_call(call: ToolCallData) -> Self {
         Self {
             role: MessageRole::ToolCall,
-            content: format!("Calling {}", tool_name),
+            content: format!("Calling {}", call.name),
             timestamp: Utc::now(),
             metadata: Some(MessageMetadata {
-                tool_name: Some(tool_name),
+                tool_name: Some(call.name),
                 is_error: false,
-                tool_input: Some(tool_input),
+                tool_input: Some(call.input),
+                tool_use_id: Some(call.id),
+            }),
+            thought: None,
+            json: None,
+        }
+    }
+
+    /// Create a message showing what a tool call returned
+    pub fn tool_result(tool_name: Option<String>, result: ToolResultData) -> Self {
+        Self {
+            role: MessageRole::ToolResult,
+            content: result.content,
+            timestamp: Utc::now(),
+            metadata: Some(MessageMetadata {
+                tool_name,
+                is_error: result.is_error,
+                tool_input: None,
+                tool_use_id: Some(result.tool_use_id),
             }),
+            thought: None,
+            json: None,
         }
     }
 
@@ -159,7 +438,10 @@ impl UiMessage {
                 tool_name: None,
                 is_error: true,
                 tool_input: None,
+                tool_use_id: None,
             }),
+            thought: None,
+            json: None,
         }
     }
 }
@@ -1,30 +1,140 @@
 //! Agent module for LLM-powered assistant functionality.
 //!
 //! This module provides:
-//! - `client` - The Agent client for communicating with Anthropic's API
+//! - `batches` - Message Batches API submission, polling and result export
+//! - `capabilities` - Per-model context window, output limit and input support
+//! - `client` - The Agent client, driving conversations through a provider
+//! - `command` - The `run_command` tool and its allow/deny lists
+//! - `context` - Context window usage estimates and conversation compaction
+//! - `directory` - Packing a folder of text files into a user turn
+//! - `files` - Files API management, a content-hash cache of uploaded file IDs
+//!   and the conversations that reference each upload
+//! - `mcp` - Model Context Protocol client, importing tools from stdio servers
 //! - `messages` - Request/response types and UI message types
+//! - `model_list` - Models API listing with a disk cache and static fallback
+//! - `permissions` - Per-tool allow/ask/deny policy for running tool calls
+//! - `profiles` - Named profiles bundling a system prompt, model and settings
+//! - `provider` - The `LlmProvider` trait with Anthropic, OpenAI-compatible and
+//!   local (Ollama, llama.cpp) backends
+//! - `sampling` - Temperature, top-p/top-k and stop sequence overrides
+//! - `structured` - JSON Schema replies through a forced tool call, and saved schemas
+//! - `tools` - The tool registry, running the model's tool calls concurrently
 //! - `types` - Core types like Tool, Message, ContentBlock
+//! - `workspace` - File tools confined to a workspace folder the user picks
 
+mod anthropic;
+mod batches;
+mod capabilities;
 mod client;
+mod command;
+mod context;
+mod directory;
+mod file_cache;
+mod file_refs;
 mod files;
+mod http;
+mod local;
+mod mcp;
 mod messages;
+mod model_list;
+mod openai;
+mod permissions;
+mod profiles;
+mod provider;
+mod sampling;
+mod structured;
+mod tools;
 mod types;
+mod workspace;
 
 // Re-export main client types
 #[allow(unused_imports)]
 pub use client::{Agent, AgentBuilder};
 
+// Re-export message batches
+#[allow(unused_imports)]
+pub use batches::{
+    BatchInput, BatchJob, BatchResult, BatchResultStatus, BatchRow, BatchRun, INPUT_PLACEHOLDER,
+    batch_results, create_batch, get_batch, render_template,
+};
+
+// Re-export model capabilities
+#[allow(unused_imports)]
+pub use capabilities::{MIN_THINKING_BUDGET, ModelCapabilities, capabilities};
+
+// Re-export profiles
+#[allow(unused_imports)]
+pub use profiles::{Profile, load_profiles};
+
+// Re-export sampling parameters
+#[allow(unused_imports)]
+pub use sampling::{MIN_THINKING_TOP_P, SamplingParams};
+
+// Re-export structured output
+#[allow(unused_imports)]
+pub use structured::{SavedSchema, load_schemas, parse_schema, validate};
+
+// Re-export context tracking
+#[allow(unused_imports)]
+pub use context::{ContextUsage, TokenCount};
+
+// Re-export tool registry
+#[allow(unused_imports)]
+pub use command::{CommandRules, RUN_COMMAND_TOOL_NAME, RunCommand};
+#[allow(unused_imports)]
+pub use mcp::{
+    McpClient, McpConfig, McpServer, McpServerConfig, McpServerState, McpServerStatus,
+    start_servers,
+};
+#[allow(unused_imports)]
+pub use permissions::{ToolPermission, ToolPolicy};
+#[allow(unused_imports)]
+pub use tools::{CancelToken, DEFAULT_TOOL_TIMEOUT, ToolHandler, ToolRegistry};
+#[allow(unused_imports)]
+pub use workspace::{MAX_TOOL_OUTPUT_BYTES, Workspace, workspace_tools};
+
+// Re-export directory packing
+#[allow(unused_imports)]
+pub use directory::{
+    DirectoryScan, DirectorySelection, SkipReason, estimate_tokens, scan_directory,
+};
+
 // Re-export files API
 #[allow(unused_imports)]
-pub use files::upload_file;
+pub use file_cache::{CachedFile, FileCache, upload_file_cached};
+#[allow(unused_imports)]
+pub use file_refs::{CHAT_ID_PREFIX, ConversationRef, FileReferences};
+#[allow(unused_imports)]
+pub use files::{
+    FileMetadata, delete_file, get_file_metadata, is_image_file, list_files, upload_file,
+};
 
 // Re-export message types
 #[allow(unused_imports)]
 pub use messages::{
-    AgentRequest, AgentResponse, MessageMetadata, MessageRole, ToolCallData, ToolResultData,
-    UiMessage,
+    AgentRequest, AgentResponse, AttachmentFailure, MessageMetadata, MessageRole, ThoughtData,
+    ToolApproval, ToolCallData, ToolDecision, ToolInfo, ToolResultData, UiMessage, UploadInfo,
 };
 
+// Re-export model list
+#[allow(unused_imports)]
+pub use model_list::{
+    DEFAULT_MODEL, ModelInfo, fallback_models, fetch_models, is_deprecated, load_models,
+    refresh_models,
+};
+
+// Re-export providers
+#[allow(unused_imports)]
+pub use anthropic::AnthropicProvider;
+#[allow(unused_imports)]
+pub use local::{LocalModel, OllamaProvider, discover_local_models, ollama_context_length};
+#[allow(unused_imports)]
+pub use openai::OpenAiProvider;
+#[allow(unused_imports)]
+pub use provider::{ChatRequest, ChatResponse, LlmProvider, ProviderKind, Usage, inline_file};
+
 // Re-export core types
 #[allow(unused_imports)]
-pub use types::{ContentBlock, FileSource, Message, Tool, ToolDefinition};
+pub use types::{
+    CacheControl, ContentBlock, FileSource, Message, Tool, ToolChoice, ToolDefinition,
+};
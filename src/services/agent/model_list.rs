@@ -0,0 +1,218 @@
+//! Available models, fetched from the Models API and cached on disk.
+//!
+//! The cache is shown immediately at startup and refreshed in the background.
+//! When neither is available the static list below is used.
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::provider::ProviderKind;
+use crate::services::paths::app_dir;
+
+/// Model used when nothing else has been chosen
+pub const DEFAULT_MODEL: &str = "claude-haiku-4-5-20251001";
+
+const MODELS_URL: &str = "https://api.anthropic.com/v1/models";
+
+/// Page size used when listing models
+const LIST_PAGE_SIZE: u32 = 100;
+
+/// Models listed when the Models API has never been reached
+const FALLBACK_MODELS: &[(&str, &str, ProviderKind)] = &[
+    (DEFAULT_MODEL, "Claude Haiku 4.5", ProviderKind::Anthropic),
+    (
+        "claude-sonnet-4-5-20250929",
+        "Claude Sonnet 4.5",
+        ProviderKind::Anthropic,
+    ),
+    (
+        "claude-opus-4-5-20251101",
+        "Claude Opus 4.5",
+        ProviderKind::Anthropic,
+    ),
+    (
+        "claude-opus-4-1-20250805",
+        "Claude Opus 4.1",
+        ProviderKind::Anthropic,
+    ),
+    ("gpt-4.1", "GPT-4.1", ProviderKind::OpenAi),
+    ("gpt-4o", "GPT-4o", ProviderKind::OpenAi),
+    ("gpt-4o-mini", "GPT-4o mini", ProviderKind::OpenAi),
+];
+
+/// Models announced for retirement; the Models API doesn't flag these itself
+const DEPRECATED_MODELS: &[&str] = &[
+    "claude-3-7-sonnet-20250219",
+    "claude-3-5-sonnet-20241022",
+    "claude-3-5-sonnet-20240620",
+    "claude-3-5-haiku-20241022",
+    "claude-3-opus-20240229",
+    "claude-3-sonnet-20240229",
+    "claude-2.1",
+    "claude-2.0",
+];
+
+/// A model that can be chosen in the model select
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    pub display_name: String,
+    pub provider: ProviderKind,
+    #[serde(default)]
+    pub deprecated: bool,
+}
+
+impl ModelInfo {
+    fn new(id: &str, display_name: &str, provider: ProviderKind) -> Self {
+        Self {
+            id: id.to_string(),
+            display_name: display_name.to_string(),
+            provider,
+            deprecated: is_deprecated(id),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiModel {
+    id: String,
+    display_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelListResponse {
+    data: Vec<ApiModel>,
+    has_more: bool,
+    last_id: Option<String>,
+}
+
+/// Models fetched from the API, persisted as JSON
+#[derive(Debug, Serialize, Deserialize)]
+struct ModelCache {
+    fetched_at: DateTime<Utc>,
+    models: Vec<ModelInfo>,
+}
+
+fn cache_path() -> PathBuf {
+    app_dir().join("models.json")
+}
+
+pub fn is_deprecated(model_id: &str) -> bool {
+    DEPRECATED_MODELS.contains(&model_id)
+}
+
+/// Hosted models from other providers, which aren't listed by the Models API
+fn other_providers() -> impl Iterator<Item = ModelInfo> {
+    FALLBACK_MODELS
+        .iter()
+        .filter(|(_, _, provider)| *provider != ProviderKind::Anthropic)
+        .map(|(id, name, provider)| ModelInfo::new(id, name, *provider))
+}
+
+/// The static model list
+pub fn fallback_models() -> Vec<ModelInfo> {
+    FALLBACK_MODELS
+        .iter()
+        .map(|(id, name, provider)| ModelInfo::new(id, name, *provider))
+        .collect()
+}
+
+/// Cached models if the API has been reached before, otherwise the static list
+pub fn load_models() -> Vec<ModelInfo> {
+    std::fs::read_to_string(cache_path())
+        .ok()
+        .and_then(|json| serde_json::from_str::<ModelCache>(&json).ok())
+        .filter(|cache| !cache.models.is_empty())
+        .map(|cache| cache.models.into_iter().chain(other_providers()).collect())
+        .unwrap_or_else(fallback_models)
+}
+
+/// Fetch every Claude model from the Models API, newest first
+pub fn fetch_models(api_key: &str) -> Result<Vec<ModelInfo>> {
+    let mut models = Vec::new();
+    let mut after_id: Option<String> = None;
+
+    loop {
+        let mut url = format!("{}?limit={}", MODELS_URL, LIST_PAGE_SIZE);
+        if let Some(after) = &after_id {
+            url.push_str(&format!("&after_id={}", after));
+        }
+
+        let response = smolhttp::Client::new(&url)
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?
+            .get()
+            .headers(vec![
+                ("x-api-key".to_string(), api_key.to_string()),
+                ("anthropic-version".to_string(), "2023-06-01".to_string()),
+            ])
+            .send()
+            .map_err(|e| anyhow!("Model list request failed: {}", e))?;
+
+        let response_text = response.text();
+
+        if response_text.contains("\"error\"") {
+            return Err(anyhow!("Model list error: {}", response_text));
+        }
+
+        let page: ModelListResponse = serde_json::from_str(&response_text).map_err(|e| {
+            anyhow!(
+                "Failed to parse model list: {}. Response: {}",
+                e,
+                response_text
+            )
+        })?;
+
+        models.extend(
+            page.data
+                .iter()
+                .map(|m| ModelInfo::new(&m.id, &m.display_name, ProviderKind::Anthropic)),
+        );
+
+        match (page.has_more, page.last_id) {
+            (true, Some(last_id)) => after_id = Some(last_id),
+            _ => break,
+        }
+    }
+
+    Ok(models)
+}
+
+/// Write fetched models to the disk cache
+fn save_cache(models: &[ModelInfo]) -> Result<()> {
+    let cache = ModelCache {
+        fetched_at: Utc::now(),
+        models: models.to_vec(),
+    };
+    let path = cache_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| anyhow!("Failed to create cache directory: {}", e))?;
+    }
+    let json = serde_json::to_string_pretty(&cache)
+        .map_err(|e| anyhow!("Failed to serialize model cache: {}", e))?;
+    std::fs::write(&path, json).map_err(|e| anyhow!("Failed to write model cache: {}", e))
+}
+
+/// Fetch the model list, cache it, and return it along with other providers' models
+pub fn refresh_models(api_key: &str) -> Result<Vec<ModelInfo>> {
+    let models = fetch_models(api_key)?;
+    if let Err(e) = save_cache(&models) {
+        tracing::warn!("{}", e);
+    }
+    Ok(models.into_iter().chain(other_providers()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback_models() {
+        let models = fallback_models();
+        assert_eq!(models[0].id, DEFAULT_MODEL);
+        assert!(models.iter().all(|m| !m.deprecated));
+        assert!(is_deprecated("claude-3-opus-20240229"));
+    }
+}
@@ -0,0 +1,665 @@
+//! OpenAI Chat Completions provider.
+//!
+//! Also works with any server exposing the same wire format; set
+//! `OPENAI_BASE_URL` to point it somewhere other than api.openai.com.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::env;
+
+use super::http;
+use super::provider::{ChatRequest, ChatResponse, LlmProvider, ProviderKind, Usage};
+use super::types::{ContentBlock, FileSource, Message, ToolChoice, ToolDefinition};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Debug, Serialize)]
+struct CompletionRequest {
+    model: String,
+    max_tokens: u32,
+    messages: Vec<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionResponse {
+    model: String,
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<CompletionUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: CompletionMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<CompletionToolCall>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CompletionToolCall {
+    id: String,
+    function: CompletionFunction,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CompletionFunction {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    #[serde(default)]
+    prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+/// OpenAI caches long prompts automatically and reports the hits here
+#[derive(Debug, Deserialize)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: u32,
+}
+
+/// A piece of a streamed completion
+#[derive(Debug, Deserialize)]
+struct CompletionChunk {
+    #[serde(default)]
+    model: String,
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    /// Only in the last chunk, and only when asked for
+    #[serde(default)]
+    usage: Option<CompletionUsage>,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+/// Part of a tool call; the ID and name come first, then the arguments in pieces
+#[derive(Debug, Deserialize)]
+struct ToolCallDelta {
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/// A completion put together from its chunks
+#[derive(Debug, Default)]
+struct StreamedCompletion {
+    model: String,
+    content: String,
+    tool_calls: Vec<CompletionToolCall>,
+    finish_reason: Option<String>,
+    usage: Option<CompletionUsage>,
+}
+
+impl StreamedCompletion {
+    fn apply(&mut self, chunk: CompletionChunk, on_text: &mut dyn FnMut(&str)) -> Result<()> {
+        if let Some(error) = chunk.error {
+            return Err(anyhow!("API error: {}", error));
+        }
+        if !chunk.model.is_empty() {
+            self.model = chunk.model;
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        for choice in chunk.choices {
+            if let Some(text) = choice.delta.content.filter(|t| !t.is_empty()) {
+                on_text(&text);
+                self.content.push_str(&text);
+            }
+            for delta in choice.delta.tool_calls {
+                if self.tool_calls.len() <= delta.index {
+                    self.tool_calls
+                        .resize_with(delta.index + 1, CompletionToolCall::default);
+                }
+                let call = &mut self.tool_calls[delta.index];
+                if let Some(id) = delta.id {
+                    call.id = id;
+                }
+                if let Some(function) = delta.function {
+                    if let Some(name) = function.name {
+                        call.function.name.push_str(&name);
+                    }
+                    if let Some(arguments) = function.arguments {
+                        call.function.arguments.push_str(&arguments);
+                    }
+                }
+            }
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> CompletionResponse {
+        CompletionResponse {
+            model: self.model,
+            choices: vec![Choice {
+                message: CompletionMessage {
+                    content: Some(self.content),
+                    tool_calls: self.tool_calls,
+                },
+                finish_reason: self.finish_reason,
+            }],
+            usage: self.usage,
+        }
+    }
+}
+
+/// Provider for the OpenAI Chat Completions API and compatible servers
+pub struct OpenAiProvider {
+    kind: ProviderKind,
+    api_key: String,
+    base_url: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(api_key: String, base_url: String) -> Self {
+        Self {
+            kind: ProviderKind::OpenAi,
+            api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// A compatible server that isn't OpenAI itself, such as llama.cpp
+    pub fn compatible(kind: ProviderKind, base_url: String) -> Self {
+        Self {
+            kind,
+            ..Self::new(String::new(), base_url)
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let api_key = env::var("OPENAI_API_KEY")
+            .map_err(|_| anyhow!("OPENAI_API_KEY environment variable not set"))?;
+        let base_url = env::var("OPENAI_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
+        Ok(Self::new(api_key, base_url))
+    }
+}
+
+/// Build a `data:` URL for inline file contents
+fn data_url(media_type: &str, data: &str) -> String {
+    format!("data:{};base64,{}", media_type, data)
+}
+
+/// Convert a tool definition to a Chat Completions function tool
+fn tool_to_openai(tool: &ToolDefinition) -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": tool.name,
+            "description": tool.description,
+            "parameters": tool.input_schema,
+        }
+    })
+}
+
+/// Convert a tool choice to a Chat Completions `tool_choice`
+fn tool_choice_to_openai(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto { .. } => json!("auto"),
+        ToolChoice::Any { .. } => json!("required"),
+        ToolChoice::Tool { name, .. } => {
+            json!({ "type": "function", "function": { "name": name } })
+        }
+        ToolChoice::None => json!("none"),
+    }
+}
+
+/// Convert a user content block to a Chat Completions content part
+fn content_part(block: &ContentBlock) -> Result<Option<Value>> {
+    Ok(match block {
+        ContentBlock::Text { text, .. } => Some(json!({ "type": "text", "text": text })),
+        ContentBlock::Image {
+            source: FileSource::Base64 { media_type, data },
+            ..
+        } => Some(json!({
+            "type": "image_url",
+            "image_url": { "url": data_url(media_type, data) },
+        })),
+        ContentBlock::Document {
+            source: FileSource::Base64 { media_type, data },
+            ..
+        } => Some(json!({
+            "type": "file",
+            "file": { "file_data": data_url(media_type, data) },
+        })),
+        ContentBlock::Image {
+            source: FileSource::File { file_id },
+            ..
+        }
+        | ContentBlock::Document {
+            source: FileSource::File { file_id },
+            ..
+        } => {
+            return Err(anyhow!(
+                "File {} was uploaded to Anthropic and can't be sent to this model",
+                file_id
+            ));
+        }
+        ContentBlock::ToolUse { .. }
+        | ContentBlock::ToolResult { .. }
+        | ContentBlock::Thinking { .. }
+        | ContentBlock::RedactedThinking { .. } => None,
+    })
+}
+
+/// Convert the conversation to Chat Completions messages.
+/// Tool results become separate `tool` messages and tool uses become `tool_calls`.
+fn messages_to_openai(system_prompt: &str, messages: &[Message]) -> Result<Vec<Value>> {
+    let mut out = Vec::new();
+    if !system_prompt.is_empty() {
+        out.push(json!({ "role": "system", "content": system_prompt }));
+    }
+
+    for message in messages {
+        match message {
+            Message::User { content, .. } => {
+                for block in content {
+                    if let ContentBlock::ToolResult {
+                        tool_use_id,
+                        content,
+                        ..
+                    } = block
+                    {
+                        out.push(json!({
+                            "role": "tool",
+                            "tool_call_id": tool_use_id,
+                            "content": content,
+                        }));
+                    }
+                }
+
+                let mut parts = Vec::new();
+                for block in content {
+                    parts.extend(content_part(block)?);
+                }
+                if !parts.is_empty() {
+                    out.push(json!({ "role": "user", "content": parts }));
+                }
+            }
+            Message::Assistant { content, .. } => {
+                let text: Vec<&str> = content
+                    .iter()
+                    .filter_map(|block| match block {
+                        ContentBlock::Text { text, .. } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                let tool_calls: Vec<Value> = content
+                    .iter()
+                    .filter_map(|block| match block {
+                        ContentBlock::ToolUse { id, name, input } => Some(json!({
+                            "id": id,
+                            "type": "function",
+                            "function": { "name": name, "arguments": input.to_string() },
+                        })),
+                        _ => None,
+                    })
+                    .collect();
+
+                let mut message = json!({ "role": "assistant", "content": text.join("\n") });
+                if !tool_calls.is_empty() {
+                    message["tool_calls"] = Value::Array(tool_calls);
+                }
+                out.push(message);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Map a Chat Completions finish reason onto Anthropic's stop reasons
+fn stop_reason(finish_reason: &str) -> String {
+    match finish_reason {
+        "stop" => "end_turn",
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        other => other,
+    }
+    .to_string()
+}
+
+/// Convert a Chat Completions response into an assistant turn
+fn response_from_openai(response: CompletionResponse) -> Result<ChatResponse> {
+    let choice = response
+        .choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No choices in response"))?;
+
+    let mut content = Vec::new();
+    if let Some(text) = choice.message.content.filter(|t| !t.is_empty()) {
+        content.push(ContentBlock::text(text));
+    }
+    for call in choice.message.tool_calls {
+        let input = serde_json::from_str(&call.function.arguments)
+            .unwrap_or(Value::String(call.function.arguments));
+        content.push(ContentBlock::ToolUse {
+            id: call.id,
+            name: call.function.name,
+            input,
+        });
+    }
+
+    Ok(ChatResponse {
+        model: response.model,
+        content,
+        stop_reason: choice.finish_reason.as_deref().map(stop_reason),
+        usage: response
+            .usage
+            .map(|u| {
+                let cached = u
+                    .prompt_tokens_details
+                    .map(|d| d.cached_tokens)
+                    .unwrap_or_default();
+                // Prompt tokens include the cached ones, which Anthropic counts separately
+                Usage {
+                    input_tokens: u.prompt_tokens.saturating_sub(cached),
+                    output_tokens: u.completion_tokens,
+                    cache_creation_input_tokens: 0,
+                    cache_read_input_tokens: cached,
+                }
+            })
+            .unwrap_or_default(),
+    })
+}
+
+impl OpenAiProvider {
+    /// Wire format of a chat request
+    fn completion_request(&self, request: &ChatRequest, stream: bool) -> Result<CompletionRequest> {
+        Ok(CompletionRequest {
+            model: request.model.clone(),
+            max_tokens: request.max_tokens,
+            messages: messages_to_openai(&request.system_prompt, &request.messages)?,
+            tools: request.tools.iter().map(tool_to_openai).collect(),
+            // Both are rejected without tools
+            tool_choice: request
+                .tool_choice
+                .as_ref()
+                .filter(|_| !request.tools.is_empty())
+                .map(tool_choice_to_openai),
+            parallel_tool_calls: request
+                .tool_choice
+                .as_ref()
+                .filter(|choice| !request.tools.is_empty() && choice.disables_parallel_tool_use())
+                .map(|_| false),
+            // OpenAI has no top_k
+            temperature: request.sampling.temperature,
+            top_p: request.sampling.top_p,
+            stop: request.sampling.stop_sequences.clone(),
+            stream,
+            // Usage is only reported for streams when asked for
+            stream_options: stream.then(|| json!({ "include_usage": true })),
+        })
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![("content-type".to_string(), "application/json".to_string())];
+        // Local servers usually don't need a key
+        if !self.api_key.is_empty() {
+            headers.push((
+                "authorization".to_string(),
+                format!("Bearer {}", self.api_key),
+            ));
+        }
+        headers
+    }
+}
+
+impl LlmProvider for OpenAiProvider {
+    fn kind(&self) -> ProviderKind {
+        self.kind
+    }
+
+    fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let request = self.completion_request(request, false)?;
+        let body = serde_json::to_string(&request)
+            .map_err(|e| anyhow!("Failed to serialize request: {}", e))?;
+
+        let response = http::send(
+            "POST",
+            &format!("{}/chat/completions", self.base_url),
+            self.headers(),
+            Some(body.into()),
+        )
+        .map_err(|e| anyhow!("API request failed: {}", e))?;
+
+        let status = response.status_code();
+        let response_text = response.text();
+
+        if status >= 400 {
+            return Err(anyhow!("API error ({}): {}", status, response_text));
+        }
+
+        let completion: CompletionResponse = serde_json::from_str(&response_text).map_err(|e| {
+            anyhow!(
+                "Failed to parse response: {}. Response: {}",
+                e,
+                response_text
+            )
+        })?;
+
+        response_from_openai(completion)
+    }
+
+    fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<ChatResponse> {
+        let request = self.completion_request(request, true)?;
+        let body = serde_json::to_vec(&request)
+            .map_err(|e| anyhow!("Failed to serialize request: {}", e))?;
+
+        let mut response = http::send_streaming(
+            "POST",
+            &format!("{}/chat/completions", self.base_url),
+            self.headers(),
+            body,
+        )
+        .map_err(|e| anyhow!("API request failed: {}", e))?;
+        let status = response.status_code();
+        if status >= 400 {
+            return Err(anyhow!("API error ({}): {}", status, response.text()));
+        }
+
+        let mut completion = StreamedCompletion::default();
+        while let Some(data) = response.next_event()? {
+            if data == "[DONE]" {
+                break;
+            }
+            let chunk: CompletionChunk = serde_json::from_str(&data)
+                .map_err(|e| anyhow!("Failed to parse stream chunk: {}. Chunk: {}", e, data))?;
+            completion.apply(chunk, on_text)?;
+        }
+        if completion.finish_reason.is_none() {
+            return Err(anyhow!("The reply stream ended early"));
+        }
+        response_from_openai(completion.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_to_openai() {
+        let messages = vec![
+            Message::User {
+                role: "user".to_string(),
+                content: vec![ContentBlock::text("Weather?")],
+            },
+            Message::Assistant {
+                role: "assistant".to_string(),
+                content: vec![ContentBlock::ToolUse {
+                    id: "call_1".to_string(),
+                    name: "weather".to_string(),
+                    input: json!({ "city": "Oslo" }),
+                }],
+            },
+            Message::User {
+                role: "user".to_string(),
+                content: vec![ContentBlock::ToolResult {
+                    tool_use_id: "call_1".to_string(),
+                    content: "Sunny".to_string(),
+                    is_error: None,
+                    cache_control: None,
+                }],
+            },
+        ];
+
+        let out = messages_to_openai("Be brief", &messages).unwrap();
+        assert_eq!(out.len(), 4);
+        assert_eq!(out[0]["role"], "system");
+        assert_eq!(out[1]["content"][0]["text"], "Weather?");
+        assert_eq!(out[2]["tool_calls"][0]["function"]["name"], "weather");
+        assert_eq!(
+            out[2]["tool_calls"][0]["function"]["arguments"],
+            "{\"city\":\"Oslo\"}"
+        );
+        assert_eq!(out[3]["role"], "tool");
+        assert_eq!(out[3]["tool_call_id"], "call_1");
+    }
+
+    #[test]
+    fn test_files_api_reference_is_rejected() {
+        let messages = vec![Message::User {
+            role: "user".to_string(),
+            content: vec![ContentBlock::Document {
+                source: FileSource::File {
+                    file_id: "file_1".to_string(),
+                },
+                cache_control: None,
+            }],
+        }];
+        assert!(messages_to_openai("", &messages).is_err());
+    }
+
+    #[test]
+    fn test_streamed_completion() {
+        let chunk = |choice: Value| json!({ "model": "gpt-4o", "choices": [choice] });
+        let chunks = [
+            chunk(json!({ "delta": { "role": "assistant", "content": "" } })),
+            chunk(json!({ "delta": { "content": "Checking" } })),
+            chunk(json!({ "delta": { "tool_calls": [{
+                "index": 0, "id": "call_1", "type": "function",
+                "function": { "name": "weather", "arguments": "" }
+            }] } })),
+            chunk(json!({ "delta": { "tool_calls": [{
+                "index": 0, "function": { "arguments": "{\"city\":" }
+            }] } })),
+            chunk(json!({ "delta": { "tool_calls": [{
+                "index": 0, "function": { "arguments": "\"Oslo\"}" }
+            }] } })),
+            chunk(json!({ "delta": {}, "finish_reason": "tool_calls" })),
+            json!({ "model": "gpt-4o", "choices": [], "usage": {
+                "prompt_tokens": 20, "completion_tokens": 8
+            } }),
+        ];
+        let mut streamed = Vec::new();
+        let mut completion = StreamedCompletion::default();
+        for chunk in chunks {
+            let chunk = serde_json::from_value(chunk).unwrap();
+            completion
+                .apply(chunk, &mut |text| streamed.push(text.to_string()))
+                .unwrap();
+        }
+        let response = response_from_openai(completion.finish()).unwrap();
+
+        assert_eq!(streamed, ["Checking"]);
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(response.usage.input_tokens, 20);
+        assert!(matches!(
+            &response.content[1],
+            ContentBlock::ToolUse { id, input, .. }
+                if id == "call_1" && input == &json!({ "city": "Oslo" })
+        ));
+    }
+
+    #[test]
+    fn test_response_from_openai() {
+        let completion: CompletionResponse = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "choices": [{
+                "message": {
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "weather", "arguments": "{\"city\":\"Oslo\"}" }
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {
+                "prompt_tokens": 10,
+                "completion_tokens": 5,
+                "prompt_tokens_details": { "cached_tokens": 6 }
+            }
+        }))
+        .unwrap();
+
+        let response = response_from_openai(completion).unwrap();
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(response.usage.input_tokens, 4);
+        assert_eq!(response.usage.cache_read_input_tokens, 6);
+        match &response.content[0] {
+            ContentBlock::ToolUse { name, input, .. } => {
+                assert_eq!(name, "weather");
+                assert_eq!(input["city"], "Oslo");
+            }
+            other => panic!("unexpected block {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Per-tool permissions deciding which calls run without asking the user.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::services::paths::app_dir;
+
+/// What happens when the model calls a tool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolPermission {
+    /// Run without asking
+    Allow,
+    /// Wait for the user to approve, edit or deny the call
+    #[default]
+    Ask,
+    /// Refuse every call
+    Deny,
+}
+
+impl ToolPermission {
+    pub const ALL: [ToolPermission; 3] = [
+        ToolPermission::Allow,
+        ToolPermission::Ask,
+        ToolPermission::Deny,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ToolPermission::Allow => "Allow",
+            ToolPermission::Ask => "Ask",
+            ToolPermission::Deny => "Deny",
+        }
+    }
+}
+
+/// Tool name → permission chosen by the user, persisted as JSON.
+/// Tools without a choice use their own default.
+#[derive(Debug, Default)]
+pub struct ToolPolicy {
+    path: PathBuf,
+    permissions: HashMap<String, ToolPermission>,
+}
+
+#[allow(dead_code)]
+impl ToolPolicy {
+    /// Load the policy from the default location, starting empty if missing
+    pub fn load() -> Self {
+        Self::load_from(app_dir().join("tool_permissions.json"))
+    }
+
+    /// Load the policy from a specific file
+    pub fn load_from(path: PathBuf) -> Self {
+        let permissions = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        Self { path, permissions }
+    }
+
+    /// Write the policy back to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create settings directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.permissions)
+            .map_err(|e| anyhow!("Failed to serialize tool permissions: {}", e))?;
+        std::fs::write(&self.path, json)
+            .map_err(|e| anyhow!("Failed to write tool permissions: {}", e))
+    }
+
+    /// The user's choice for a tool, or `default` if they haven't made one
+    pub fn permission(&self, tool_name: &str, default: ToolPermission) -> ToolPermission {
+        self.permissions.get(tool_name).copied().unwrap_or(default)
+    }
+
+    pub fn set(&mut self, tool_name: &str, permission: ToolPermission) {
+        self.permissions.insert(tool_name.to_string(), permission);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "chatai-tool-permissions-{}.json",
+            std::process::id()
+        ));
+        let mut policy = ToolPolicy::load_from(path.clone());
+        assert_eq!(
+            policy.permission("read_file", ToolPermission::Allow),
+            ToolPermission::Allow
+        );
+
+        policy.set("read_file", ToolPermission::Deny);
+        policy.save().unwrap();
+
+        let policy = ToolPolicy::load_from(path.clone());
+        assert_eq!(
+            policy.permission("read_file", ToolPermission::Allow),
+            ToolPermission::Deny
+        );
+        assert_eq!(
+            policy.permission("write_file", ToolPermission::Ask),
+            ToolPermission::Ask
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,203 @@
+//! Named assistant profiles bundling a prompt, model and settings.
+//!
+//! Each profile is a JSON file in the profiles directory, so profiles can be
+//! shared by copying the file or importing it from the settings popover.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::sampling::SamplingParams;
+use crate::services::paths::app_dir;
+
+/// A reusable conversation setup
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub system_prompt: String,
+    /// Model ID to switch to; `None` keeps the current model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<u32>,
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    /// Names of the tools to offer; `None` offers every tool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<String>>,
+    /// Files attached when the profile is selected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<PathBuf>,
+}
+
+impl Profile {
+    /// Path for the profile in `dir`, derived from its name. A profile with the
+    /// same name keeps its file; names that differ only in case or punctuation
+    /// get a numbered file instead of replacing each other.
+    fn path_in(&self, dir: &Path) -> Result<PathBuf> {
+        let slug = slug(&self.name);
+        if slug.is_empty() {
+            return Err(anyhow!("Profile name needs at least one letter or digit"));
+        }
+        (1..)
+            .map(|n| match n {
+                1 => dir.join(format!("{}.json", slug)),
+                n => dir.join(format!("{}-{}.json", slug, n)),
+            })
+            .find(|path| {
+                !path.exists() || Self::read(path).is_ok_and(|saved| saved.name == self.name)
+            })
+            .ok_or_else(|| anyhow!("No file name left for profile {}", self.name))
+    }
+
+    /// Write the profile to the profiles directory, replacing one with the same name
+    pub fn save(&self) -> Result<PathBuf> {
+        self.save_in(&profiles_dir())
+    }
+
+    fn save_in(&self, dir: &Path) -> Result<PathBuf> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Profile name can't be empty"));
+        }
+        // Profiles aren't tied to a provider; the model's limits apply when one is used
+        self.sampling
+            .validate(self.thinking_budget.is_some(), None)?;
+
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create profiles directory: {}", e))?;
+        let path = self.path_in(dir)?;
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize profile: {}", e))?;
+        std::fs::write(&path, json).map_err(|e| anyhow!("Failed to write profile: {}", e))?;
+        Ok(path)
+    }
+
+    /// Read a profile from a JSON file
+    pub fn read(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&json)
+            .map_err(|e| anyhow!("Invalid profile {}: {}", path.display(), e))
+    }
+
+    /// Remember the open conversation's settings for the next launch
+    pub fn save_current(&self) -> Result<()> {
+        let path = current_settings_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create settings directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize conversation settings: {}", e))?;
+        std::fs::write(path, json)
+            .map_err(|e| anyhow!("Failed to write conversation settings: {}", e))
+    }
+
+    /// The settings of the conversation open at the last exit
+    pub fn load_current() -> Option<Self> {
+        Self::read(&current_settings_path())
+            .inspect_err(|e| tracing::debug!("{}", e))
+            .ok()
+    }
+
+    /// Copy a shared profile file into the profiles directory. Attachments
+    /// are dropped, since a shared file could point at any file on this
+    /// machine; they are returned so the user can see what was left out.
+    pub fn import(path: &Path) -> Result<(Self, Vec<PathBuf>)> {
+        let mut profile = Self::read(path)?;
+        let dropped = std::mem::take(&mut profile.attachments);
+        profile.save()?;
+        Ok((profile, dropped))
+    }
+}
+
+/// A name made safe for use as a file name
+pub(super) fn slug(name: &str) -> String {
+    let slug: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    slug.trim_matches('-').to_string()
+}
+
+/// Where the open conversation's settings are kept, as an unnamed profile
+fn current_settings_path() -> PathBuf {
+    app_dir().join("conversation_settings.json")
+}
+
+fn profiles_dir() -> PathBuf {
+    app_dir().join("profiles")
+}
+
+/// All saved profiles, sorted by name. Unreadable files are skipped.
+pub fn load_profiles() -> Vec<Profile> {
+    load_profiles_from(&profiles_dir())
+}
+
+fn load_profiles_from(dir: &Path) -> Vec<Profile> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut profiles: Vec<Profile> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            Profile::read(&path)
+                .inspect_err(|e| tracing::warn!("{}", e))
+                .ok()
+        })
+        .collect();
+    profiles.sort_by_key(|p| p.name.to_lowercase());
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_profiles() {
+        let dir = std::env::temp_dir().join(format!("chatai-profiles-{}", std::process::id()));
+        let profile = Profile {
+            name: "SQL Helper".to_string(),
+            system_prompt: "You write SQL.".to_string(),
+            model: Some("claude-haiku-4-5-20251001".to_string()),
+            thinking_budget: None,
+            sampling: SamplingParams {
+                temperature: Some(0.2),
+                ..Default::default()
+            },
+            tools: Some(vec![]),
+            attachments: vec![],
+        };
+
+        let path = profile.save_in(&dir).unwrap();
+        assert_eq!(path.file_name().unwrap(), "sql-helper.json");
+        assert_eq!(profile.save_in(&dir).unwrap(), path);
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        // Same slug, different name
+        let similar = Profile {
+            name: "sql-helper".to_string(),
+            ..profile.clone()
+        };
+        let similar_path = similar.save_in(&dir).unwrap();
+        assert_eq!(similar_path.file_name().unwrap(), "sql-helper-2.json");
+
+        let unnamed = Profile {
+            name: "!!!".to_string(),
+            ..profile.clone()
+        };
+        assert!(unnamed.save_in(&dir).is_err());
+
+        assert_eq!(load_profiles_from(&dir), vec![profile, similar]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
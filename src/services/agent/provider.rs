@@ -0,0 +1,160 @@
+//! Provider abstraction over LLM chat APIs.
+//!
+//! The agent speaks in Anthropic-shaped `Message`/`ContentBlock` values and each
+//! provider translates them to and from its own wire format.
+
+use anyhow::{Result, anyhow};
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, sync::Arc};
+
+use super::anthropic::AnthropicProvider;
+use super::files::{get_mime_type, is_image_file};
+use super::local::{OllamaProvider, llama_cpp_provider};
+use super::openai::OpenAiProvider;
+use super::sampling::SamplingParams;
+use super::types::{ContentBlock, FileSource, Message, ToolChoice, ToolDefinition};
+
+/// Which backend serves a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProviderKind {
+    Anthropic,
+    OpenAi,
+    Ollama,
+    LlamaCpp,
+}
+
+impl ProviderKind {
+    pub fn icon_path(&self) -> &'static str {
+        match self {
+            ProviderKind::Anthropic => "icons/anthropic.svg",
+            ProviderKind::OpenAi => "icons/openai.svg",
+            ProviderKind::Ollama | ProviderKind::LlamaCpp => "icons/server.svg",
+        }
+    }
+
+    /// Create a provider using credentials from the environment
+    pub fn create(&self) -> Result<Arc<dyn LlmProvider>> {
+        Ok(match self {
+            ProviderKind::Anthropic => Arc::new(AnthropicProvider::from_env()?),
+            ProviderKind::OpenAi => Arc::new(OpenAiProvider::from_env()?),
+            ProviderKind::Ollama => Arc::new(OllamaProvider::from_env()),
+            ProviderKind::LlamaCpp => Arc::new(llama_cpp_provider()),
+        })
+    }
+}
+
+/// A single inference request, independent of the wire format
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub model: String,
+    pub system_prompt: String,
+    pub max_tokens: u32,
+    pub messages: Vec<Message>,
+    pub tools: Vec<ToolDefinition>,
+    /// Force a tool call; `None` lets the model decide
+    pub tool_choice: Option<ToolChoice>,
+    /// Extended thinking budget; ignored by providers without thinking
+    pub thinking_budget: Option<u32>,
+    pub sampling: SamplingParams,
+    /// The context window the conversation is sized for, sent to providers
+    /// that allocate it per request; `None` keeps the server's setting
+    pub context_window: Option<u32>,
+}
+
+/// Token usage reported for a request
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    /// Input tokens written to the prompt cache
+    #[serde(default)]
+    pub cache_creation_input_tokens: u32,
+    /// Input tokens read from the prompt cache
+    #[serde(default)]
+    pub cache_read_input_tokens: u32,
+}
+
+/// The assistant turn produced by a provider.
+/// `stop_reason` uses Anthropic's vocabulary (`end_turn`, `max_tokens`, `tool_use`).
+#[derive(Debug, Clone)]
+pub struct ChatResponse {
+    pub model: String,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<String>,
+    pub usage: Usage,
+}
+
+/// A backend able to run chat inference.
+/// Calls are blocking and should be run off the main thread.
+#[allow(dead_code)]
+pub trait LlmProvider: Send + Sync {
+    fn kind(&self) -> ProviderKind;
+
+    /// Run one inference step, including tool definitions
+    fn chat(&self, request: &ChatRequest) -> Result<ChatResponse>;
+
+    /// Run one inference step, reporting text as it becomes available.
+    /// Providers that can't stream report the text in one piece.
+    fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<ChatResponse> {
+        let response = self.chat(request)?;
+        for block in &response.content {
+            if let ContentBlock::Text { text, .. } = block {
+                on_text(text);
+            }
+        }
+        Ok(response)
+    }
+
+    /// Exact input tokens for a request, counted without running it.
+    /// `None` when the backend can't count, in which case callers estimate.
+    fn count_tokens(&self, _request: &ChatRequest) -> Result<Option<u32>> {
+        Ok(None)
+    }
+
+    /// Whether attachments can be referenced by Files API ID.
+    /// Otherwise they are sent inline with `inline_file`.
+    fn supports_file_uploads(&self) -> bool {
+        false
+    }
+}
+
+/// Read a file into a content block that carries its contents inline
+pub fn inline_file(path: &Path) -> Result<ContentBlock> {
+    let media_type = get_mime_type(path);
+    let bytes =
+        std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+    if media_type.starts_with("text/") || media_type == "application/json" {
+        let text = String::from_utf8(bytes)
+            .map_err(|_| anyhow!("{} is not valid UTF-8", path.display()))?;
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        return Ok(ContentBlock::text(format!(
+            "<file path=\"{}\">\n{}\n</file>",
+            name, text
+        )));
+    }
+
+    let source = FileSource::Base64 {
+        media_type: media_type.to_string(),
+        data: base64::engine::general_purpose::STANDARD.encode(bytes),
+    };
+    Ok(if is_image_file(path) {
+        ContentBlock::Image {
+            source,
+            cache_control: None,
+        }
+    } else {
+        ContentBlock::Document {
+            source,
+            cache_control: None,
+        }
+    })
+}
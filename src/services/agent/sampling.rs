@@ -0,0 +1,131 @@
+//! Sampling parameters sent with each request, and the rules they must follow.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use super::provider::ProviderKind;
+
+/// Most stop sequences OpenAI accepts; the other providers have no small limit
+const OPENAI_MAX_STOP_SEQUENCES: usize = 4;
+
+/// Lowest `top_p` allowed while extended thinking is on
+pub const MIN_THINKING_TOP_P: f32 = 0.95;
+
+/// Optional overrides of the model's sampling defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Not supported by OpenAI, which ignores it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+}
+
+/// Highest temperature the provider accepts. Without a provider, the
+/// highest any of them accepts.
+fn max_temperature(provider: Option<ProviderKind>) -> f32 {
+    match provider {
+        Some(ProviderKind::Anthropic) => 1.0,
+        _ => 2.0,
+    }
+}
+
+impl SamplingParams {
+    /// Check the values are in range for the provider and can be used together
+    /// with thinking. Limits of a particular provider are skipped without one.
+    pub fn validate(&self, thinking: bool, provider: Option<ProviderKind>) -> Result<()> {
+        if let Some(temperature) = self.temperature {
+            let max = max_temperature(provider);
+            if !(0.0..=max).contains(&temperature) {
+                return Err(anyhow!("Temperature must be between 0 and {}", max));
+            }
+            if thinking {
+                return Err(anyhow!(
+                    "Temperature can't be changed while extended thinking is on"
+                ));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                return Err(anyhow!("Top P must be between 0 and 1"));
+            }
+            if thinking && top_p < MIN_THINKING_TOP_P {
+                return Err(anyhow!(
+                    "Top P must be at least {} while extended thinking is on",
+                    MIN_THINKING_TOP_P
+                ));
+            }
+        }
+        if let Some(top_k) = self.top_k {
+            if top_k == 0 {
+                return Err(anyhow!("Top K must be at least 1"));
+            }
+            if thinking {
+                return Err(anyhow!(
+                    "Top K can't be changed while extended thinking is on"
+                ));
+            }
+        }
+        if provider == Some(ProviderKind::OpenAi)
+            && self.stop_sequences.len() > OPENAI_MAX_STOP_SEQUENCES
+        {
+            return Err(anyhow!(
+                "OpenAI allows at most {} stop sequences",
+                OPENAI_MAX_STOP_SEQUENCES
+            ));
+        }
+        if self.stop_sequences.iter().any(|s| s.trim().is_empty()) {
+            return Err(anyhow!("Stop sequences can't be blank"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_sampling() {
+        let params = SamplingParams {
+            temperature: Some(0.7),
+            top_k: Some(40),
+            ..Default::default()
+        };
+        let anthropic = Some(ProviderKind::Anthropic);
+        assert!(params.validate(false, anthropic).is_ok());
+        assert!(params.validate(true, anthropic).is_err());
+
+        let thinking = SamplingParams {
+            top_p: Some(0.95),
+            ..Default::default()
+        };
+        assert!(thinking.validate(true, anthropic).is_ok());
+        assert!(
+            SamplingParams {
+                top_p: Some(0.9),
+                ..Default::default()
+            }
+            .validate(true, anthropic)
+            .is_err()
+        );
+
+        let hot = SamplingParams {
+            temperature: Some(1.5),
+            ..Default::default()
+        };
+        assert!(hot.validate(false, anthropic).is_err());
+        assert!(hot.validate(false, Some(ProviderKind::OpenAi)).is_ok());
+
+        let stops = SamplingParams {
+            stop_sequences: (1..=5).map(|n| format!("END{}", n)).collect(),
+            ..Default::default()
+        };
+        assert!(stops.validate(false, anthropic).is_ok());
+        assert!(stops.validate(false, Some(ProviderKind::OpenAi)).is_err());
+    }
+}
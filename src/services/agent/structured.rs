@@ -0,0 +1,356 @@
+//! Structured output: replies that must match a JSON Schema.
+//!
+//! The model is made to call a single tool whose input schema is the
+//! requested one, so its reply arrives as the tool input. Replies are checked
+//! against the schema here, covering the keywords extraction schemas use
+//! (types, properties, required, items, enums and simple bounds). Schemas
+//! using any other keyword, apart from annotations such as `title`, are
+//! rejected rather than half checked.
+//! Schemas can be saved by name, as JSON files in the schemas directory.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use super::profiles::slug;
+use crate::services::paths::app_dir;
+
+/// Tool the model is made to call with its structured reply
+pub const STRUCTURED_TOOL_NAME: &str = "respond_with_json";
+
+/// Description of the structured reply tool
+pub const STRUCTURED_TOOL_DESCRIPTION: &str =
+    "Give your answer as JSON matching the input schema. Always answer through this tool.";
+
+/// Keywords `validate` checks
+const CHECKED_KEYWORDS: &[&str] = &[
+    "type",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "enum",
+    "minLength",
+    "maxLength",
+    "minItems",
+    "maxItems",
+    "minimum",
+    "maximum",
+];
+
+/// Keywords that only describe a schema, with nothing to check
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+];
+
+/// Values of `type` that `validate` knows
+const TYPE_NAMES: &[&str] = &[
+    "object", "array", "string", "boolean", "null", "number", "integer",
+];
+
+/// Parse a schema typed by the user. Tool input must be an object, so the
+/// schema must describe one.
+pub fn parse_schema(text: &str) -> Result<Value> {
+    let schema: Value =
+        serde_json::from_str(text).map_err(|e| anyhow!("Schema isn't valid JSON: {}", e))?;
+    match schema.get("type").and_then(Value::as_str) {
+        Some("object") => {
+            check_keywords(&schema, "$")?;
+            Ok(schema)
+        }
+        _ => Err(anyhow!("Schema must have \"type\": \"object\"")),
+    }
+}
+
+/// Fail on the first keyword or type `validate` doesn't support, so a reply
+/// is never accepted against rules that weren't checked
+fn check_keywords(schema: &Value, path: &str) -> Result<()> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+    if let Some(keyword) = schema.keys().find(|key| {
+        !CHECKED_KEYWORDS.contains(&key.as_str()) && !ANNOTATIONS.contains(&key.as_str())
+    }) {
+        return Err(anyhow!("\"{}\" at {} isn't supported", keyword, path));
+    }
+
+    let types = match schema.get("type") {
+        None => Vec::new(),
+        Some(Value::Array(types)) => types.iter().collect(),
+        Some(single) => vec![single],
+    };
+    if let Some(unknown) = types
+        .into_iter()
+        .find(|t| !t.as_str().is_some_and(|t| TYPE_NAMES.contains(&t)))
+    {
+        return Err(anyhow!("Type {} at {} isn't supported", unknown, path));
+    }
+    // A list of item schemas describes a tuple, which isn't checked
+    if schema.get("items").is_some_and(Value::is_array) {
+        return Err(anyhow!("\"items\" at {} must be a single schema", path));
+    }
+
+    for (name, property) in schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        check_keywords(property, &format!("{}.{}", path, name))?;
+    }
+    if let Some(items) = schema.get("items") {
+        check_keywords(items, &format!("{}[]", path))?;
+    }
+    if let Some(extra) = schema.get("additionalProperties") {
+        check_keywords(extra, &format!("{}.*", path))?;
+    }
+    Ok(())
+}
+
+/// Check a value against a schema, listing every mismatch
+pub fn validate(schema: &Value, value: &Value) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "$", &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn matches_type(expected: &str, value: &Value) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        // `parse_schema` rejects other types
+        _ => false,
+    }
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    // `true` and `{}` accept anything
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(expected)) => vec![expected.as_str()],
+        Some(Value::Array(expected)) => expected.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|t| matches_type(t, value)) {
+        errors.push(format!("{} should be {}", path, types.join(" or ")));
+        return;
+    }
+
+    if let Some(options) = schema
+        .get("enum")
+        .and_then(Value::as_array)
+        .filter(|options| !options.contains(value))
+    {
+        errors.push(format!(
+            "{} should be one of {}",
+            path,
+            Value::from(options.clone())
+        ));
+    }
+
+    let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+    let check_bounds = |len: f64, min: &str, max: &str, what: &str, errors: &mut Vec<String>| {
+        if let Some(min) = bound(min).filter(|min| len < *min) {
+            errors.push(format!("{} should have at least {} {}", path, min, what));
+        }
+        if let Some(max) = bound(max).filter(|max| len > *max) {
+            errors.push(format!("{} should have at most {} {}", path, max, what));
+        }
+    };
+
+    match value {
+        Value::Object(map) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for name in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !map.contains_key(name) {
+                    errors.push(format!("{}.{} is required", path, name));
+                }
+            }
+            for (name, item) in map {
+                let item_path = format!("{}.{}", path, name);
+                match properties.and_then(|p| p.get(name)) {
+                    Some(item_schema) => validate_at(item_schema, item, &item_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{} is not allowed", item_path))
+                        }
+                        Some(extra) => validate_at(extra, item, &item_path, errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (ix, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{}[{}]", path, ix), errors);
+                }
+            }
+            check_bounds(items.len() as f64, "minItems", "maxItems", "items", errors);
+        }
+        Value::String(text) => {
+            let len = text.chars().count() as f64;
+            check_bounds(len, "minLength", "maxLength", "characters", errors);
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = bound("minimum").filter(|min| number < *min) {
+                errors.push(format!("{} should be at least {}", path, min));
+            }
+            if let Some(max) = bound("maximum").filter(|max| number > *max) {
+                errors.push(format!("{} should be at most {}", path, max));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A named schema kept for reuse
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedSchema {
+    pub name: String,
+    pub schema: Value,
+}
+
+impl SavedSchema {
+    /// Write the schema to the schemas directory, replacing one with the same name
+    pub fn save(&self) -> Result<PathBuf> {
+        self.save_in(&schemas_dir())
+    }
+
+    fn save_in(&self, dir: &Path) -> Result<PathBuf> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Schema name can't be empty"));
+        }
+
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create schemas directory: {}", e))?;
+        let path = dir.join(format!("{}.json", slug(&self.name)));
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| anyhow!("Failed to serialize schema: {}", e))?;
+        std::fs::write(&path, json).map_err(|e| anyhow!("Failed to write schema: {}", e))?;
+        Ok(path)
+    }
+}
+
+fn schemas_dir() -> PathBuf {
+    app_dir().join("schemas")
+}
+
+/// All saved schemas, sorted by name. Unreadable files are skipped.
+pub fn load_schemas() -> Vec<SavedSchema> {
+    let Ok(entries) = std::fs::read_dir(schemas_dir()) else {
+        return Vec::new();
+    };
+
+    let mut schemas: Vec<SavedSchema> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let json = std::fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&json)
+                .inspect_err(|e| tracing::warn!("Invalid schema {}: {}", path.display(), e))
+                .ok()
+        })
+        .collect();
+    schemas.sort_by_key(|s| s.name.to_lowercase());
+    schemas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_invoice() {
+        let schema = parse_schema(
+            r#"{
+                "type": "object",
+                "properties": {
+                    "number": { "type": "string", "minLength": 1 },
+                    "total": { "type": "number", "minimum": 0 },
+                    "currency": { "enum": ["EUR", "USD"] },
+                    "lines": { "type": "array", "items": { "type": "object",
+                        "properties": { "qty": { "type": "integer" } } } }
+                },
+                "required": ["number", "total"],
+                "additionalProperties": false
+            }"#,
+        )
+        .unwrap();
+
+        let valid = json!({ "number": "INV-1", "total": 12.5, "lines": [{ "qty": 2 }] });
+        assert_eq!(validate(&schema, &valid), Ok(()));
+
+        let invalid = json!({ "total": -1, "currency": "GBP", "lines": [{ "qty": 1.5 }], "x": 1 });
+        assert_eq!(
+            validate(&schema, &invalid),
+            Err(vec![
+                "$.number is required".to_string(),
+                "$.total should be at least 0".to_string(),
+                "$.currency should be one of [\"EUR\",\"USD\"]".to_string(),
+                "$.lines[0].qty should be integer".to_string(),
+                "$.x is not allowed".to_string(),
+            ])
+        );
+
+        assert!(parse_schema(r#"{"type": "array"}"#).is_err());
+        assert!(parse_schema("{").is_err());
+        let error = parse_schema(
+            r#"{"type": "object", "properties": {
+                "tags": { "type": "array", "items": { "type": "string", "pattern": "^a" } }
+            }}"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "\"pattern\" at $.tags[] isn't supported");
+        assert!(parse_schema(r#"{"type": "object", "anyOf": []}"#).is_err());
+        assert!(parse_schema(r#"{"type": "object", "minProperties": 1}"#).is_err());
+        let error = parse_schema(
+            r#"{"type": "object", "properties": {
+                "n": { "type": "number", "exclusiveMinimum": 0 }
+            }}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "\"exclusiveMinimum\" at $.n isn't supported"
+        );
+        let error =
+            parse_schema(r#"{"type": "object", "properties": { "d": { "type": "date" } }}"#)
+                .unwrap_err();
+        assert_eq!(error.to_string(), "Type \"date\" at $.d isn't supported");
+        assert!(
+            parse_schema(r#"{"type": "object", "title": "Invoice", "description": "An invoice"}"#)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Tools the agent can run on the user's behalf.
+//!
+//! Each tool implements `ToolHandler` and is registered with a `ToolRegistry`,
+//! which offers the tools to the model and runs the calls it makes. Calls from
+//! one reply run concurrently, each with its own timeout.
+
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use super::messages::{ToolCallData, ToolResultData};
+use super::permissions::ToolPermission;
+use super::types::Tool;
+
+/// Time a tool call may take unless the tool sets its own limit
+pub const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Set once a call has timed out. Tools that loop or wait check it and stop
+/// early, since their result is no longer wanted.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Fail if the call was cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(anyhow!("Cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A tool the model can call. Calls are blocking and run off the main thread.
+pub trait ToolHandler: Send + Sync {
+    /// Name, description and input schema offered to the model
+    fn definition(&self) -> Tool;
+
+    /// Run the tool, returning the text sent back to the model. Long calls
+    /// should give up once `cancel` is set.
+    fn call(&self, input: &Value, cancel: &CancelToken) -> Result<String>;
+
+    /// How long a call may run before it's reported as failed
+    fn timeout(&self) -> Duration {
+        DEFAULT_TOOL_TIMEOUT
+    }
+
+    /// Permission used until the user picks one
+    fn default_permission(&self) -> ToolPermission {
+        ToolPermission::Ask
+    }
+}
+
+/// The tools available to the agent, looked up by name
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    handlers: Vec<Arc<dyn ToolHandler>>,
+}
+
+#[allow(dead_code)]
+impl ToolRegistry {
+    /// Add a tool, replacing any with the same name
+    pub fn register(&mut self, handler: Arc<dyn ToolHandler>) {
+        let name = handler.definition().name;
+        self.handlers.retain(|h| h.definition().name != name);
+        self.handlers.push(handler);
+    }
+
+    /// Definitions of every registered tool
+    pub fn definitions(&self) -> Vec<Tool> {
+        self.handlers.iter().map(|h| h.definition()).collect()
+    }
+
+    /// Permission a tool uses until the user picks one. Unknown tools are
+    /// allowed, since calling them only returns an error.
+    pub fn default_permission(&self, name: &str) -> ToolPermission {
+        self.get(name)
+            .map(|h| h.default_permission())
+            .unwrap_or(ToolPermission::Allow)
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<dyn ToolHandler>> {
+        self.handlers
+            .iter()
+            .find(|h| h.definition().name == name)
+            .cloned()
+    }
+
+    /// Run one call. Failures, unknown tools and timeouts become error results.
+    /// A call that times out is cancelled, and its output is dropped if it
+    /// still finishes.
+    pub async fn execute(&self, call: &ToolCallData) -> ToolResultData {
+        let result = match self.get(&call.name) {
+            Some(handler) => {
+                let timeout = handler.timeout();
+                let input = call.input.clone();
+                let cancel = CancelToken::default();
+                let task = smol::unblock({
+                    let cancel = cancel.clone();
+                    move || handler.call(&input, &cancel)
+                });
+                smol::future::or(async { Some(task.await) }, async {
+                    smol::Timer::after(timeout).await;
+                    None
+                })
+                .await
+                .unwrap_or_else(|| {
+                    cancel.cancel();
+                    Err(anyhow!("{} timed out after {:?}", call.name, timeout))
+                })
+            }
+            None => Err(anyhow!("Unknown tool: {}", call.name)),
+        };
+
+        if let Err(e) = &result {
+            tracing::warn!("Tool call {} failed: {}", call.name, e);
+        }
+        ToolResultData {
+            tool_use_id: call.id.clone(),
+            is_error: result.is_err(),
+            content: result.unwrap_or_else(|e| e.to_string()),
+        }
+    }
+
+    /// Run calls concurrently, returning results in the order of `calls`
+    pub async fn execute_all(&self, calls: &[ToolCallData]) -> Vec<ToolResultData> {
+        // Tasks start running as soon as they're spawned, so awaiting them in
+        // order doesn't serialize them
+        let tasks: Vec<_> = calls
+            .iter()
+            .map(|call| {
+                let registry = self.clone();
+                let call = call.clone();
+                smol::spawn(async move { registry.execute(&call).await })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            results.push(task.await);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::AtomicUsize;
+
+    fn tool(name: &str) -> Tool {
+        Tool {
+            name: name.to_string(),
+            description: name.to_string(),
+            input_schema: json!({ "type": "object" }),
+        }
+    }
+
+    /// Waits until two calls are running at once. Run one after another,
+    /// the first call never sees the second and times out.
+    struct Meet(Arc<AtomicUsize>);
+
+    impl ToolHandler for Meet {
+        fn definition(&self) -> Tool {
+            tool("meet")
+        }
+
+        fn call(&self, _: &Value, cancel: &CancelToken) -> Result<String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            while self.0.load(Ordering::SeqCst) < 2 {
+                cancel.check()?;
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            Ok("met".to_string())
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_secs(5)
+        }
+    }
+
+    /// Runs until cancelled, recording that it stopped
+    struct Hang(Arc<AtomicBool>);
+
+    impl ToolHandler for Hang {
+        fn definition(&self) -> Tool {
+            tool("hang")
+        }
+
+        fn call(&self, _: &Value, cancel: &CancelToken) -> Result<String> {
+            while !cancel.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            self.0.store(true, Ordering::SeqCst);
+            Err(anyhow!("Cancelled"))
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(100)
+        }
+    }
+
+    #[test]
+    fn test_execute_all_in_parallel_and_in_order() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut registry = ToolRegistry::default();
+        registry.register(Arc::new(Meet(Arc::default())));
+        registry.register(Arc::new(Hang(stopped.clone())));
+        let call = |id: &str, name: &str| ToolCallData {
+            id: id.to_string(),
+            name: name.to_string(),
+            input: json!({}),
+        };
+        let calls = [
+            call("a", "meet"),
+            call("b", "meet"),
+            call("c", "missing"),
+            call("d", "hang"),
+        ];
+
+        let results = smol::block_on(registry.execute_all(&calls));
+        let ids: Vec<&str> = results.iter().map(|r| r.tool_use_id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c", "d"]);
+        assert_eq!(results[0].content, "met");
+        assert!(!results[1].is_error);
+        assert_eq!(results[2].content, "Unknown tool: missing");
+        assert_eq!(results[3].content, "hang timed out after 100ms");
+        assert!(results[3].is_error);
+
+        // The timed out call was told to stop
+        for _ in 0..500 {
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+}
@@ -30,7 +30,11 @@ pub enum Message {
 #[serde(tag = "type")]
 pub enum ContentBlock {
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     #[serde(rename = "tool_use")]
     ToolUse {
         id: String,
@@ -43,9 +47,68 @@ pub enum ContentBlock {
         content: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         is_error: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
     },
     #[serde(rename = "document")]
-    Document { source: FileSource },
+    Document {
+        source: FileSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    #[serde(rename = "image")]
+    Image {
+        source: FileSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    /// Extended thinking; must be sent back unchanged, signature included
+    #[serde(rename = "thinking")]
+    Thinking { thinking: String, signature: String },
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
+}
+
+impl ContentBlock {
+    /// A plain text block
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentBlock::Text {
+            text: text.into(),
+            cache_control: None,
+        }
+    }
+
+    /// Mark the end of a cacheable prefix. Returns false for blocks that can't carry a breakpoint.
+    pub fn set_cache_control(&mut self, control: Option<CacheControl>) -> bool {
+        match self {
+            ContentBlock::Text { cache_control, .. }
+            | ContentBlock::ToolResult { cache_control, .. }
+            | ContentBlock::Document { cache_control, .. }
+            | ContentBlock::Image { cache_control, .. } => {
+                *cache_control = control;
+                true
+            }
+            ContentBlock::ToolUse { .. }
+            | ContentBlock::Thinking { .. }
+            | ContentBlock::RedactedThinking { .. } => false,
+        }
+    }
+}
+
+/// Prompt caching breakpoint; everything up to and including the marked block is cached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheControl {
+    #[serde(rename = "type")]
+    pub cache_type: String,
+}
+
+impl CacheControl {
+    /// The default five-minute cache
+    pub fn ephemeral() -> Self {
+        Self {
+            cache_type: "ephemeral".to_string(),
+        }
+    }
 }
 
 /// File source for referencing uploaded files
@@ -54,6 +117,9 @@ pub enum ContentBlock {
 pub enum FileSource {
     #[serde(rename = "file")]
     File { file_id: String },
+    /// File contents sent inline, for providers without a Files API
+    #[serde(rename = "base64")]
+    Base64 { media_type: String, data: String },
 }
 
 /// Tool definition for the LLM API
@@ -62,4 +128,74 @@ pub struct ToolDefinition {
     pub name: String,
     pub description: String,
     pub input_schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
+}
+
+/// How the model may use the tools it's offered, in the Messages API shape
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// Call tools or reply directly, as the model decides
+    Auto {
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        disable_parallel_tool_use: bool,
+    },
+    /// Call at least one tool
+    Any {
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        disable_parallel_tool_use: bool,
+    },
+    /// Call the named tool
+    Tool {
+        name: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        disable_parallel_tool_use: bool,
+    },
+    /// Don't call tools
+    None,
+}
+
+impl ToolChoice {
+    /// Force a call to the named tool
+    pub fn tool(name: impl Into<String>) -> Self {
+        ToolChoice::Tool {
+            name: name.into(),
+            disable_parallel_tool_use: false,
+        }
+    }
+
+    /// Whether the model must call a tool
+    pub fn forces_tool_use(&self) -> bool {
+        matches!(self, ToolChoice::Any { .. } | ToolChoice::Tool { .. })
+    }
+
+    /// Whether the model may call at most one tool per turn
+    pub fn disables_parallel_tool_use(&self) -> bool {
+        match self {
+            ToolChoice::Auto {
+                disable_parallel_tool_use,
+            }
+            | ToolChoice::Any {
+                disable_parallel_tool_use,
+            }
+            | ToolChoice::Tool {
+                disable_parallel_tool_use,
+                ..
+            } => *disable_parallel_tool_use,
+            ToolChoice::None => false,
+        }
+    }
+
+    /// The same choice without forcing a tool call, for the steps that follow
+    /// tool results. Otherwise the model could never finish its turn.
+    pub fn relaxed(&self) -> Self {
+        if self.forces_tool_use() {
+            ToolChoice::Auto {
+                disable_parallel_tool_use: self.disables_parallel_tool_use(),
+            }
+        } else {
+            self.clone()
+        }
+    }
 }
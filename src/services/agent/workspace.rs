@@ -0,0 +1,680 @@
+//! Built-in tools that read, search and, if allowed, edit files in a
+//! workspace folder chosen by the user.
+//!
+//! Every path the model gives is resolved against the workspace root and must
+//! still be inside it once symlinks are followed. Output is capped so one call
+//! can't fill the context window.
+
+use anyhow::{Result, anyhow};
+use ignore::{WalkBuilder, overrides::OverrideBuilder};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read as _},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::directory::{MAX_PACKED_FILE_BYTES, looks_binary};
+use super::permissions::ToolPermission;
+use super::tools::{CancelToken, ToolHandler};
+use super::types::Tool;
+
+/// Most text a single tool call returns
+pub const MAX_TOOL_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// Most paths listed by `list_directory` and `glob`
+const MAX_LISTED_PATHS: usize = 500;
+
+/// Most matching lines returned by `grep`
+const MAX_GREP_MATCHES: usize = 200;
+
+/// Matching lines longer than this are shortened
+const MAX_GREP_LINE_CHARS: usize = 300;
+
+/// A folder the workspace tools are confined to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workspace {
+    /// Canonical path, so resolved paths can be compared against it
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(root: &Path) -> Result<Self> {
+        let root = root
+            .canonicalize()
+            .map_err(|e| anyhow!("Can't open workspace {}: {}", root.display(), e))?;
+        if !root.is_dir() {
+            return Err(anyhow!("{} is not a directory", root.display()));
+        }
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolve a path given by the model, relative to the root. Paths that
+    /// leave the workspace, directly or through a symlink, are rejected.
+    /// The path doesn't have to exist, so files can be created.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let joined = self.root.join(path);
+
+        // Canonicalize the part that exists; the rest can only be plain names
+        let mut existing = joined.as_path();
+        let mut missing = Vec::new();
+        while existing.symlink_metadata().is_err() {
+            let name = existing
+                .file_name()
+                .ok_or_else(|| anyhow!("Invalid path: {}", path))?;
+            missing.push(name);
+            existing = existing
+                .parent()
+                .ok_or_else(|| anyhow!("Invalid path: {}", path))?;
+        }
+        let mut resolved = existing
+            .canonicalize()
+            .map_err(|e| anyhow!("Can't resolve {}: {}", path, e))?;
+        resolved.extend(missing.iter().rev());
+
+        if !resolved.starts_with(&self.root) {
+            return Err(anyhow!("{} is outside the workspace", path));
+        }
+        Ok(resolved)
+    }
+
+    /// A path as shown to the model, relative to the root
+    fn display(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if relative.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            relative.display().to_string()
+        }
+    }
+
+    /// Walk files under `dir`, respecting `.gitignore` and never following symlinks
+    fn walk_files(
+        &self,
+        dir: &Path,
+        glob: Option<&str>,
+        cancel: &CancelToken,
+    ) -> Result<Vec<PathBuf>> {
+        let mut overrides = OverrideBuilder::new(dir);
+        if let Some(glob) = glob {
+            overrides
+                .add(glob)
+                .map_err(|e| anyhow!("Invalid glob {}: {}", glob, e))?;
+        }
+        let overrides = overrides
+            .build()
+            .map_err(|e| anyhow!("Invalid glob: {}", e))?;
+
+        let files = WalkBuilder::new(dir)
+            .overrides(overrides)
+            .require_git(false)
+            .sort_by_file_path(|a, b| a.cmp(b))
+            .build()
+            .take_while(|_| !cancel.is_cancelled())
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+            .map(|entry| entry.into_path())
+            .collect();
+        cancel.check()?;
+        Ok(files)
+    }
+}
+
+/// Cut text down to `MAX_TOOL_OUTPUT_BYTES`, saying how much was left out
+fn cap_output(mut text: String) -> String {
+    if text.len() <= MAX_TOOL_OUTPUT_BYTES {
+        return text;
+    }
+    let total = text.len();
+    let mut end = MAX_TOOL_OUTPUT_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text.push_str(&format!(
+        "\n[Truncated: showing {} of {} bytes]",
+        end, total
+    ));
+    text
+}
+
+/// Parse a tool's input into its argument struct
+fn parse_input<T: serde::de::DeserializeOwned>(input: &Value) -> Result<T> {
+    serde_json::from_value(input.clone()).map_err(|e| anyhow!("Invalid input: {}", e))
+}
+
+/// Read a text file, rejecting binaries
+fn read_text(path: &Path, name: &str) -> Result<String> {
+    if looks_binary(path).map_err(|e| anyhow!("Can't read {}: {}", name, e))? {
+        return Err(anyhow!("{} is a binary file", name));
+    }
+    std::fs::read_to_string(path).map_err(|e| anyhow!("Can't read {}: {}", name, e))
+}
+
+/// Longest line `read_file` returns, leaving room in the output for the line
+/// number and the truncation and stop notes
+const MAX_READ_LINE_BYTES: usize = MAX_TOOL_OUTPUT_BYTES - 256;
+
+/// Appended to lines cut to `MAX_READ_LINE_BYTES`
+const LINE_TRUNCATED: &str = " [line truncated]";
+
+/// Read one line, keeping at most `max` bytes of it and skipping the rest.
+/// Longer lines end with `LINE_TRUNCATED`. `None` at the end of the file.
+fn read_capped_line(reader: &mut impl BufRead, max: usize) -> io::Result<Option<String>> {
+    let mut bytes = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(max as u64)
+        .read_until(b'\n', &mut bytes)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    let mut truncated = false;
+    if bytes.last() == Some(&b'\n') {
+        bytes.pop();
+    } else {
+        truncated = reader.skip_until(b'\n')? > 0;
+    }
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    let mut line = String::from_utf8_lossy(&bytes).into_owned();
+    if truncated {
+        line.push_str(LINE_TRUNCATED);
+    }
+    Ok(Some(line))
+}
+
+struct ReadFile(Workspace);
+
+#[derive(Deserialize)]
+struct ReadFileInput {
+    path: String,
+    /// First line to read, counting from 1
+    offset: Option<usize>,
+    /// Lines to read
+    limit: Option<usize>,
+}
+
+impl ToolHandler for ReadFile {
+    fn definition(&self) -> Tool {
+        Tool {
+            name: "read_file".to_string(),
+            description: "Read a text file in the workspace. Lines are numbered. Use offset \
+                and limit to read part of a large file."
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path relative to the workspace" },
+                    "offset": { "type": "integer", "minimum": 1, "description": "First line" },
+                    "limit": { "type": "integer", "minimum": 1, "description": "Lines to read" }
+                },
+                "required": ["path"]
+            }),
+        }
+    }
+
+    fn call(&self, input: &Value, _: &CancelToken) -> Result<String> {
+        let input: ReadFileInput = parse_input(input)?;
+        let path = self.0.resolve(&input.path)?;
+        let cant_read = |e: io::Error| anyhow!("Can't read {}: {}", input.path, e);
+        if looks_binary(&path).map_err(cant_read)? {
+            return Err(anyhow!("{} is a binary file", input.path));
+        }
+
+        // Lines are read one at a time, so a large file costs no more than
+        // the output sent back
+        let mut reader = BufReader::new(File::open(&path).map_err(cant_read)?);
+        let first = input.offset.unwrap_or(1).max(1);
+        let last = first.saturating_add(input.limit.unwrap_or(usize::MAX));
+        let mut numbered = String::new();
+        let mut number = 1;
+        while number < last {
+            let Some(line) =
+                read_capped_line(&mut reader, MAX_READ_LINE_BYTES).map_err(cant_read)?
+            else {
+                break;
+            };
+            if number >= first {
+                let line = format!("{:>6}\t{}\n", number, line);
+                // The first line always fits, so continuing from the offset
+                // given below makes progress
+                if !numbered.is_empty() && numbered.len() + line.len() > MAX_TOOL_OUTPUT_BYTES {
+                    numbered.push_str(&format!(
+                        "[Stopped before line {} to keep the output short; \
+                         continue with offset {}]",
+                        number, number
+                    ));
+                    return Ok(numbered);
+                }
+                numbered.push_str(&line);
+            }
+            number += 1;
+        }
+        numbered.pop();
+        Ok(numbered)
+    }
+
+    fn default_permission(&self) -> ToolPermission {
+        ToolPermission::Allow
+    }
+}
+
+struct ListDirectory(Workspace);
+
+#[derive(Deserialize)]
+struct ListDirectoryInput {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+impl ToolHandler for ListDirectory {
+    fn definition(&self) -> Tool {
+        Tool {
+            name: "list_directory".to_string(),
+            description: "List a directory in the workspace. Directories end with a slash."
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path relative to the workspace; defaults to its root"
+                    }
+                }
+            }),
+        }
+    }
+
+    fn call(&self, input: &Value, _: &CancelToken) -> Result<String> {
+        let input: ListDirectoryInput = parse_input(input)?;
+        let name = input.path.unwrap_or_else(|| ".".to_string());
+        let dir = self.0.resolve(&name)?;
+
+        let mut entries: Vec<String> = std::fs::read_dir(&dir)
+            .map_err(|e| anyhow!("Can't list {}: {}", name, e))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    name + "/"
+                } else {
+                    name
+                }
+            })
+            .collect();
+        entries.sort();
+
+        let total = entries.len();
+        entries.truncate(MAX_LISTED_PATHS);
+        if total > MAX_LISTED_PATHS {
+            entries.push(format!("[{} more entries]", total - MAX_LISTED_PATHS));
+        }
+        if entries.is_empty() {
+            return Ok(format!("{} is empty", name));
+        }
+        Ok(cap_output(entries.join("\n")))
+    }
+
+    fn default_permission(&self) -> ToolPermission {
+        ToolPermission::Allow
+    }
+}
+
+struct Glob(Workspace);
+
+#[derive(Deserialize)]
+struct GlobInput {
+    pattern: String,
+}
+
+impl ToolHandler for Glob {
+    fn definition(&self) -> Tool {
+        Tool {
+            name: "glob".to_string(),
+            description: "Find files in the workspace whose paths match a glob such as \
+                `src/**/*.rs`. Files ignored by .gitignore are skipped."
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": { "pattern": { "type": "string" } },
+                "required": ["pattern"]
+            }),
+        }
+    }
+
+    fn call(&self, input: &Value, cancel: &CancelToken) -> Result<String> {
+        let input: GlobInput = parse_input(input)?;
+        let files = self
+            .0
+            .walk_files(self.0.root(), Some(&input.pattern), cancel)?;
+        if files.is_empty() {
+            return Ok(format!("No files match {}", input.pattern));
+        }
+
+        let mut paths: Vec<String> = files
+            .iter()
+            .take(MAX_LISTED_PATHS)
+            .map(|path| self.0.display(path))
+            .collect();
+        if files.len() > MAX_LISTED_PATHS {
+            paths.push(format!("[{} more files]", files.len() - MAX_LISTED_PATHS));
+        }
+        Ok(cap_output(paths.join("\n")))
+    }
+
+    fn default_permission(&self) -> ToolPermission {
+        ToolPermission::Allow
+    }
+}
+
+struct Grep(Workspace);
+
+#[derive(Deserialize)]
+struct GrepInput {
+    pattern: String,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    glob: Option<String>,
+}
+
+impl ToolHandler for Grep {
+    fn definition(&self) -> Tool {
+        Tool {
+            name: "grep".to_string(),
+            description: "Search text files in the workspace for a regular expression. \
+                Returns matching lines as path:line: text."
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "pattern": { "type": "string", "description": "Regular expression" },
+                    "path": {
+                        "type": "string",
+                        "description": "File or directory to search; defaults to the workspace"
+                    },
+                    "glob": { "type": "string", "description": "Only search matching files" }
+                },
+                "required": ["pattern"]
+            }),
+        }
+    }
+
+    fn call(&self, input: &Value, cancel: &CancelToken) -> Result<String> {
+        let input: GrepInput = parse_input(input)?;
+        let regex = Regex::new(&input.pattern)
+            .map_err(|e| anyhow!("Invalid pattern {}: {}", input.pattern, e))?;
+        let target = self.0.resolve(input.path.as_deref().unwrap_or("."))?;
+        let files = if target.is_file() {
+            vec![target]
+        } else {
+            self.0.walk_files(&target, input.glob.as_deref(), cancel)?
+        };
+
+        let mut matches = Vec::new();
+        'files: for path in files {
+            cancel.check()?;
+            let too_large = path
+                .metadata()
+                .is_ok_and(|m| m.len() > MAX_PACKED_FILE_BYTES);
+            if too_large || looks_binary(&path).unwrap_or(true) {
+                continue;
+            }
+            let Ok(text) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            for (ix, line) in text.lines().enumerate() {
+                if !regex.is_match(line) {
+                    continue;
+                }
+                if matches.len() == MAX_GREP_MATCHES {
+                    matches.push(format!("[Stopped after {} matches]", MAX_GREP_MATCHES));
+                    break 'files;
+                }
+                let line: String = line.chars().take(MAX_GREP_LINE_CHARS).collect();
+                matches.push(format!("{}:{}: {}", self.0.display(&path), ix + 1, line));
+            }
+        }
+
+        if matches.is_empty() {
+            return Ok(format!("No matches for {}", input.pattern));
+        }
+        Ok(cap_output(matches.join("\n")))
+    }
+
+    fn default_permission(&self) -> ToolPermission {
+        ToolPermission::Allow
+    }
+}
+
+struct WriteFile(Workspace);
+
+#[derive(Deserialize)]
+struct WriteFileInput {
+    path: String,
+    content: String,
+}
+
+impl ToolHandler for WriteFile {
+    fn definition(&self) -> Tool {
+        Tool {
+            name: "write_file".to_string(),
+            description: "Create or overwrite a file in the workspace with the given content. \
+                Missing parent directories are created."
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path relative to the workspace" },
+                    "content": { "type": "string" }
+                },
+                "required": ["path", "content"]
+            }),
+        }
+    }
+
+    fn call(&self, input: &Value, _: &CancelToken) -> Result<String> {
+        let input: WriteFileInput = parse_input(input)?;
+        let path = self.0.resolve(&input.path)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Can't create directories for {}: {}", input.path, e))?;
+        }
+        std::fs::write(&path, &input.content)
+            .map_err(|e| anyhow!("Can't write {}: {}", input.path, e))?;
+        Ok(format!(
+            "Wrote {} bytes to {}",
+            input.content.len(),
+            input.path
+        ))
+    }
+}
+
+struct ApplyPatch(Workspace);
+
+#[derive(Deserialize)]
+struct ApplyPatchInput {
+    path: String,
+    edits: Vec<Edit>,
+}
+
+#[derive(Deserialize)]
+struct Edit {
+    old_text: String,
+    new_text: String,
+}
+
+/// Apply edits in order. Each must match exactly one place in the text as it
+/// stands after the edits before it.
+fn apply_edits(mut text: String, edits: &[Edit]) -> Result<String> {
+    for (ix, edit) in edits.iter().enumerate() {
+        if edit.old_text.is_empty() {
+            return Err(anyhow!("Edit {} has empty old_text", ix + 1));
+        }
+        match text.matches(&edit.old_text).count() {
+            0 => return Err(anyhow!("Edit {}: old_text wasn't found", ix + 1)),
+            1 => text = text.replacen(&edit.old_text, &edit.new_text, 1),
+            n => {
+                return Err(anyhow!(
+                    "Edit {}: old_text matches {} places; include more context",
+                    ix + 1,
+                    n
+                ));
+            }
+        }
+    }
+    Ok(text)
+}
+
+impl ToolHandler for ApplyPatch {
+    fn definition(&self) -> Tool {
+        Tool {
+            name: "apply_patch".to_string(),
+            description: "Edit a file in the workspace by replacing exact text. Each edit's \
+                old_text must appear exactly once, so include enough surrounding lines. \
+                Edits apply in order, and none are written unless all of them match."
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path relative to the workspace" },
+                    "edits": {
+                        "type": "array",
+                        "minItems": 1,
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "old_text": { "type": "string" },
+                                "new_text": { "type": "string" }
+                            },
+                            "required": ["old_text", "new_text"]
+                        }
+                    }
+                },
+                "required": ["path", "edits"]
+            }),
+        }
+    }
+
+    fn call(&self, input: &Value, _: &CancelToken) -> Result<String> {
+        let input: ApplyPatchInput = parse_input(input)?;
+        let path = self.0.resolve(&input.path)?;
+        let text = read_text(&path, &input.path)?;
+        let patched = apply_edits(text, &input.edits)?;
+        std::fs::write(&path, patched).map_err(|e| anyhow!("Can't write {}: {}", input.path, e))?;
+        Ok(format!(
+            "Applied {} edits to {}",
+            input.edits.len(),
+            input.path
+        ))
+    }
+}
+
+/// The workspace tools to register. Tools that change files are only
+/// included when `allow_writes` is set.
+pub fn workspace_tools(workspace: &Workspace, allow_writes: bool) -> Vec<Arc<dyn ToolHandler>> {
+    let mut tools: Vec<Arc<dyn ToolHandler>> = vec![
+        Arc::new(ReadFile(workspace.clone())),
+        Arc::new(ListDirectory(workspace.clone())),
+        Arc::new(Glob(workspace.clone())),
+        Arc::new(Grep(workspace.clone())),
+    ];
+    if allow_writes {
+        tools.push(Arc::new(WriteFile(workspace.clone())));
+        tools.push(Arc::new(ApplyPatch(workspace.clone())));
+    }
+    tools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_confines_paths() {
+        let base = std::env::temp_dir().join(format!("chatai-workspace-{}", std::process::id()));
+        let root = base.join("root");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}\n// TODO: more\n").unwrap();
+        std::fs::write(base.join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(base.join("secret.txt"), root.join("link.txt")).unwrap();
+
+        let workspace = Workspace::new(&root).unwrap();
+        assert!(workspace.resolve("src/main.rs").is_ok());
+        assert!(workspace.resolve("src/new/file.rs").is_ok());
+        assert!(workspace.resolve("../secret.txt").is_err());
+        assert!(workspace.resolve("src/../../secret.txt").is_err());
+        assert!(workspace.resolve("missing/../../secret.txt").is_err());
+        assert!(
+            workspace
+                .resolve(&base.join("secret.txt").display().to_string())
+                .is_err()
+        );
+        #[cfg(unix)]
+        assert!(workspace.resolve("link.txt").is_err());
+
+        let cancel = CancelToken::default();
+        let read = ReadFile(workspace.clone());
+        assert_eq!(
+            read.call(&json!({ "path": "src/main.rs", "offset": 2 }), &cancel)
+                .unwrap(),
+            "     2\t// TODO: more"
+        );
+        std::fs::write(root.join("long.txt"), "line\n".repeat(50_000)).unwrap();
+        let long = read.call(&json!({ "path": "long.txt" }), &cancel).unwrap();
+        assert!(long.len() <= MAX_TOOL_OUTPUT_BYTES + 100);
+        assert!(long.ends_with("continue with offset 5462]"));
+
+        // A line longer than the output budget is cut rather than never read
+        let wide = format!("{}\nnext\n", "x".repeat(MAX_TOOL_OUTPUT_BYTES + 10));
+        std::fs::write(root.join("wide.txt"), wide).unwrap();
+        let wide = read.call(&json!({ "path": "wide.txt" }), &cancel).unwrap();
+        assert!(wide.len() <= MAX_TOOL_OUTPUT_BYTES + 100);
+        assert!(wide.starts_with("     1\txxx"));
+        assert!(wide.ends_with("x [line truncated]\n     2\tnext"));
+
+        let grep = Grep(workspace.clone());
+        assert_eq!(
+            grep.call(&json!({ "pattern": "TODO" }), &cancel).unwrap(),
+            "src/main.rs:2: // TODO: more"
+        );
+
+        let patch = ApplyPatch(workspace);
+        patch
+            .call(
+                &json!({
+                    "path": "src/main.rs",
+                    "edits": [{ "old_text": "fn main() {}", "new_text": "fn main() { run() }" }]
+                }),
+                &cancel,
+            )
+            .unwrap();
+        assert!(
+            patch
+                .call(
+                    &json!({
+                        "path": "src/main.rs",
+                        "edits": [{ "old_text": "missing", "new_text": "" }]
+                    }),
+                    &cancel
+                )
+                .is_err()
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("src/main.rs")).unwrap(),
+            "fn main() { run() }\n// TODO: more\n"
+        );
+
+        let _ = std::fs::remove_dir_all(base);
+    }
+}
@@ -0,0 +1,10 @@
+//! Filesystem locations for state persisted between runs.
+
+use std::path::PathBuf;
+
+/// Root directory for ChatAI's caches and settings
+pub fn app_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("chatai")
+}
@@ -0,0 +1,238 @@
+use crate::services::agent::{AgentRequest, UploadInfo};
+use async_channel::Sender;
+use gpui::{
+    Context, Div, IntoElement, ParentElement as _, Render, SharedString, Styled as _, Window, div,
+    prelude::FluentBuilder as _,
+};
+use gpui_component::{
+    ActiveTheme as _, Disableable as _, Icon, Sizable as _, StyledExt as _, WindowExt as _,
+    button::*, dialog::DialogButtonProps, h_flex, label::Label, v_flex,
+};
+
+/// Format a byte count for display
+pub fn format_size(bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    const MB: f64 = KB * 1024.0;
+
+    let bytes_f = bytes as f64;
+    if bytes_f >= MB {
+        format!("{:.1} MB", bytes_f / MB)
+    } else if bytes_f >= KB {
+        format!("{:.1} KB", bytes_f / KB)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Panel listing files stored with the Files API
+pub struct UploadsPanel {
+    request_tx: Sender<AgentRequest>,
+    uploads: Vec<UploadInfo>,
+    is_loading: bool,
+}
+
+impl UploadsPanel {
+    pub fn new(request_tx: Sender<AgentRequest>) -> Self {
+        Self {
+            request_tx,
+            uploads: vec![],
+            is_loading: false,
+        }
+    }
+
+    /// Ask the agent for the current list of uploads
+    pub fn refresh(&mut self, cx: &mut Context<Self>) {
+        match self.request_tx.try_send(AgentRequest::ListUploads) {
+            Ok(_) => self.set_loading(true, cx),
+            Err(e) => tracing::error!("Failed to list uploads: {}", e),
+        }
+    }
+
+    pub fn set_uploads(&mut self, uploads: Vec<UploadInfo>, cx: &mut Context<Self>) {
+        self.uploads = uploads;
+        self.set_loading(false, cx);
+    }
+
+    pub fn set_loading(&mut self, loading: bool, cx: &mut Context<Self>) {
+        self.is_loading = loading;
+        cx.notify();
+    }
+
+    fn delete(&mut self, file_ids: Vec<String>, cx: &mut Context<Self>) {
+        if file_ids.is_empty() {
+            return;
+        }
+        match self
+            .request_tx
+            .try_send(AgentRequest::DeleteUploads(file_ids))
+        {
+            Ok(_) => self.set_loading(true, cx),
+            Err(e) => tracing::error!("Failed to delete uploads: {}", e),
+        }
+    }
+
+    /// Uploads from this app that no known conversation references
+    fn orphans(&self) -> Vec<UploadInfo> {
+        self.uploads
+            .iter()
+            .filter(|upload| upload.is_orphan())
+            .cloned()
+            .collect()
+    }
+
+    /// List the files about to be deleted and delete them once confirmed
+    fn confirm_delete(
+        &mut self,
+        uploads: Vec<UploadInfo>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        if uploads.is_empty() {
+            return;
+        }
+        let panel = cx.entity().downgrade();
+        window.open_dialog(cx, move |dialog, _window, cx| {
+            let panel = panel.clone();
+            let file_ids: Vec<String> = uploads.iter().map(|u| u.file.id.clone()).collect();
+            dialog
+                .title(format!("Delete {} files?", uploads.len()))
+                .child(
+                    v_flex()
+                        .gap_1()
+                        .text_sm()
+                        .child(Label::new(
+                            "These files will be deleted from the Files API for good:",
+                        ))
+                        .children(uploads.iter().map(|upload| {
+                            Label::new(format!(
+                                "{} ({})",
+                                upload.file.filename,
+                                format_size(upload.file.size_bytes)
+                            ))
+                            .text_color(cx.theme().muted_foreground)
+                        })),
+                )
+                .confirm()
+                .button_props(
+                    DialogButtonProps::default()
+                        .ok_text("Delete")
+                        .ok_variant(ButtonVariant::Danger),
+                )
+                .on_ok(move |_, _window, cx| {
+                    let _ = panel.update(cx, |panel, cx| panel.delete(file_ids.clone(), cx));
+                    true
+                })
+        });
+    }
+
+    fn render_upload(&self, ix: usize, upload: &UploadInfo, cx: &mut Context<Self>) -> Div {
+        let pending = upload.clone();
+        let details = format!(
+            "{} · {}",
+            format_size(upload.file.size_bytes),
+            upload.file.created_at.format("%b %-d, %Y")
+        );
+        let usage: SharedString = if !upload.owned {
+            "Not uploaded by this app".into()
+        } else if upload.is_orphan() {
+            "Not used in any conversation".into()
+        } else {
+            format!("Used in: {}", upload.conversations.join(", ")).into()
+        };
+
+        div()
+            .p_2()
+            .border_1()
+            .border_color(cx.theme().border)
+            .rounded_lg()
+            .child(
+                h_flex()
+                    .justify_between()
+                    .gap_2()
+                    .child(
+                        h_flex()
+                            .gap_2()
+                            .child(Icon::empty().path("icons/file.svg"))
+                            .child(Label::new(upload.file.filename.clone()).font_semibold()),
+                    )
+                    // Files from other tools or users are listed but never deleted here
+                    .when(upload.owned, |d| {
+                        d.child(
+                            Button::new(("delete-upload", ix))
+                                .icon(Icon::empty().path("icons/trash-2.svg"))
+                                .tooltip("Delete")
+                                .xsmall()
+                                .ghost()
+                                .disabled(self.is_loading)
+                                .on_click(cx.listener(move |this, _, window, cx| {
+                                    this.confirm_delete(vec![pending.clone()], window, cx);
+                                })),
+                        )
+                    }),
+            )
+            .child(
+                Label::new(details)
+                    .text_sm()
+                    .text_color(cx.theme().muted_foreground),
+            )
+            .child(
+                Label::new(usage)
+                    .text_sm()
+                    .text_color(cx.theme().muted_foreground),
+            )
+    }
+}
+
+impl Render for UploadsPanel {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let orphans = self.orphans();
+
+        let toolbar = h_flex()
+            .justify_between()
+            .gap_2()
+            .child(Label::new(format!("{} files", self.uploads.len())).text_sm())
+            .child(
+                h_flex()
+                    .gap_1()
+                    .child(
+                        Button::new("refresh-uploads")
+                            .label("Refresh")
+                            .small()
+                            .ghost()
+                            .loading(self.is_loading)
+                            .on_click(cx.listener(|this, _, _, cx| this.refresh(cx))),
+                    )
+                    .child(
+                        Button::new("delete-orphans")
+                            .label(format!("Delete {} unused", orphans.len()))
+                            .small()
+                            .danger()
+                            .disabled(orphans.is_empty() || self.is_loading)
+                            .on_click(cx.listener(move |this, _, window, cx| {
+                                this.confirm_delete(orphans.clone(), window, cx);
+                            })),
+                    ),
+            );
+
+        let uploads: Vec<Div> = self
+            .uploads
+            .iter()
+            .enumerate()
+            .map(|(ix, upload)| self.render_upload(ix, upload, cx))
+            .collect();
+
+        v_flex().gap_2().child(toolbar).children(uploads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2048), "2.0 KB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MB");
+    }
+}